@@ -20,6 +20,11 @@ struct Args {
 
     #[clap(long, default_value = "10")]
     recv_timeout: u64,
+
+    /// directory to persist the backends' storage in, one subdirectory per
+    /// backend. Without it, storage is kept in memory only.
+    #[clap(long)]
+    data_dir: Option<String>,
 }
 
 #[tokio::main]
@@ -32,6 +37,7 @@ async fn main() -> TribResult<()> {
         args.cfg,
         args.ready_addrs,
         args.recv_timeout,
+        args.data_dir,
     )
     .await
 }
@@ -28,8 +28,9 @@ async fn main() -> TribResult<()> {
     let args = Options::parse();
     env_logger::builder().filter_level(args.log).init();
     let cfg = Config::read(Some(&args.config))?;
+    let replicas = cfg.replica_count();
     let addrs = cfg.backs;
-    let bc = scalable::new_bin_client(addrs).await?;
+    let bc = scalable::new_replicated_bin_client(addrs, replicas).await?;
     let app = Command::new("bin-client")
         .subcommands(app_commands())
         .subcommands(bin_cmd());
@@ -32,6 +32,7 @@ async fn main() -> TribResult<()> {
         args.config,
         args.ready_addrs,
         args.recv_timeout,
+        None,
     )
     .await
 }
@@ -22,6 +22,9 @@ struct Options {
     /// number of keepers
     #[clap(short, long, default_value = "1")]
     keeps: usize,
+    /// number of backends each bin is replicated on
+    #[clap(short, long, default_value = "3")]
+    replicas: usize,
     /// location to write the config file. Use `-` for stdout
     #[clap(long, default_value = DEFAULT_CONFIG_LOCATION)]
     file: String,
@@ -41,6 +44,10 @@ fn main() -> TribResult<()> {
         eprintln!("too many keepers: {}. Must be <= 10", args.keeps);
         process::exit(1)
     }
+    if args.replicas == 0 {
+        eprintln!("replicas must be at least 1");
+        process::exit(1)
+    }
 
     let mut p = 3000;
     if !args.fix {
@@ -59,7 +66,11 @@ fn main() -> TribResult<()> {
         p += 1;
     }
 
-    let cfg = config::Config { backs, keepers };
+    let cfg = config::Config {
+        backs,
+        keepers,
+        replicas: args.replicas,
+    };
 
     cfg.write(Some(&args.file))
 }
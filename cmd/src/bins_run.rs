@@ -10,7 +10,13 @@ use std::{
 use scalable::{self, kvstore};
 use log::{error, info, warn, LevelFilter};
 use tokio::join;
-use tribbler::{addr, config::Config, err::TribResult, storage::MemStorage};
+use tribbler::{
+    addr,
+    config::Config,
+    disk::DiskStorage,
+    err::TribResult,
+    storage::{MemStorage, Storage},
+};
 
 #[derive(Debug, Clone)]
 pub enum ProcessType {
@@ -24,6 +30,7 @@ pub async fn main(
     cfg: String,
     _ready_addrs: Vec<String>,
     recv_timeout: u64,
+    data_dir: Option<String>,
 ) -> TribResult<()> {
     env_logger::builder()
         .default_format()
@@ -46,6 +53,7 @@ pub async fn main(
                 i,
                 config.clone(),
                 Some(tx.clone()),
+                data_dir.clone(),
             )));
         }
     }
@@ -83,10 +91,32 @@ pub async fn main(
 }
 
 #[allow(unused_must_use)]
-async fn run_srv(t: ProcessType, idx: usize, config: Arc<Config>, tx: Option<Sender<bool>>) {
+async fn run_srv(
+    t: ProcessType,
+    idx: usize,
+    config: Arc<Config>,
+    tx: Option<Sender<bool>>,
+    data_dir: Option<String>,
+) {
     match t {
         ProcessType::Back => {
-            let cfg = config.back_config(idx, Box::new(MemStorage::default()), tx, None);
+            let storage: Box<dyn Storage> = match data_dir {
+                Some(dir) => {
+                    let dir = std::path::Path::new(&dir).join(format!("back{}", idx));
+                    match DiskStorage::open(&dir).await {
+                        Ok(storage) => Box::new(storage),
+                        Err(e) => {
+                            error!("failed to open storage in {}: {}", dir.display(), e);
+                            if let Some(tx) = tx {
+                                tx.send(false);
+                            }
+                            return;
+                        }
+                    }
+                }
+                None => Box::new(MemStorage::default()),
+            };
+            let cfg = config.back_config(idx, storage, tx, None);
             info!("starting backend on {}", cfg.addr);
             kvstore::serve_back(cfg).await;
         }
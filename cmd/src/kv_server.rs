@@ -3,7 +3,7 @@
 use clap::Parser;
 use scalable::kvstore::serve_back;
 use log::{info, LevelFilter};
-use tribbler::{config::BackConfig, err::TribResult};
+use tribbler::{config::BackConfig, disk::DiskStorage, err::TribResult, storage::Storage};
 
 #[derive(Parser, Debug)]
 #[clap(name = "kv-server")]
@@ -13,6 +13,11 @@ struct Options {
 
     #[clap(short, long, default_value = "INFO")]
     log_level: LevelFilter,
+
+    /// directory to persist the storage in. Without it, everything is kept
+    /// in memory only and lost on exit.
+    #[clap(long)]
+    data_dir: Option<String>,
 }
 
 #[tokio::main]
@@ -22,11 +27,17 @@ async fn main() -> TribResult<()> {
         .default_format()
         .filter_level(options.log_level)
         .init();
-    let memstorage = Box::new(tribbler::storage::MemStorage::new());
+    let storage: Box<dyn Storage> = match &options.data_dir {
+        Some(dir) => {
+            info!("persisting storage in {}", dir);
+            Box::new(DiskStorage::open(dir).await?)
+        }
+        None => Box::new(tribbler::storage::MemStorage::new()),
+    };
     let addr = options.address.clone();
     let config = BackConfig {
         addr: options.address,
-        storage: memstorage,
+        storage,
         ready: None,
         shutdown: None,
     };
@@ -10,6 +10,7 @@ use tribbler::config::DEFAULT_CONFIG_LOCATION;
 use tribbler::err::{TribResult, TribblerError};
 use tribbler::ref_impl::RefServer;
 use tribbler::trib::Server;
+use tribbler::validate::UsernamePolicy;
 
 type Srv = Box<dyn Server + Send + Sync>;
 
@@ -56,6 +57,18 @@ struct Cfg {
     /// the host port to bind
     #[clap(long, default_value = "8080")]
     port: u16,
+
+    /// build home timelines on write instead of on read (scalable only)
+    #[clap(long)]
+    fanout: bool,
+
+    /// accept lowercase usernames in any script, not only ASCII
+    #[clap(long)]
+    unicode_usernames: bool,
+
+    /// refuse usernames that mix scripts or look like a taken one
+    #[clap(long)]
+    reject_confusables: bool,
 }
 
 #[tokio::main]
@@ -66,12 +79,17 @@ async fn main() -> TribResult<()> {
         .default_format()
         .filter_level(args.log_level)
         .init();
+    let policy = UsernamePolicy {
+        unicode: args.unicode_usernames,
+        reject_confusables: args.reject_confusables,
+    };
     let srv_impl: Srv = match args.server_type {
-        ServerType::Ref => Box::new(RefServer::new()),
+        ServerType::Ref => Box::new(RefServer::with_policy(policy)),
         ServerType::Scalable => {
             let cfg = Config::read(Some(&args.config))?;
-            let bc = scalable::new_bin_client(cfg.backs).await?;
-            scalable::new_front(bc).await?
+            let replicas = cfg.replica_count();
+            let bc = scalable::new_replicated_bin_client(cfg.backs, replicas).await?;
+            scalable::new_front_with_policy(bc, args.fanout, policy).await?
         }
     };
     let server: web::Data<Srv> = web::Data::new(srv_impl);
@@ -82,17 +100,39 @@ async fn main() -> TribResult<()> {
     let srv = HttpServer::new(move || {
         App::new()
             .app_data(server.clone())
+            .service(web::scope("/api/v2").configure(v2::configure))
             .service(
                 web::scope("/api")
-                    .service(api::add_user)
+                    .service(api::sign_up)
+                    .service(api::login)
+                    .service(api::logout)
                     .service(api::list_users)
                     .service(api::list_tribs)
                     .service(api::list_home)
+                    .service(api::list_tribs_before)
+                    .service(api::list_home_before)
                     .service(api::is_following)
                     .service(api::follow)
                     .service(api::unfollow)
                     .service(api::following)
-                    .service(api::post),
+                    .service(api::followers)
+                    .service(api::user_stats)
+                    .service(api::profile)
+                    .service(api::update_profile)
+                    .service(api::delete_user)
+                    .service(api::export_user)
+                    .service(api::post)
+                    .service(api::reply)
+                    .service(api::retrib)
+                    .service(api::like)
+                    .service(api::unlike)
+                    .service(api::list_mentions)
+                    .service(api::thread)
+                    .service(api::tag_timeline)
+                    .service(api::search)
+                    .service(api::delete_trib)
+                    .service(api::edit_trib)
+                    .service(api::stream_home),
             )
             .service(Files::new("/", "./www").index_file("index.html"))
     })
@@ -108,10 +148,13 @@ async fn main() -> TribResult<()> {
     Ok(())
 }
 
+/// password of the users the test server is pre-populated with
+static DEMO_PASSWORD: &str = "tribbler";
+
 async fn populate(server: &web::Data<Box<dyn Server + Send + Sync>>) -> TribResult<()> {
-    server.sign_up("h8liu").await?;
-    server.sign_up("fenglu").await?;
-    server.sign_up("rkapoor").await?;
+    server.sign_up_with_password("h8liu", DEMO_PASSWORD).await?;
+    server.sign_up_with_password("fenglu", DEMO_PASSWORD).await?;
+    server.sign_up_with_password("rkapoor", DEMO_PASSWORD).await?;
     server.post("h8liu", "Hello, world.", 0).await?;
     server.post("h8liu", "Just tribble it.", 0).await?;
     server.post("fenglu", "Double tribble.", 0).await?;
@@ -127,11 +170,29 @@ mod api {
     use std::error::Error;
     use std::{collections::HashMap, sync::Arc};
 
-    use actix_web::{get, http::header::ContentType, post, web, HttpResponse, Responder};
-    use log::debug;
+    use actix_web::{
+        cookie::{time::Duration, Cookie, SameSite},
+        get,
+        http::header::{ContentType, AUTHORIZATION},
+        post,
+        web::{self, Bytes},
+        HttpRequest, HttpResponse, Responder,
+    };
+    use futures_util::stream;
+    use tribbler::{
+        auth::SESSION_TTL,
+        err::{TribResult, TribblerError},
+    };
 
     use crate::Srv;
 
+    /// name of the cookie [login] stores the session token in
+    static SESSION_COOKIE: &str = "session";
+
+    /// how long a live timeline stream may stay idle before a comment is
+    /// sent on it, so that proxies keep it open and closed clients are noticed
+    const KEEP_ALIVE: std::time::Duration = std::time::Duration::from_secs(15);
+
     fn build_resp<T: Serialize>(d: &T) -> HttpResponse {
         HttpResponse::Ok()
             .content_type(ContentType::plaintext())
@@ -142,23 +203,188 @@ mod api {
         HttpResponse::InternalServerError().body(err.to_string())
     }
 
-    /// signs up a new user
-    #[post("/add-user")]
-    pub async fn add_user(
+    /// responds to a form without the key holding the JSON or the plain
+    /// argument the front-end sends
+    fn empty_form() -> HttpResponse {
+        HttpResponse::BadRequest().body("empty form")
+    }
+
+    /// returns the session token of a request, taken from an
+    /// `Authorization: Bearer` header or else from the session cookie
+    pub fn session_token(req: &HttpRequest) -> Option<String> {
+        if let Some(v) = req.headers().get(AUTHORIZATION) {
+            if let Some(token) = v.to_str().ok().and_then(|v| v.strip_prefix("Bearer ")) {
+                return Some(token.trim().to_string());
+            }
+        }
+        req.cookie(SESSION_COOKIE).map(|c| c.value().to_string())
+    }
+
+    /// returns the cookie [login] stores a session token in
+    pub fn session_cookie(token: &str) -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, token.to_string())
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .max_age(Duration::seconds(SESSION_TTL as i64))
+            .finish()
+    }
+
+    /// returns a cookie that clears the one [login] stored
+    pub fn removal_cookie() -> Cookie<'static> {
+        let mut cookie = Cookie::named(SESSION_COOKIE);
+        cookie.set_path("/");
+        cookie.make_removal();
+        cookie
+    }
+
+    /// checks that a request holds a session of `who` before it acts on
+    /// their behalf
+    pub async fn signed_in_as(
+        data: &web::Data<Srv>,
+        req: &HttpRequest,
+        who: &str,
+    ) -> TribResult<()> {
+        let token = match session_token(req) {
+            Some(t) => t,
+            None => return Err(Box::new(TribblerError::NotSignedIn(who.to_string()))),
+        };
+        let user = data.session_user(&token).await?;
+        if user != who {
+            return Err(Box::new(TribblerError::SignedInAsOther(
+                who.to_string(),
+                user,
+            )));
+        }
+        Ok(())
+    }
+
+    /// like [signed_in_as], but returns the response to send back if the
+    /// request is not signed in as `who`: forbidden when it is signed in as
+    /// someone else, unauthorized otherwise
+    async fn authorize(
+        data: &web::Data<Srv>,
+        req: &HttpRequest,
+        who: &str,
+    ) -> Result<(), HttpResponse> {
+        signed_in_as(data, req, who).await.map_err(|e| {
+            match e.downcast_ref::<TribblerError>() {
+                Some(TribblerError::SignedInAsOther(_, _)) => HttpResponse::Forbidden(),
+                _ => HttpResponse::Unauthorized(),
+            }
+            .body(e.to_string())
+        })
+    }
+
+    /// adds to each of `tribs` how many users like it, and whether the user
+    /// the request is signed in as, if any, is one of them. A retrib counts
+    /// the likes of the original.
+    pub async fn with_likes(
+        data: &web::Data<Srv>,
+        req: &HttpRequest,
+        tribs: Vec<Arc<Trib>>,
+    ) -> TribResult<Vec<LikedTrib>> {
+        let ids = tribs
+            .iter()
+            .map(|t| match t.retrib_of.as_str() {
+                "" => t.id.clone(),
+                id => id.to_string(),
+            })
+            .collect::<Vec<String>>();
+        let counts = data.like_counts(&ids).await?;
+        let viewer = match session_token(req) {
+            Some(token) => data.session_user(&token).await.ok(),
+            None => None,
+        };
+        let liked = match viewer {
+            Some(user) => data.liked(&user, &ids).await?,
+            None => vec![false; ids.len()],
+        };
+        Ok(tribs
+            .into_iter()
+            .zip(counts.into_iter().zip(liked))
+            .map(|(trib, (likes, liked))| LikedTrib { trib, likes, liked })
+            .collect())
+    }
+
+    /// signs up a new user who can log in with a password
+    #[post("/sign-up")]
+    pub async fn sign_up(
         data: web::Data<Srv>,
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        debug!("add-user: {:?}", &s);
-        match data.sign_up(s.keys().next().unwrap()).await {
-            Ok(_) => build_resp(&UserList {
-                users: data.list_users().await.unwrap(),
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let c = match serde_json::from_str::<Credentials>(raw) {
+            Ok(c) => c,
+            Err(e) => return err_response(Box::new(e)),
+        };
+        if let Err(e) = data.sign_up_with_password(&c.user, &c.password).await {
+            return err_response(e);
+        }
+        match data.list_users().await {
+            Ok(users) => build_resp(&UserList {
+                users,
                 err: "".to_string(),
             }),
             Err(e) => err_response(e),
         }
     }
 
+    /// logs a user in, returning the session token and also storing it in
+    /// a cookie
+    #[post("/login")]
+    pub async fn login(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let c = match serde_json::from_str::<Credentials>(raw) {
+            Ok(c) => c,
+            Err(e) => return err_response(Box::new(e)),
+        };
+        match data.login(&c.user, &c.password).await {
+            Ok(token) => HttpResponse::Ok()
+                .content_type(ContentType::plaintext())
+                .cookie(session_cookie(&token))
+                .body(
+                    serde_json::to_string(&Token {
+                        token,
+                        err: "".to_string(),
+                    })
+                    .unwrap(),
+                ),
+            Err(e) => HttpResponse::Unauthorized().body(e.to_string()),
+        }
+    }
+
+    /// ends the session of a request and clears the session cookie
+    #[post("/logout")]
+    pub async fn logout(data: web::Data<Srv>, req: HttpRequest) -> impl Responder {
+        if let Some(token) = session_token(&req) {
+            if let Err(e) = data.logout(&token).await {
+                return err_response(e);
+            }
+        }
+        HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .cookie(removal_cookie())
+            .body(
+                serde_json::to_string(&Bool {
+                    v: true,
+                    err: "".to_string(),
+                })
+                .unwrap(),
+            )
+    }
+
     /// lists all the users registered
     #[get("list-users")]
     pub async fn list_users(data: web::Data<Srv>) -> impl Responder {
@@ -174,17 +400,31 @@ mod api {
         }
     }
 
-    /// lists all the tribs for a particular user
+    /// lists all the tribs for a particular user, along with their profile
     #[post("list-tribs")]
     pub async fn list_tribs(
         data: web::Data<Srv>,
+        req: HttpRequest,
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        match data.tribs(s.keys().next().unwrap()).await {
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let tribs = match data.tribs(user).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e),
+        };
+        let tribs = match with_likes(&data, &req, tribs).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e),
+        };
+        match data.get_profile(user).await {
             Ok(v) => {
-                let ul = TribList {
-                    tribs: v,
+                let ul = UserTribs {
+                    tribs,
+                    profile: v,
                     err: "".to_string(),
                 };
                 build_resp(&ul)
@@ -197,12 +437,87 @@ mod api {
     #[post("list-home")]
     pub async fn list_home(
         data: web::Data<Srv>,
+        req: HttpRequest,
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        match data.home(s.keys().next().unwrap()).await {
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let tribs = match data.home(user).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e),
+        };
+        match with_likes(&data, &req, tribs).await {
             Ok(v) => {
-                let ul = TribList {
+                let ul = LikedTribList {
+                    tribs: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// lists the tribs a particular user posted before a cursor, so that
+    /// older tribs can be loaded page by page
+    #[post("list-tribs-before")]
+    pub async fn list_tribs_before(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let p = match serde_json::from_str::<Page>(raw) {
+            Ok(p) => p,
+            Err(e) => return err_response(Box::new(e)),
+        };
+        let tribs = match data.tribs_before(&p.user, &p.cursor, p.limit).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e),
+        };
+        match with_likes(&data, &req, tribs).await {
+            Ok(v) => {
+                let ul = LikedTribList {
+                    tribs: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// lists the home page of a particular user before a cursor, so that
+    /// older tribs can be loaded page by page
+    #[post("list-home-before")]
+    pub async fn list_home_before(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let p = match serde_json::from_str::<Page>(raw) {
+            Ok(p) => p,
+            Err(e) => return err_response(Box::new(e)),
+        };
+        let tribs = match data.home_before(&p.user, &p.cursor, p.limit).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e),
+        };
+        match with_likes(&data, &req, tribs).await {
+            Ok(v) => {
+                let ul = LikedTribList {
                     tribs: v,
                     err: "".to_string(),
                 };
@@ -219,8 +534,14 @@ mod api {
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        let raw = s.keys().next().unwrap();
-        let t = serde_json::from_str::<WhoWhom>(raw).unwrap();
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let t = match serde_json::from_str::<WhoWhom>(raw) {
+            Ok(t) => t,
+            Err(e) => return err_response(Box::new(e)),
+        };
         match data.is_following(&t.who, &t.whom).await {
             Ok(v) => {
                 let ul = Bool {
@@ -237,11 +558,21 @@ mod api {
     #[post("follow")]
     pub async fn follow(
         data: web::Data<Srv>,
+        req: HttpRequest,
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        let raw = s.keys().next().unwrap();
-        let t = serde_json::from_str::<WhoWhom>(raw).unwrap();
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let t = match serde_json::from_str::<WhoWhom>(raw) {
+            Ok(t) => t,
+            Err(e) => return err_response(Box::new(e)),
+        };
+        if let Err(resp) = authorize(&data, &req, &t.who).await {
+            return resp;
+        }
         match data.follow(&t.who, &t.whom).await {
             Ok(_) => {
                 let ul = Bool {
@@ -258,11 +589,21 @@ mod api {
     #[post("unfollow")]
     pub async fn unfollow(
         data: web::Data<Srv>,
+        req: HttpRequest,
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        let raw = s.keys().next().unwrap();
-        let t = serde_json::from_str::<WhoWhom>(raw).unwrap();
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let t = match serde_json::from_str::<WhoWhom>(raw) {
+            Ok(t) => t,
+            Err(e) => return err_response(Box::new(e)),
+        };
+        if let Err(resp) = authorize(&data, &req, &t.who).await {
+            return resp;
+        }
         match data.unfollow(&t.who, &t.whom).await {
             Ok(_) => {
                 let ul = Bool {
@@ -282,7 +623,34 @@ mod api {
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        match data.following(s.keys().next().unwrap()).await {
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match data.following(user).await {
+            Ok(v) => {
+                let ul = UserList {
+                    users: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// gets the list of users following a particular user
+    #[post("followers")]
+    pub async fn followers(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match data.followers(user).await {
             Ok(v) => {
                 let ul = UserList {
                     users: v,
@@ -294,16 +662,161 @@ mod api {
         }
     }
 
+    /// gets the follower, following and trib counts of a particular user
+    #[post("user-stats")]
+    pub async fn user_stats(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match data.user_stats(user).await {
+            Ok(v) => {
+                let ul = Stats {
+                    stats: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// gets the profile of a particular user
+    #[post("profile")]
+    pub async fn profile(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match data.get_profile(user).await {
+            Ok(v) => {
+                let ul = ProfileOf {
+                    profile: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// replaces the display name, bio and avatar of a particular user
+    #[post("update-profile")]
+    pub async fn update_profile(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match serde_json::from_str::<NewProfile>(raw) {
+            Ok(p) => {
+                if let Err(resp) = authorize(&data, &req, &p.who).await {
+                    return resp;
+                }
+                let x = match data.update_profile(&p.who, &p.profile).await {
+                    Ok(_) => Bool {
+                        v: true,
+                        err: "".to_string(),
+                    },
+                    Err(e) => Bool {
+                        v: false,
+                        err: e.to_string(),
+                    },
+                };
+                build_resp(&x)
+            }
+            Err(e) => err_response(Box::new(e)),
+        }
+    }
+
+    /// deletes a particular user along with all their tribs
+    #[post("delete-user")]
+    pub async fn delete_user(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        if let Err(resp) = authorize(&data, &req, user).await {
+            return resp;
+        }
+        let x = match data.delete_user(user).await {
+            Ok(_) => Bool {
+                v: true,
+                err: "".to_string(),
+            },
+            Err(e) => Bool {
+                v: false,
+                err: e.to_string(),
+            },
+        };
+        let mut resp = build_resp(&x);
+        if x.v {
+            let _ = resp.add_cookie(&removal_cookie());
+        }
+        resp
+    }
+
+    /// gets everything kept on a particular user
+    #[post("export-user")]
+    pub async fn export_user(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        if let Err(resp) = authorize(&data, &req, user).await {
+            return resp;
+        }
+        match data.export_user(user).await {
+            Ok(v) => {
+                let ul = Archive {
+                    archive: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
     /// adds a post for a particular user
     #[post("post")]
     pub async fn post(
         data: web::Data<Srv>,
+        req: HttpRequest,
         form: web::Form<HashMap<String, String>>,
     ) -> impl Responder {
         let s = form.0;
-        let raw = s.keys().next().unwrap();
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
         match serde_json::from_str::<Post>(raw) {
             Ok(p) => {
+                if let Err(resp) = authorize(&data, &req, &p.who).await {
+                    return resp;
+                }
                 let x = match data.post(&p.who, &p.message, p.clock).await {
                     Ok(_) => Bool {
                         v: true,
@@ -320,34 +833,416 @@ mod api {
         }
     }
 
-    use serde::{Deserialize, Serialize};
-    use tribbler::trib::Trib;
-
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct UserList {
-        err: String,
-        users: Vec<String>,
+    /// adds a post for a particular user in reply to another post
+    #[post("reply")]
+    pub async fn reply(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match serde_json::from_str::<Reply>(raw) {
+            Ok(p) => {
+                if let Err(resp) = authorize(&data, &req, &p.who).await {
+                    return resp;
+                }
+                let x = match data.reply(&p.who, &p.parent, &p.message, p.clock).await {
+                    Ok(_) => Bool {
+                        v: true,
+                        err: "".to_string(),
+                    },
+                    Err(e) => Bool {
+                        v: false,
+                        err: e.to_string(),
+                    },
+                };
+                build_resp(&x)
+            }
+            Err(e) => err_response(Box::new(e)),
+        }
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct TribList {
-        err: String,
-        tribs: Vec<Arc<Trib>>,
+    /// reposts a trib on behalf of a particular user
+    #[post("retrib")]
+    pub async fn retrib(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match serde_json::from_str::<RetribOf>(raw) {
+            Ok(p) => {
+                if let Err(resp) = authorize(&data, &req, &p.who).await {
+                    return resp;
+                }
+                let x = match data.retrib(&p.who, &p.id, p.clock).await {
+                    Ok(_) => Bool {
+                        v: true,
+                        err: "".to_string(),
+                    },
+                    Err(e) => Bool {
+                        v: false,
+                        err: e.to_string(),
+                    },
+                };
+                build_resp(&x)
+            }
+            Err(e) => err_response(Box::new(e)),
+        }
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct Bool {
-        err: String,
-        v: bool,
+    /// likes a trib on behalf of a particular user
+    #[post("like")]
+    pub async fn like(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match serde_json::from_str::<LikeOf>(raw) {
+            Ok(p) => {
+                if let Err(resp) = authorize(&data, &req, &p.who).await {
+                    return resp;
+                }
+                let x = match data.like(&p.who, &p.id).await {
+                    Ok(_) => Bool {
+                        v: true,
+                        err: "".to_string(),
+                    },
+                    Err(e) => Bool {
+                        v: false,
+                        err: e.to_string(),
+                    },
+                };
+                build_resp(&x)
+            }
+            Err(e) => err_response(Box::new(e)),
+        }
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
-    struct Clock {
-        err: String,
-        n: u64,
+    /// takes back a like of a trib by a particular user
+    #[post("unlike")]
+    pub async fn unlike(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match serde_json::from_str::<LikeOf>(raw) {
+            Ok(p) => {
+                if let Err(resp) = authorize(&data, &req, &p.who).await {
+                    return resp;
+                }
+                let x = match data.unlike(&p.who, &p.id).await {
+                    Ok(_) => Bool {
+                        v: true,
+                        err: "".to_string(),
+                    },
+                    Err(e) => Bool {
+                        v: false,
+                        err: e.to_string(),
+                    },
+                };
+                build_resp(&x)
+            }
+            Err(e) => err_response(Box::new(e)),
+        }
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    /// lists the tribs mentioning a particular user
+    #[post("list-mentions")]
+    pub async fn list_mentions(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let user = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match data.mentions(user).await {
+            Ok(v) => {
+                let ul = TribList {
+                    tribs: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// lists the conversation a particular trib is part of
+    #[post("thread")]
+    pub async fn thread(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let id = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match data.thread(id).await {
+            Ok(v) => {
+                let ul = TribList {
+                    tribs: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// lists the latest tribs tagged with a particular hashtag
+    #[post("tag-timeline")]
+    pub async fn tag_timeline(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let tag = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match data.tag_timeline(tag).await {
+            Ok(v) => {
+                let ul = TribList {
+                    tribs: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// searches the messages of all tribs, best match first
+    #[post("search")]
+    pub async fn search(
+        data: web::Data<Srv>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        let q = match serde_json::from_str::<Query>(raw) {
+            Ok(q) => q,
+            Err(e) => return err_response(Box::new(e)),
+        };
+        match data.search(&q.query, q.limit).await {
+            Ok(v) => {
+                let ul = TribList {
+                    tribs: v,
+                    err: "".to_string(),
+                };
+                build_resp(&ul)
+            }
+            Err(e) => err_response(e),
+        }
+    }
+
+    /// deletes one of a user's posts
+    #[post("delete-trib")]
+    pub async fn delete_trib(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match serde_json::from_str::<TribRef>(raw) {
+            Ok(t) => {
+                if let Err(resp) = authorize(&data, &req, &t.who).await {
+                    return resp;
+                }
+                let x = match data.delete_trib(&t.who, t.clock).await {
+                    Ok(_) => Bool {
+                        v: true,
+                        err: "".to_string(),
+                    },
+                    Err(e) => Bool {
+                        v: false,
+                        err: e.to_string(),
+                    },
+                };
+                build_resp(&x)
+            }
+            Err(e) => err_response(Box::new(e)),
+        }
+    }
+
+    /// changes the message of one of a user's posts
+    #[post("edit-trib")]
+    pub async fn edit_trib(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        form: web::Form<HashMap<String, String>>,
+    ) -> impl Responder {
+        let s = form.0;
+        let raw = match s.keys().next() {
+            Some(k) => k,
+            None => return empty_form(),
+        };
+        match serde_json::from_str::<Post>(raw) {
+            Ok(p) => {
+                if let Err(resp) = authorize(&data, &req, &p.who).await {
+                    return resp;
+                }
+                let x = match data.edit_trib(&p.who, p.clock, &p.message).await {
+                    Ok(_) => Bool {
+                        v: true,
+                        err: "".to_string(),
+                    },
+                    Err(e) => Bool {
+                        v: false,
+                        err: e.to_string(),
+                    },
+                };
+                build_resp(&x)
+            }
+            Err(e) => err_response(Box::new(e)),
+        }
+    }
+
+    /// streams the tribs joining the home timeline of a particular user as
+    /// server-sent events, one `trib` event per trib. Only that user may
+    /// subscribe. The stream ends when the timeline has to be read again;
+    /// browsers then reconnect on their own.
+    #[get("stream/home/{user}")]
+    pub async fn stream_home(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        user: web::Path<String>,
+    ) -> impl Responder {
+        if let Err(resp) = authorize(&data, &req, &user).await {
+            return resp;
+        }
+        let tribs = match data.watch_home(&user).await {
+            Ok(rx) => rx,
+            Err(e) => return err_response(e),
+        };
+        let events = stream::unfold(tribs, |mut tribs| async move {
+            let event = tokio::select! {
+                trib = tribs.recv() => {
+                    let trib = serde_json::to_string(&trib?).unwrap();
+                    format!("event: trib\ndata: {}\n\n", trib)
+                }
+                _ = tokio::time::sleep(KEEP_ALIVE) => ": keep-alive\n\n".to_string(),
+            };
+            Some((Ok::<_, actix_web::Error>(Bytes::from(event)), tribs))
+        });
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(events)
+    }
+
+    use serde::{Deserialize, Serialize};
+    use tribbler::trib::{Profile, Trib, UserArchive, UserStats};
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct UserList {
+        err: String,
+        users: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct TribList {
+        err: String,
+        tribs: Vec<Arc<Trib>>,
+    }
+
+    /// a trib as [list_tribs] and [list_home] list it, see [with_likes]
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct LikedTrib {
+        #[serde(flatten)]
+        trib: Arc<Trib>,
+        likes: u64,
+        liked: bool,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct LikedTribList {
+        err: String,
+        tribs: Vec<LikedTrib>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct UserTribs {
+        err: String,
+        tribs: Vec<LikedTrib>,
+        profile: Profile,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Stats {
+        err: String,
+        #[serde(flatten)]
+        stats: UserStats,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct ProfileOf {
+        err: String,
+        #[serde(flatten)]
+        profile: Profile,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Archive {
+        err: String,
+        #[serde(flatten)]
+        archive: UserArchive,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Bool {
+        err: String,
+        v: bool,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Token {
+        err: String,
+        token: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Credentials {
+        user: String,
+        password: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Clock {
+        err: String,
+        n: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
     struct WhoWhom {
         who: String,
         whom: String,
@@ -359,4 +1254,937 @@ mod api {
         message: String,
         clock: u64,
     }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Reply {
+        who: String,
+        parent: String,
+        message: String,
+        clock: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct RetribOf {
+        who: String,
+        id: String,
+        clock: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct LikeOf {
+        who: String,
+        id: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct NewProfile {
+        who: String,
+        #[serde(flatten)]
+        profile: Profile,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Page {
+        user: String,
+        /// id of the oldest trib already loaded, empty for the first page
+        #[serde(default)]
+        cursor: String,
+        #[serde(default = "default_page_limit")]
+        limit: usize,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Query {
+        query: String,
+        #[serde(default = "default_page_limit")]
+        limit: usize,
+    }
+
+    fn default_page_limit() -> usize {
+        tribbler::trib::MAX_TRIB_FETCH
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct TribRef {
+        who: String,
+        clock: u64,
+    }
+
+    #[cfg(test)]
+    mod test {
+        use actix_web::{
+            http::StatusCode,
+            test::{call_service, init_service, TestRequest},
+            web, App,
+        };
+        use tribbler::{ref_impl::RefServer, trib::Server};
+
+        use super::{list_home, stream_home};
+        use crate::Srv;
+
+        #[actix_web::test]
+        async fn empty_form() {
+            let srv: Srv = Box::new(RefServer::new());
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(srv))
+                    .service(web::scope("/api").service(list_home)),
+            )
+            .await;
+            let req = TestRequest::post()
+                .uri("/api/list-home")
+                .insert_header(("content-type", "application/x-www-form-urlencoded"))
+                .set_payload("")
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        }
+
+        #[actix_web::test]
+        async fn stream_home_needs_session() {
+            let srv = RefServer::new();
+            srv.sign_up_with_password("alice", "password")
+                .await
+                .unwrap();
+            srv.sign_up("bob").await.unwrap();
+            let token = srv.login("alice", "password").await.unwrap();
+            let srv: Srv = Box::new(srv);
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(srv))
+                    .service(web::scope("/api").service(stream_home)),
+            )
+            .await;
+            let req = TestRequest::get()
+                .uri("/api/stream/home/alice")
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+            let req = TestRequest::get()
+                .uri("/api/stream/home/bob")
+                .insert_header(("authorization", format!("Bearer {}", token)))
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::FORBIDDEN, resp.status());
+            let req = TestRequest::get()
+                .uri("/api/stream/home/alice")
+                .insert_header(("authorization", format!("Bearer {}", token)))
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::OK, resp.status());
+        }
+    }
+}
+
+/// this module contains the versioned JSON REST API, which takes JSON
+/// bodies and path parameters, and reports errors with fitting status
+/// codes and a JSON body
+mod v2 {
+    use std::error::Error;
+    use std::sync::Arc;
+
+    use actix_web::{
+        delete,
+        error::InternalError,
+        get,
+        http::{
+            header::{ContentDisposition, DispositionParam, DispositionType},
+            StatusCode,
+        },
+        post, put, web, HttpRequest, HttpResponse,
+    };
+    use serde::{Deserialize, Serialize};
+    use tribbler::{
+        err::TribblerError,
+        trib::{Profile, Trib, MAX_TRIB_FETCH},
+    };
+
+    use crate::api::{
+        removal_cookie, session_cookie, session_token, signed_in_as, with_likes, LikedTrib,
+    };
+    use crate::Srv;
+
+    /// returns the status code and the machine readable code an error is
+    /// reported with
+    fn status_of(err: &(dyn Error + 'static)) -> (StatusCode, &'static str) {
+        let err = match err.downcast_ref::<TribblerError>() {
+            Some(e) => e,
+            None => return (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+        match err {
+            TribblerError::UserDoesNotExist(_) => (StatusCode::NOT_FOUND, "user_does_not_exist"),
+            TribblerError::TribDoesNotExist(_, _) => (StatusCode::NOT_FOUND, "trib_does_not_exist"),
+            TribblerError::UsernameTaken(_) => (StatusCode::CONFLICT, "username_taken"),
+            TribblerError::ConfusableUsername(_) => (StatusCode::CONFLICT, "confusable_username"),
+            TribblerError::AlreadyFollowing(_, _) => (StatusCode::CONFLICT, "already_following"),
+            TribblerError::NotFollowing(_, _) => (StatusCode::CONFLICT, "not_following"),
+            TribblerError::Blocked(_, _) => (StatusCode::FORBIDDEN, "blocked"),
+            TribblerError::AlreadyRetribbed(_, _) => (StatusCode::CONFLICT, "already_retribbed"),
+            TribblerError::RetribNotEditable(_, _) => {
+                (StatusCode::CONFLICT, "retrib_not_editable")
+            }
+            TribblerError::TribTooLong => (StatusCode::UNPROCESSABLE_ENTITY, "trib_too_long"),
+            TribblerError::ProfileFieldTooLong(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "profile_field_too_long")
+            }
+            TribblerError::InvalidAvatarUrl(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "invalid_avatar_url")
+            }
+            TribblerError::InvalidUsername(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "invalid_username")
+            }
+            TribblerError::PasswordTooShort => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "password_too_short")
+            }
+            TribblerError::FollowingTooMany => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "following_too_many")
+            }
+            TribblerError::WhoWhom(_) => (StatusCode::UNPROCESSABLE_ENTITY, "who_whom"),
+            TribblerError::InvalidTribId(_) => (StatusCode::BAD_REQUEST, "invalid_trib_id"),
+            TribblerError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "invalid_credentials"),
+            TribblerError::InvalidSession => (StatusCode::UNAUTHORIZED, "invalid_session"),
+            TribblerError::NotSignedIn(_) => (StatusCode::UNAUTHORIZED, "not_signed_in"),
+            TribblerError::SignedInAsOther(_, _) => (StatusCode::FORBIDDEN, "signed_in_as_other"),
+            TribblerError::RpcError(_) => (StatusCode::BAD_GATEWAY, "rpc_error"),
+            TribblerError::MaxedSeq => (StatusCode::SERVICE_UNAVAILABLE, "maxed_seq"),
+            TribblerError::StaleTerm(_) => (StatusCode::SERVICE_UNAVAILABLE, "stale_term"),
+            TribblerError::Unknown(_) => (StatusCode::INTERNAL_SERVER_ERROR, "unknown"),
+        }
+    }
+
+    fn err_response(err: &(dyn Error + 'static)) -> HttpResponse {
+        let (status, code) = status_of(err);
+        HttpResponse::build(status).json(ErrorBody {
+            code: code.to_string(),
+            message: err.to_string(),
+        })
+    }
+
+    /// responds to a request with a malformed body, path or query
+    fn bad_request(err: &dyn Error) -> HttpResponse {
+        HttpResponse::BadRequest().json(ErrorBody {
+            code: "bad_request".to_string(),
+            message: err.to_string(),
+        })
+    }
+
+    /// makes extractors that fail report it the same way as the handlers
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        cfg.app_data(web::JsonConfig::default().error_handler(|e, _| {
+            let resp = bad_request(&e);
+            InternalError::from_response(e, resp).into()
+        }))
+        .app_data(web::PathConfig::default().error_handler(|e, _| {
+            let resp = bad_request(&e);
+            InternalError::from_response(e, resp).into()
+        }))
+        .app_data(web::QueryConfig::default().error_handler(|e, _| {
+            let resp = bad_request(&e);
+            InternalError::from_response(e, resp).into()
+        }))
+        .service(sign_up)
+        .service(list_users)
+        .service(login)
+        .service(logout)
+        .service(tribs)
+        .service(post)
+        .service(edit_trib)
+        .service(delete_trib)
+        .service(retrib)
+        .service(home)
+        .service(like)
+        .service(unlike)
+        .service(following)
+        .service(is_following)
+        .service(follow)
+        .service(unfollow)
+        .service(followers)
+        .service(blocked)
+        .service(block)
+        .service(unblock)
+        .service(muted)
+        .service(mute)
+        .service(unmute)
+        .service(user_stats)
+        .service(profile)
+        .service(update_profile)
+        .service(delete_user)
+        .service(export_user)
+        .service(mentions)
+        .service(thread)
+        .service(tag_timeline)
+        .service(search);
+    }
+
+    /// signs up a new user who can log in with a password
+    #[post("/users")]
+    pub async fn sign_up(data: web::Data<Srv>, body: web::Json<Credentials>) -> HttpResponse {
+        match data.sign_up_with_password(&body.user, &body.password).await {
+            Ok(_) => HttpResponse::Created().json(User {
+                user: body.user.clone(),
+            }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the users registered
+    #[get("/users")]
+    pub async fn list_users(data: web::Data<Srv>) -> HttpResponse {
+        match data.list_users().await {
+            Ok(v) => HttpResponse::Ok().json(UserList { users: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// logs a user in, returning the session token and also storing it in
+    /// a cookie
+    #[post("/sessions")]
+    pub async fn login(data: web::Data<Srv>, body: web::Json<Credentials>) -> HttpResponse {
+        match data.login(&body.user, &body.password).await {
+            Ok(token) => HttpResponse::Created()
+                .cookie(session_cookie(&token))
+                .json(Token { token }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// ends the session of a request and clears the session cookie
+    #[delete("/sessions")]
+    pub async fn logout(data: web::Data<Srv>, req: HttpRequest) -> HttpResponse {
+        if let Some(token) = session_token(&req) {
+            if let Err(e) = data.logout(&token).await {
+                return err_response(e.as_ref());
+            }
+        }
+        HttpResponse::NoContent().cookie(removal_cookie()).finish()
+    }
+
+    /// lists the tribs of a user, a page at a time, along with their profile
+    #[get("/users/{name}/tribs")]
+    pub async fn tribs(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+        page: web::Query<Page>,
+    ) -> HttpResponse {
+        let tribs = match data.tribs_before(&name, &page.before, page.limit).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e.as_ref()),
+        };
+        let tribs = match with_likes(&data, &req, tribs).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e.as_ref()),
+        };
+        match data.get_profile(&name).await {
+            Ok(v) => HttpResponse::Ok().json(UserTribs { tribs, profile: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// posts a trib, in reply to another one if a parent is given
+    #[post("/users/{name}/tribs")]
+    pub async fn post(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+        body: web::Json<NewTrib>,
+    ) -> HttpResponse {
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        let r = match &body.parent {
+            Some(parent) => data.reply(&name, parent, &body.message, body.clock).await,
+            None => data.post(&name, &body.message, body.clock).await,
+        };
+        match r {
+            Ok(_) => HttpResponse::Created().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// changes the message of one of a user's tribs
+    #[put("/users/{name}/tribs/{clock}")]
+    pub async fn edit_trib(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, u64)>,
+        body: web::Json<Message>,
+    ) -> HttpResponse {
+        let (name, clock) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.edit_trib(&name, clock, &body.message).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// deletes one of a user's tribs
+    #[delete("/users/{name}/tribs/{clock}")]
+    pub async fn delete_trib(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, u64)>,
+    ) -> HttpResponse {
+        let (name, clock) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.delete_trib(&name, clock).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// reposts a trib on behalf of a user
+    #[post("/users/{name}/retribs")]
+    pub async fn retrib(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+        body: web::Json<NewRetrib>,
+    ) -> HttpResponse {
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.retrib(&name, &body.id, body.clock).await {
+            Ok(_) => HttpResponse::Created().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the home timeline of a user, a page at a time
+    #[get("/users/{name}/home")]
+    pub async fn home(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+        page: web::Query<Page>,
+    ) -> HttpResponse {
+        let timeline = match data.home_before(&name, &page.before, page.limit).await {
+            Ok(v) => v,
+            Err(e) => return err_response(e.as_ref()),
+        };
+        match with_likes(&data, &req, timeline).await {
+            Ok(v) => HttpResponse::Ok().json(LikedTribList { tribs: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// makes a user like a trib
+    #[put("/users/{name}/likes/{id}")]
+    pub async fn like(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, id) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.like(&name, &id).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// takes back a like of a trib by a user
+    #[delete("/users/{name}/likes/{id}")]
+    pub async fn unlike(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, id) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.unlike(&name, &id).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the users a user follows
+    #[get("/users/{name}/following")]
+    pub async fn following(data: web::Data<Srv>, name: web::Path<String>) -> HttpResponse {
+        match data.following(&name).await {
+            Ok(v) => HttpResponse::Ok().json(UserList { users: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// tells whether a user follows another one
+    #[get("/users/{name}/following/{whom}")]
+    pub async fn is_following(
+        data: web::Data<Srv>,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, whom) = path.into_inner();
+        match data.is_following(&name, &whom).await {
+            Ok(v) => HttpResponse::Ok().json(Following { following: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// makes a user follow another one
+    #[put("/users/{name}/following/{whom}")]
+    pub async fn follow(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, whom) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.follow(&name, &whom).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// makes a user unfollow another one
+    #[delete("/users/{name}/following/{whom}")]
+    pub async fn unfollow(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, whom) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.unfollow(&name, &whom).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the users following a user
+    #[get("/users/{name}/followers")]
+    pub async fn followers(data: web::Data<Srv>, name: web::Path<String>) -> HttpResponse {
+        match data.followers(&name).await {
+            Ok(v) => HttpResponse::Ok().json(UserList { users: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the users a user has blocked
+    #[get("/users/{name}/blocked")]
+    pub async fn blocked(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+    ) -> HttpResponse {
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.blocked(&name).await {
+            Ok(v) => HttpResponse::Ok().json(UserList { users: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// makes a user block another one
+    #[put("/users/{name}/blocked/{whom}")]
+    pub async fn block(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, whom) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.block(&name, &whom).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// makes a user unblock another one
+    #[delete("/users/{name}/blocked/{whom}")]
+    pub async fn unblock(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, whom) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.unblock(&name, &whom).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the users a user has muted
+    #[get("/users/{name}/muted")]
+    pub async fn muted(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+    ) -> HttpResponse {
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.muted(&name).await {
+            Ok(v) => HttpResponse::Ok().json(UserList { users: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// makes a user mute another one
+    #[put("/users/{name}/muted/{whom}")]
+    pub async fn mute(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, whom) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.mute(&name, &whom).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// makes a user unmute another one
+    #[delete("/users/{name}/muted/{whom}")]
+    pub async fn unmute(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        path: web::Path<(String, String)>,
+    ) -> HttpResponse {
+        let (name, whom) = path.into_inner();
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.unmute(&name, &whom).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// gets the follower, following and trib counts of a user
+    #[get("/users/{name}/stats")]
+    pub async fn user_stats(data: web::Data<Srv>, name: web::Path<String>) -> HttpResponse {
+        match data.user_stats(&name).await {
+            Ok(v) => HttpResponse::Ok().json(v),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// gets the profile of a user
+    #[get("/users/{name}/profile")]
+    pub async fn profile(data: web::Data<Srv>, name: web::Path<String>) -> HttpResponse {
+        match data.get_profile(&name).await {
+            Ok(v) => HttpResponse::Ok().json(v),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// replaces the display name, bio and avatar of a user
+    #[put("/users/{name}/profile")]
+    pub async fn update_profile(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+        body: web::Json<Profile>,
+    ) -> HttpResponse {
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.update_profile(&name, &body).await {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// deletes a user along with all their tribs, and clears the session
+    /// cookie
+    #[delete("/users/{name}")]
+    pub async fn delete_user(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+    ) -> HttpResponse {
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.delete_user(&name).await {
+            Ok(_) => HttpResponse::NoContent().cookie(removal_cookie()).finish(),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// downloads everything kept on a user as a JSON file
+    #[get("/users/{name}/export")]
+    pub async fn export_user(
+        data: web::Data<Srv>,
+        req: HttpRequest,
+        name: web::Path<String>,
+    ) -> HttpResponse {
+        if let Err(e) = signed_in_as(&data, &req, &name).await {
+            return err_response(e.as_ref());
+        }
+        match data.export_user(&name).await {
+            Ok(v) => HttpResponse::Ok()
+                .insert_header(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(format!("{}.json", name))],
+                })
+                .json(v),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the tribs mentioning a user
+    #[get("/users/{name}/mentions")]
+    pub async fn mentions(data: web::Data<Srv>, name: web::Path<String>) -> HttpResponse {
+        match data.mentions(&name).await {
+            Ok(v) => HttpResponse::Ok().json(TribList { tribs: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the conversation a trib is part of
+    #[get("/tribs/{id}/thread")]
+    pub async fn thread(data: web::Data<Srv>, id: web::Path<String>) -> HttpResponse {
+        match data.thread(&id).await {
+            Ok(v) => HttpResponse::Ok().json(TribList { tribs: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// lists the latest tribs tagged with a hashtag
+    #[get("/tags/{tag}")]
+    pub async fn tag_timeline(data: web::Data<Srv>, tag: web::Path<String>) -> HttpResponse {
+        match data.tag_timeline(&tag).await {
+            Ok(v) => HttpResponse::Ok().json(TribList { tribs: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    /// searches the messages of all tribs, best match first
+    #[get("/search")]
+    pub async fn search(data: web::Data<Srv>, query: web::Query<Search>) -> HttpResponse {
+        match data.search(&query.q, query.limit).await {
+            Ok(v) => HttpResponse::Ok().json(TribList { tribs: v }),
+            Err(e) => err_response(e.as_ref()),
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct ErrorBody {
+        code: String,
+        message: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct User {
+        user: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct UserList {
+        users: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct TribList {
+        tribs: Vec<Arc<Trib>>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct LikedTribList {
+        tribs: Vec<LikedTrib>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct UserTribs {
+        tribs: Vec<LikedTrib>,
+        profile: Profile,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Token {
+        token: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    struct Following {
+        following: bool,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Credentials {
+        user: String,
+        password: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct NewTrib {
+        message: String,
+        clock: u64,
+        /// id of the trib this one replies to
+        parent: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Message {
+        message: String,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct NewRetrib {
+        id: String,
+        clock: u64,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Page {
+        /// id of the oldest trib already loaded, empty for the first page
+        #[serde(default)]
+        before: String,
+        #[serde(default = "default_page_limit")]
+        limit: usize,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Search {
+        q: String,
+        #[serde(default = "default_page_limit")]
+        limit: usize,
+    }
+
+    fn default_page_limit() -> usize {
+        MAX_TRIB_FETCH
+    }
+
+    #[cfg(test)]
+    mod test {
+        use actix_web::{
+            http::StatusCode,
+            test::{
+                call_and_read_body_json, call_service, init_service, read_body_json, TestRequest,
+            },
+            web, App,
+        };
+        use serde_json::json;
+        use tribbler::{err::TribblerError, ref_impl::RefServer};
+
+        use super::{configure, status_of};
+        use crate::Srv;
+
+        #[test]
+        fn status_codes() {
+            let missing = TribblerError::UserDoesNotExist("alice".to_string());
+            assert_eq!(
+                (StatusCode::NOT_FOUND, "user_does_not_exist"),
+                status_of(&missing)
+            );
+            let taken = TribblerError::UsernameTaken("alice".to_string());
+            assert_eq!((StatusCode::CONFLICT, "username_taken"), status_of(&taken));
+            let other = TribblerError::SignedInAsOther("alice".to_string(), "bob".to_string());
+            assert_eq!(
+                (StatusCode::FORBIDDEN, "signed_in_as_other"),
+                status_of(&other)
+            );
+            let io = std::io::Error::other("disk");
+            assert_eq!(
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+                status_of(&io)
+            );
+        }
+
+        #[actix_web::test]
+        async fn requests() {
+            let srv: Srv = Box::new(RefServer::new());
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(srv))
+                    .service(web::scope("/api/v2").configure(configure)),
+            )
+            .await;
+            let sign_up = |body| {
+                TestRequest::post()
+                    .uri("/api/v2/users")
+                    .set_json(body)
+                    .to_request()
+            };
+
+            let alice = json!({"user": "alice", "password": "password"});
+            let resp = call_service(&app, sign_up(alice.clone())).await;
+            assert_eq!(StatusCode::CREATED, resp.status());
+            let resp = call_service(&app, sign_up(alice.clone())).await;
+            assert_eq!(StatusCode::CONFLICT, resp.status());
+            let bob = json!({"user": "bob", "password": "password"});
+            call_service(&app, sign_up(bob)).await;
+
+            // Sign-up takes a password, a valid username and a JSON body
+            let resp = call_service(&app, sign_up(json!({"user": "carol"}))).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+            let bad_name = json!({"user": "Carol!", "password": "password"});
+            let resp = call_service(&app, sign_up(bad_name)).await;
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+            let req = TestRequest::post()
+                .uri("/api/v2/users")
+                .insert_header(("content-type", "application/json"))
+                .set_payload("{")
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+            let req = TestRequest::get()
+                .uri("/api/v2/users/nobody/tribs")
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+            // Posting takes a session of the poster
+            let post = json!({"message": "hello", "clock": 0});
+            let req = TestRequest::post()
+                .uri("/api/v2/users/bob/tribs")
+                .set_json(post.clone())
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+            let req = TestRequest::post()
+                .uri("/api/v2/sessions")
+                .set_json(alice)
+                .to_request();
+            let token: serde_json::Value = call_and_read_body_json(&app, req).await;
+            let bearer = format!("Bearer {}", token["token"].as_str().unwrap());
+            let req = TestRequest::post()
+                .uri("/api/v2/users/bob/tribs")
+                .insert_header(("authorization", bearer.clone()))
+                .set_json(post.clone())
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::FORBIDDEN, resp.status());
+            let req = TestRequest::post()
+                .uri("/api/v2/users/alice/tribs")
+                .insert_header(("authorization", bearer.clone()))
+                .set_json(post)
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::CREATED, resp.status());
+
+            // Unfollowing a user not followed is a conflict
+            let req = TestRequest::delete()
+                .uri("/api/v2/users/alice/following/bob")
+                .insert_header(("authorization", bearer))
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(StatusCode::CONFLICT, resp.status());
+            let body: serde_json::Value = read_body_json(resp).await;
+            assert_eq!("not_following", body["code"]);
+        }
+    }
 }
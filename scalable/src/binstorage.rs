@@ -1,17 +1,164 @@
 use async_trait::async_trait;
-use std::{collections::hash_map::DefaultHasher, hash::Hasher, sync::Arc};
+use futures_util::future::join_all;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, RwLock};
 
 use tribbler::{
     colon,
-    err::TribResult,
-    storage::{BinStorage, KeyList, KeyString, KeyValue, List, Pattern, Storage},
+    err::{TribResult, TribblerError},
+    storage::{
+        AppendCondition, BinStorage, Change, KeyList, KeyString, KeyValue, List, Pattern, Storage,
+        WATCH_BUFFER,
+    },
 };
 
 use crate::kvstore::client::StorageClient;
+use crate::ring::Ring;
+
+/// The raw key under which the keeper publishes its [Membership] view on
+/// every live backend. It contains no `::`, so it never collides with the
+/// fully qualified keys of a bin.
+pub const KEY_MEMBERSHIP: &str = "__membership__";
+
+/// The raw key of the list, on every backend, of the bins whose other
+/// replicas missed a write this backend took. The keeper copies each of
+/// them onto its other replicas and takes it off the list.
+pub const KEY_DIVERGED: &str = "__diverged__";
+
+/// How long a client trusts a fetched [Membership] before fetching it again
+pub const MEMBERSHIP_TTL: Duration = Duration::from_secs(1);
+
+/// How long a client waits on each backend for its [Membership] view. Kept
+/// short, since every bin looked up waits on the slowest backend.
+const MEMBERSHIP_DEADLINE: Duration = Duration::from_millis(500);
+
+/// The keeper's view of which backends may serve bins
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Membership {
+    /// bumped every time the keeper publishes a new view
+    pub version: u64,
+    /// the term of the primary keeper that published the view. Each keeper
+    /// taking over starts a new, larger one, and views are ordered by term
+    /// first, then by version.
+    #[serde(default)]
+    pub term: u64,
+    /// whether each backend (by index into the config) is alive and caught
+    /// up. An empty list means every backend is considered alive.
+    pub alive: Vec<bool>,
+}
+
+impl Membership {
+    /// Whether the backend at index `i` may hold replicas under this view
+    pub fn is_alive(&self, i: usize) -> bool {
+        self.alive.get(i).copied().unwrap_or(self.alive.is_empty())
+    }
+
+    /// Whether this view was published after `other`
+    pub fn is_newer_than(&self, other: &Membership) -> bool {
+        (self.term, self.version) > (other.term, other.version)
+    }
+}
+
+/// Returns the indexes of the backends holding the replicas of bin `name`:
+/// the first `replicas` backends that are alive in `view`, walking the
+/// `ring` clockwise from the bin's hash.
+pub fn replica_indexes(ring: &Ring, name: &str, replicas: usize, view: &Membership) -> Vec<usize> {
+    if ring.is_empty() {
+        return vec![];
+    }
+    ring.successors(name)
+        .into_iter()
+        .filter(|i| view.is_alive(*i))
+        .take(replicas.clamp(1, ring.len()))
+        .collect()
+}
+
+/// Splits a fully qualified key into its (unescaped) bin name and the
+/// escaped key within the bin. Returns [None] for keys outside any bin.
+pub fn split_key(key_escfq: &str) -> Option<(String, &str)> {
+    key_escfq
+        .split_once("::")
+        .map(|(name_esc, key_esc)| (colon::unescape(name_esc), key_esc))
+}
 
 pub struct BinStorageClient {
     // Addresses of the backend servers
     pub backs: Vec<String>,
+    // Placement of bins onto the backends
+    pub ring: Ring,
+    // Number of backends each bin is replicated on
+    pub replicas: usize,
+    // One client per backend, shared by every bin placed on it
+    pub clients: Vec<StorageClient>,
+    // Last membership view fetched from the backends, and when
+    pub view: RwLock<Option<(Instant, Membership)>>,
+}
+
+impl BinStorageClient {
+    pub fn new(backs: Vec<String>, replicas: usize) -> TribResult<BinStorageClient> {
+        let clients = backs
+            .iter()
+            .map(|back| StorageClient::new(&format!("http://{}", back)))
+            .collect::<TribResult<Vec<StorageClient>>>()?;
+        Ok(BinStorageClient {
+            ring: Ring::new(&backs),
+            backs,
+            replicas,
+            clients,
+            view: RwLock::new(None),
+        })
+    }
+
+    /// Returns the current membership view, fetching it once the cached
+    /// copy expires. Every backend is asked and the newest view wins, since
+    /// a backend that was down, such as a disk-backed one that came back,
+    /// may still hold an old one.
+    async fn membership(&self) -> Membership {
+        if let Some((fetched, view)) = self.view.read().await.as_ref() {
+            if fetched.elapsed() < MEMBERSHIP_TTL {
+                return view.clone();
+            }
+        }
+        let fetches = self.clients.iter().map(|storage| async move {
+            storage
+                .with_deadline(MEMBERSHIP_DEADLINE)
+                .get(KEY_MEMBERSHIP)
+                .await
+        });
+        let mut view = Membership::default();
+        for raw in join_all(fetches).await.into_iter().flatten().flatten() {
+            if let Ok(v) = serde_json::from_str::<Membership>(&raw) {
+                if v.is_newer_than(&view) {
+                    view = v;
+                }
+            }
+        }
+        *self.view.write().await = Some((Instant::now(), view.clone()));
+        view
+    }
+
+    /// Returns the indexes of the backends holding the replicas of the bin
+    /// `name`, starting with the one closest to the bin on the ring.
+    async fn replicas_of(&self, name: &str) -> Vec<usize> {
+        let view = self.membership().await;
+        replica_indexes(&self.ring, name, self.replicas, &view)
+    }
+
+    /// Returns the addresses of the backends holding the replicas of the
+    /// bin `name`, starting with the one closest to the bin on the ring.
+    pub async fn replica_addrs(&self, name: &str) -> Vec<String> {
+        self.replicas_of(name)
+            .await
+            .into_iter()
+            .map(|i| self.backs[i].clone())
+            .collect()
+    }
 }
 
 #[async_trait]
@@ -19,140 +166,377 @@ impl BinStorage for BinStorageClient {
     async fn bin(&self, name: &str) -> TribResult<Box<dyn Storage>> {
         let name = name.to_string();
 
-        // Create the prefix ("{name}::") to translate the key
-        // into a fully qualified one in a form of "{name}::{key}"
-        let mut prefix = colon::escape(name.clone());
-        prefix.push_str(&"::".to_string());
-
-        // Determine the backend node will this bin belongs to
-        let mut hasher = DefaultHasher::new();
-        hasher.write(name.as_bytes());
-        let hashcode = hasher.finish();
-        let bk = &self.backs[(hashcode % self.backs.len() as u64) as usize];
-
-        // Create a new storage client instance
-        let storage = StorageClient {
-            addr: format!("http://{}", bk.clone()),
-            client: Arc::new(tokio::sync::Mutex::new(None)),
-        };
+        let prefix = bin_prefix(&name);
+
+        // Share the connection to the backend of every replica of this bin
+        let replicas = self
+            .replicas_of(&name)
+            .await
+            .into_iter()
+            .map(|i| self.clients[i].clone())
+            .collect();
         Ok(Box::new(Bin {
             _name: name,
             prefix,
-            storage,
+            replicas,
         }))
     }
+
+    async fn list_get_bins(&self, names: &[String], key: &str) -> TribResult<Vec<List>> {
+        // Group the bins by the backend holding their first replica, so each
+        // backend is asked for all of its lists in one call
+        let view = self.membership().await;
+        let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, name) in names.iter().enumerate() {
+            if let Some(back) = replica_indexes(&self.ring, name, self.replicas, &view).first() {
+                groups.entry(*back).or_default().push(i);
+            }
+        }
+        let fetches = groups.into_iter().map(|(back, bins)| {
+            let keys = bins
+                .iter()
+                .map(|i| qualify(&bin_prefix(&names[*i]), key))
+                .collect::<Vec<String>>();
+            async move { (bins, self.clients[back].list_get_many(&keys).await) }
+        });
+
+        let mut lists = vec![List(vec![]); names.len()];
+        for (bins, res) in join_all(fetches).await {
+            match res {
+                Ok(got) => bins.into_iter().zip(got).for_each(|(i, l)| lists[i] = l),
+                // Fall through to the other replicas one bin at a time
+                Err(_) => {
+                    for i in bins {
+                        lists[i] = self.bin(&names[i]).await?.list_get(key).await?;
+                    }
+                }
+            }
+        }
+        Ok(lists)
+    }
 }
 
 pub struct Bin {
     pub _name: String,
     pub prefix: String,
-    pub storage: StorageClient,
+    pub replicas: Vec<StorageClient>,
 }
 
-#[async_trait]
-impl KeyString for Bin {
-    async fn get(&self, key: &str) -> TribResult<Option<String>> {
-        let key_esc = colon::escape(key.to_string());
-        let mut key_escfq = self.prefix.clone();
-        key_escfq.push_str(&key_esc);
-        Ok(self.storage.get(&key_escfq).await?)
+/// Returns the prefix ("{name}::") translating the keys of the bin `name`
+/// into fully qualified ones in a form of "{name}::{key}"
+pub fn bin_prefix(name: &str) -> String {
+    let mut prefix = colon::escape(name.to_string());
+    prefix.push_str("::");
+    prefix
+}
+
+/// Translates a key into the escaped, fully qualified key stored on the
+/// backends, given the prefix of its bin
+fn qualify(prefix: &str, key: &str) -> String {
+    let key_esc = colon::escape(key.to_string());
+    let mut key_escfq = prefix.to_string();
+    key_escfq.push_str(&key_esc);
+    key_escfq
+}
+
+impl Bin {
+    /// Translates a key into the escaped, fully qualified key stored on
+    /// the backends.
+    fn qualify(&self, key: &str) -> String {
+        qualify(&self.prefix, key)
     }
 
-    async fn set(&self, kv: &KeyValue) -> TribResult<bool> {
-        let key_esc = colon::escape(&kv.key.to_string());
-        let mut key_escfq = self.prefix.clone();
-        key_escfq.push_str(&key_esc);
-        Ok(self
-            .storage
-            .set(&KeyValue {
-                key: key_escfq,
-                value: kv.value.clone(),
-            })
-            .await?)
+    /// Translates a pattern into one matching the fully qualified keys of
+    /// this bin.
+    fn qualify_pattern(&self, p: &Pattern) -> Pattern {
+        Pattern {
+            prefix: self.qualify(&p.prefix),
+            suffix: colon::escape(p.suffix.clone()),
+        }
     }
 
-    async fn keys(&self, p: &Pattern) -> TribResult<List> {
-        let prefix_esc = colon::escape(p.prefix.clone());
-        let mut prefix_escfq = self.prefix.clone();
-        prefix_escfq.push_str(&prefix_esc);
-        let suffix_esc = colon::escape(p.suffix.clone());
-        let List(keys_escfq) = self
-            .storage
-            .keys(&Pattern {
-                prefix: prefix_escfq,
-                suffix: suffix_esc,
-            })
-            .await?;
+    /// Strips this bin's prefix from fully qualified keys.
+    fn strip(&self, keys_escfq: Vec<String>) -> List {
         let mut keys: Vec<String> = Vec::new();
         keys_escfq.into_iter().for_each(|kescfq| {
             let key_esc = String::from(&kescfq[self.prefix.len()..]);
-            let key = colon::escape(key_esc);
+            let key = colon::unescape(key_esc);
             keys.push(key);
         });
-        Ok(List(keys))
+        List(keys)
+    }
+
+    /// Issues `op` against every replica of the bin at once. The call
+    /// succeeds as long as one replica accepted it, and the results of the
+    /// replicas that did are folded together with `merge`.
+    ///
+    /// A replica missing the write, even one the keeper still counts as
+    /// alive, is recorded as diverged, see [Bin::mark_diverged], and the
+    /// keeper catches it up from those that took the write.
+    async fn write_all<'a, T, F, Fut>(&'a self, op: F, merge: fn(T, T) -> T) -> TribResult<T>
+    where
+        F: Fn(&'a StorageClient) -> Fut,
+        Fut: Future<Output = TribResult<T>>,
+    {
+        let results = join_all(self.replicas.iter().map(op)).await;
+        let mut result: Option<T> = None;
+        let mut last_err = None;
+        let mut took = vec![];
+        for (i, res) in results.into_iter().enumerate() {
+            match res {
+                Ok(v) => {
+                    took.push(i);
+                    result = Some(match result {
+                        Some(prev) => merge(prev, v),
+                        None => v,
+                    });
+                }
+                Err(error) => {
+                    warn!("replica of bin {} missed a write: {}", self._name, error);
+                    last_err = Some(error);
+                }
+            }
+        }
+        if last_err.is_some() && !took.is_empty() {
+            self.mark_diverged(&took).await;
+        }
+        match (result, last_err) {
+            (Some(v), _) => Ok(v),
+            (None, Some(error)) => Err(error),
+            (None, None) => Err(Box::new(TribblerError::Unknown(format!(
+                "no replicas for bin {}",
+                self._name
+            )))),
+        }
+    }
+
+    /// Issues the conditional write `op` against the first replica that
+    /// answers, which decides for the whole bin. Once it has taken effect
+    /// there, the remaining replicas are brought along with `then`, all at
+    /// once. The write has happened by then, so a replica missing it does
+    /// not fail the call: the bin is recorded as diverged instead, see
+    /// [Bin::mark_diverged], and the keeper catches the replica up.
+    async fn write_first<'a, F, Fut, G, Gut, T>(&'a self, op: F, then: G) -> TribResult<bool>
+    where
+        F: Fn(&'a StorageClient) -> Fut,
+        Fut: Future<Output = TribResult<bool>>,
+        G: Fn(&'a StorageClient) -> Gut,
+        Gut: Future<Output = TribResult<T>>,
+    {
+        let mut last_err = None;
+        for (i, replica) in self.replicas.iter().enumerate() {
+            match op(replica).await {
+                Ok(true) => {
+                    let rest = join_all(self.replicas[i + 1..].iter().map(then)).await;
+                    let mut missed = i > 0;
+                    for error in rest.into_iter().filter_map(|res| res.err()) {
+                        warn!("replica of bin {} missed a write: {}", self._name, error);
+                        missed = true;
+                    }
+                    if missed {
+                        self.mark_diverged(&[i]).await;
+                    }
+                    return Ok(true);
+                }
+                Ok(false) => return Ok(false),
+                Err(error) => last_err = Some(error),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            Box::new(TribblerError::Unknown(format!(
+                "no replicas for bin {}",
+                self._name
+            )))
+        }))
+    }
+
+    /// Records on the replicas at the indexes `took`, which hold a write
+    /// the other replicas of the bin missed, that the bin has diverged.
+    /// The keeper copies the bin from them onto the others on its next
+    /// round, see [KEY_DIVERGED].
+    async fn mark_diverged(&self, took: &[usize]) {
+        let kv = KeyValue::new(KEY_DIVERGED, &self._name);
+        let marks = took.iter().map(|i| self.replicas[*i].list_append(&kv));
+        if join_all(marks).await.iter().all(|res| res.is_err()) {
+            warn!("bin {} diverged and could not be marked", self._name);
+        }
+    }
+
+    /// Issues `op` against the replicas of the bin in order, returning the
+    /// result of the first replica that answers.
+    async fn read_any<'a, T, F, Fut>(&'a self, op: F) -> TribResult<T>
+    where
+        F: Fn(&'a StorageClient) -> Fut,
+        Fut: Future<Output = TribResult<T>>,
+    {
+        let mut last_err = None;
+        for replica in self.replicas.iter() {
+            match op(replica).await {
+                Ok(v) => return Ok(v),
+                Err(error) => last_err = Some(error),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            Box::new(TribblerError::Unknown(format!(
+                "no replicas for bin {}",
+                self._name
+            )))
+        }))
+    }
+}
+
+#[async_trait]
+impl KeyString for Bin {
+    async fn get(&self, key: &str) -> TribResult<Option<String>> {
+        let key_escfq = self.qualify(key);
+        self.read_any(|s| s.get(&key_escfq)).await
+    }
+
+    async fn set(&self, kv: &KeyValue) -> TribResult<bool> {
+        let kv = KeyValue {
+            key: self.qualify(&kv.key),
+            value: kv.value.clone(),
+        };
+        self.write_all(|s| s.set(&kv), |a, b| a || b).await
+    }
+
+    async fn keys(&self, p: &Pattern) -> TribResult<List> {
+        let p = self.qualify_pattern(p);
+        let List(keys_escfq) = self.read_any(|s| s.keys(&p)).await?;
+        Ok(self.strip(keys_escfq))
+    }
+
+    async fn compare_and_set(&self, key: &str, expected: &str, value: &str) -> TribResult<bool> {
+        let kv = KeyValue {
+            key: self.qualify(key),
+            value: value.to_string(),
+        };
+        self.write_first(
+            |s| s.compare_and_set(&kv.key, expected, &kv.value),
+            |s| s.set(&kv),
+        )
+        .await
+    }
+
+    async fn get_many(&self, keys: &[String]) -> TribResult<Vec<Option<String>>> {
+        let keys_escfq = keys
+            .iter()
+            .map(|k| self.qualify(k))
+            .collect::<Vec<String>>();
+        self.read_any(|s| s.get_many(&keys_escfq)).await
     }
 }
 
 #[async_trait]
 impl KeyList for Bin {
     async fn list_get(&self, key: &str) -> TribResult<List> {
-        let key_esc = colon::escape(key.to_string());
-        let mut key_escfq = self.prefix.clone();
-        key_escfq.push_str(&key_esc);
-        Ok(self.storage.list_get(&key_escfq).await?)
+        let key_escfq = self.qualify(key);
+        self.read_any(|s| s.list_get(&key_escfq)).await
     }
 
     async fn list_append(&self, kv: &KeyValue) -> TribResult<bool> {
-        let key_esc = colon::escape(kv.key.to_string());
-        let mut key_escfq = self.prefix.clone();
-        key_escfq.push_str(&key_esc);
-        Ok(self
-            .storage
-            .list_append(&KeyValue {
-                key: key_escfq,
-                value: kv.value.clone(),
-            })
-            .await?)
+        let kv = KeyValue {
+            key: self.qualify(&kv.key),
+            value: kv.value.clone(),
+        };
+        self.write_all(|s| s.list_append(&kv), |a, b| a || b).await
     }
 
     async fn list_remove(&self, kv: &KeyValue) -> TribResult<u32> {
-        let key_esc = colon::escape(kv.key.to_string());
-        let mut key_escfq = self.prefix.clone();
-        key_escfq.push_str(&key_esc);
-        Ok(self
-            .storage
-            .list_remove(&KeyValue {
-                key: key_escfq,
-                value: kv.value.clone(),
-            })
-            .await?)
+        let kv = KeyValue {
+            key: self.qualify(&kv.key),
+            value: kv.value.clone(),
+        };
+        self.write_all(|s| s.list_remove(&kv), u32::max).await
     }
 
     async fn list_keys(&self, p: &Pattern) -> TribResult<List> {
-        let prefix_esc = colon::escape(p.prefix.clone());
-        let mut prefix_escfq = self.prefix.clone();
-        prefix_escfq.push_str(&prefix_esc);
-        let suffix_esc = colon::escape(p.suffix.clone());
-        let List(keys_fq) = self
-            .storage
-            .list_keys(&Pattern {
-                prefix: prefix_escfq,
-                suffix: suffix_esc,
+        let p = self.qualify_pattern(p);
+        let List(keys_escfq) = self.read_any(|s| s.list_keys(&p)).await?;
+        Ok(self.strip(keys_escfq))
+    }
+
+    async fn list_append_if(&self, kv: &KeyValue, cond: &AppendCondition) -> TribResult<bool> {
+        let kv = KeyValue {
+            key: self.qualify(&kv.key),
+            value: kv.value.clone(),
+        };
+        self.write_first(|s| s.list_append_if(&kv, cond), |s| s.list_append(&kv))
+            .await
+    }
+
+    async fn list_get_many(&self, keys: &[String]) -> TribResult<Vec<List>> {
+        let keys_escfq = keys
+            .iter()
+            .map(|k| self.qualify(k))
+            .collect::<Vec<String>>();
+        self.read_any(|s| s.list_get_many(&keys_escfq)).await
+    }
+
+    async fn list_append_many(&self, kvs: &[KeyValue]) -> TribResult<bool> {
+        let kvs = kvs
+            .iter()
+            .map(|kv| KeyValue {
+                key: self.qualify(&kv.key),
+                value: kv.value.clone(),
             })
-            .await?;
-        let mut keys: Vec<String> = Vec::new();
-        keys_fq.into_iter().for_each(|kescfq| {
-            let key_esc = String::from(&kescfq[self.prefix.len()..]);
-            let key = colon::escape(key_esc);
-            keys.push(key);
-        });
-        Ok(List(keys))
+            .collect::<Vec<KeyValue>>();
+        self.write_all(|s| s.list_append_many(&kvs), |a, b| a || b)
+            .await
     }
 }
 
 #[async_trait]
 impl Storage for Bin {
     async fn clock(&self, at_least: u64) -> TribResult<u64> {
-        Ok(self.storage.clock(at_least).await?)
+        // Carry the largest clock seen forward so every replica ends up at
+        // or beyond the value handed back to the caller.
+        let mut clock: Option<u64> = None;
+        let mut last_err = None;
+        for replica in self.replicas.iter() {
+            match replica
+                .clock(clock.map_or(at_least, |c| c.max(at_least)))
+                .await
+            {
+                Ok(v) => clock = Some(clock.map_or(v, |c| c.max(v))),
+                Err(error) => last_err = Some(error),
+            }
+        }
+        match (clock, last_err) {
+            (Some(v), _) => Ok(v),
+            (None, Some(error)) => Err(error),
+            (None, None) => Err(Box::new(TribblerError::Unknown(format!(
+                "no replicas for bin {}",
+                self._name
+            )))),
+        }
+    }
+
+    async fn watch(&self, p: &Pattern) -> TribResult<mpsc::Receiver<Change>> {
+        // Follow the first replica that answers. Replicas cannot be merged
+        // without double reporting, since two equal appends look alike, so
+        // a change that missed this replica (it failed the write, or it is
+        // still being caught up after a view change) is not reported. The
+        // stream ends with this replica, and the caller watches anew.
+        let p = self.qualify_pattern(p);
+        let mut changes = self.read_any(|s| s.watch(&p)).await?;
+        let prefix_len = self.prefix.len();
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let mut change = tokio::select! {
+                    _ = tx.closed() => break,
+                    change = changes.recv() => match change {
+                        Some(change) => change,
+                        None => break,
+                    },
+                };
+                change.key = colon::unescape(&change.key[prefix_len..]);
+                if tx.send(change).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
     }
 }
@@ -1,26 +1,69 @@
 use async_trait::async_trait;
+use futures_util::future::join_all;
+use log::warn;
 use serde::{self, Deserialize, Serialize};
 use std::{
     cmp::{min, Ordering},
-    collections::HashSet,
-    sync::Arc,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+    },
     time::SystemTime,
 };
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 
+use crate::ring::stable_hash;
 use tribbler::{
+    auth::{check_password, new_token, now, parse_token, Credential, SESSION_TTL},
     err::{TribResult, TribblerError},
-    storage::{BinStorage, KeyValue},
+    search::{rank, score, tokenize},
+    storage::{AppendCondition, BinStorage, ChangeKind, KeyValue, Pattern, WATCH_BUFFER},
     trib::{
-        is_valid_username, Server, Trib, MAX_FOLLOWING, MAX_TRIB_FETCH, MAX_TRIB_LEN, MIN_LIST_USER,
+        as_retrib, hashtags, normalize_tag, page_before, parse_trib_id, trib_id, Profile, Server,
+        Trib, UserArchive, UserStats, MAX_FOLLOWING, MAX_TRIB_FETCH, MIN_LIST_USER,
     },
+    validate::{check_profile, check_trib, confusable_skeleton, UsernamePolicy},
 };
 
 static BIN_USER_BASE: &str = "UserBase";
 static KEY_USERS: &str = "users";
+static KEY_DELETION: &str = "deletion";
+static KEY_SKELETONS: &str = "skeletons";
 static KEY_TRIBS: &str = "tribs";
 static KEY_FOLLOWS: &str = "follows";
+static KEY_FOLLOWERS: &str = "followers";
+static KEY_FOLLOWERS_INDEXED: &str = "followers_indexed";
+static KEY_BLOCKS: &str = "blocks";
+static KEY_MUTES: &str = "mutes";
+static KEY_HOME: &str = "home";
+static KEY_MENTIONS: &str = "mentions";
+static KEY_REPLIES: &str = "replies";
+static KEY_RETRIBS: &str = "retribs";
+static KEY_LIKES: &str = "likes";
+static KEY_LIKED: &str = "liked";
+static KEY_TAGGED: &str = "tagged";
+static KEY_CREDENTIAL: &str = "credential";
+static KEY_PROFILE: &str = "profile";
+
+/// Number of bins the search index is split across, by term
+const SEARCH_SHARDS: u64 = 16;
+
+/// Once a precomputed home list grows past this many tribs, it is trimmed
+/// down to the latest this many shown and, apart, the latest this many
+/// hidden ones, so a deleted trib leaves enough behind to fill the timeline
+const MAX_HOME_LEN: usize = 4 * MAX_TRIB_FETCH;
+
+/// Once the list of ids in a tag bin grows past this many, it is trimmed
+/// down to the ids of the latest this many tribs still tagged, so a deleted
+/// or edited trib leaves enough behind to fill the tag timeline
+const MAX_TAG_LEN: usize = 4 * MAX_TRIB_FETCH;
 
+/// An entry in a follow log. Block and mute logs are kept the same way,
+/// with `followed` telling whether `user` was blocked or muted rather than
+/// unblocked or unmuted. The followers index names its candidates with
+/// such entries too.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Follow {
     user: String,
@@ -28,6 +71,14 @@ struct Follow {
     timestamp: u64,
 }
 
+/// An entry in the retribs list of a user, recording that `user` retribbed
+/// the trib they posted at `clock`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Retrib {
+    user: String,
+    clock: u64,
+}
+
 /// A [Trib] type with extra augmented information for ordering
 #[derive(Debug, Clone)]
 struct SortableTrib(Arc<Trib>);
@@ -76,72 +127,585 @@ impl PartialEq for SortableTrib {
     }
 }
 
+/// Returns the entry naming `who` in a followers index
+fn follower_entry(who: &str) -> String {
+    serde_json::to_string(&Follow {
+        user: who.to_string(),
+        followed: true,
+        timestamp: 0,
+    })
+    .unwrap()
+}
+
+/// Replays a follow log into the set of users currently followed, or a
+/// block or mute log into the set of users currently blocked or muted
+fn followed_users(raw_follows: &[String]) -> HashSet<String> {
+    let mut following: HashSet<String> = HashSet::new();
+    for raw_fol in raw_follows.iter() {
+        let fol = match skip_corrupt(raw_fol, serde_json::from_str::<Follow>(raw_fol)) {
+            Some(fol) => fol,
+            None => continue,
+        };
+        if fol.followed {
+            following.insert(fol.user);
+        } else {
+            following.remove(&fol.user);
+        }
+    }
+    following
+}
+
+/// Whether `t` was posted or reposted by any of the `hidden` users, or
+/// reposts a trib of one of them. Holds for retribs both as stored and as
+/// rendered.
+fn is_hidden(t: &Trib, hidden: &HashSet<String>) -> bool {
+    let original = parse_trib_id(&t.retrib_of).map(|(author, _)| author);
+    hidden.contains(&t.user)
+        || hidden.contains(&t.retrib_by)
+        || original.is_some_and(|author| hidden.contains(author))
+}
+
+/// Drops the tribs [is_hidden] from the `hidden` users
+fn hide(tribs: Vec<Arc<Trib>>, hidden: &HashSet<String>) -> Vec<Arc<Trib>> {
+    tribs
+        .into_iter()
+        .filter(|t| !is_hidden(t, hidden))
+        .collect()
+}
+
+/// Returns the key a session is stored under in the bin of its user, given
+/// the digest of its token
+fn session_key(digest: &str) -> String {
+    format!("session::{}", digest)
+}
+
+/// Returns the key, in the bin of its author, of the list of ids of the
+/// replies to the trib posted at `clock`
+fn replies_key(clock: u64) -> String {
+    format!("{}::{}", KEY_REPLIES, clock)
+}
+
+/// Returns the key, in the bin of its author, of the list of users liking
+/// the trib posted at `clock`
+fn likes_key(clock: u64) -> String {
+    format!("{}::{}", KEY_LIKES, clock)
+}
+
+/// Returns the name of the bin holding the ids of the tribs tagged `tag`.
+/// No username starts with `#`, so it never clashes with a user's bin.
+fn tag_bin(tag: &str) -> String {
+    format!("#{}", tag)
+}
+
+/// Returns the name of the bin holding the search index entries for `term`.
+/// Each term is a list of the ids of the tribs containing it. No username
+/// starts with `%`, so it never clashes with a user's bin.
+fn search_bin(term: &str) -> String {
+    format!("%{}", stable_hash(term.as_bytes()) % SEARCH_SHARDS)
+}
+
+/// Parses a serialized trib. Tribs stored before they had ids get theirs
+/// filled in.
+fn parse_trib(raw: &str) -> TribResult<Trib> {
+    let mut trib = serde_json::from_str::<Trib>(raw)?;
+    if trib.id.is_empty() {
+        trib.id = trib_id(&trib.user, trib.clock);
+    }
+    Ok(trib)
+}
+
+/// Passes on a parsed entry of a stored list, or logs and drops one that
+/// is corrupt, so that a single bad entry does not fail the whole list
+fn skip_corrupt<T, E: Display>(raw: &str, parsed: Result<T, E>) -> Option<T> {
+    match parsed {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            warn!("skipping corrupt entry {:?}: {}", raw, e);
+            None
+        }
+    }
+}
+
+/// Parses a list of serialized tribs, skipping the corrupt ones
+fn parse_tribs(raw_tribs: &[String]) -> Vec<SortableTrib> {
+    raw_tribs
+        .iter()
+        .filter_map(|t| skip_corrupt(t, parse_trib(t)))
+        .map(|t| SortableTrib(Arc::new(t)))
+        .collect()
+}
+
+/// Returns the latest [MAX_TRIB_FETCH] of the sorted `timeline`
+fn latest(timeline: &[SortableTrib]) -> Vec<Arc<Trib>> {
+    let start = timeline.len().saturating_sub(MAX_TRIB_FETCH);
+    timeline[start..].iter().map(|st| st.0.clone()).collect()
+}
+
+/// Looks up tribs by id, leaving out the ones that do not exist (any
+/// more) and repeated ids. The tribs of each author are read once.
+async fn tribs_by_id(
+    bin_storage: &Arc<dyn BinStorage>,
+    ids: &[String],
+) -> TribResult<Vec<Arc<Trib>>> {
+    let mut authors = ids
+        .iter()
+        .filter_map(|id| parse_trib_id(id))
+        .map(|(user, _)| user.to_string())
+        .collect::<Vec<String>>();
+    authors.sort();
+    authors.dedup();
+    if authors.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut by_id = bin_storage
+        .list_get_bins(&authors, KEY_TRIBS)
+        .await?
+        .into_iter()
+        .flat_map(|l| l.0)
+        .filter_map(|t| skip_corrupt(&t, parse_trib(&t)))
+        .map(|trib| (trib.id.clone(), Arc::new(trib)))
+        .collect::<HashMap<String, Arc<Trib>>>();
+    Ok(ids.iter().filter_map(|id| by_id.remove(id)).collect())
+}
+
+/// Shows retribs among `tribs` as the tribs they repost, dropping those
+/// whose original has been deleted
+async fn render(
+    bin_storage: &Arc<dyn BinStorage>,
+    tribs: Vec<Arc<Trib>>,
+) -> TribResult<Vec<Arc<Trib>>> {
+    let ids = tribs
+        .iter()
+        .filter(|t| !t.retrib_of.is_empty())
+        .map(|t| t.retrib_of.clone())
+        .collect::<Vec<String>>();
+    let originals = tribs_by_id(bin_storage, &ids)
+        .await?
+        .into_iter()
+        .map(|t| (t.id.clone(), t))
+        .collect::<HashMap<String, Arc<Trib>>>();
+    Ok(tribs
+        .into_iter()
+        .filter_map(|t| match t.retrib_of.as_str() {
+            "" => Some(t),
+            id => originals
+                .get(id)
+                .map(|original| Arc::new(as_retrib(&t, original))),
+        })
+        .collect())
+}
+
+/// The users [Server::list_users] read last, and the last deletion of a
+/// user known when they were read
+#[derive(Default)]
+pub struct UsersCache {
+    users: Vec<String>,
+    deletion: Option<String>,
+}
+
 pub struct FrontServer {
-    pub bin_storage: Box<dyn BinStorage>,
-    pub users_cache: Mutex<Vec<String>>,
+    pub bin_storage: Arc<dyn BinStorage>,
+    pub users_cache: Mutex<UsersCache>,
+    /// Whether home timelines are built on write. A post is then copied
+    /// into the home list of every follower of its author, and [Server::home]
+    /// reads that one list instead of the tribs of everyone followed.
+    pub fanout: bool,
+    /// Which usernames are accepted on sign up
+    pub username_policy: UsernamePolicy,
+    /// Whether the followers index is known to be back-filled, see
+    /// [FrontServer::backfill_followers]
+    pub followers_indexed: AtomicBool,
 }
 
-#[async_trait]
-impl Server for FrontServer {
-    async fn sign_up(&self, user: &str) -> TribResult<()> {
-        if !is_valid_username(user) {
-            return Err(Box::new(TribblerError::InvalidUsername(user.to_string())));
+impl FrontServer {
+    /// Appends a [Follow] record to the follow log of `who`. The record is
+    /// only appended if no one else has appended to the log since it was
+    /// read and checked, so racing follows can't both pass the checks;
+    /// the loser reads the log again and starts over.
+    async fn log_follow(&self, who: &str, whom: &str, followed: bool) -> TribResult<()> {
+        // Index the follower first: a follow that then fails leaves an
+        // entry that readers check away, but never a follow missing one
+        if followed {
+            self.index_follower(who, whom).await?;
         }
-        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
-        let users = bin.list_get(KEY_USERS).await?.0;
-        if users.contains(&user.to_string()) {
-            return Err(Box::new(TribblerError::UsernameTaken(user.to_string())));
+        let bin = self.bin_storage.bin(who).await?;
+        loop {
+            let raw_follows = bin.list_get(KEY_FOLLOWS).await?.0;
+            let following = followed_users(&raw_follows);
+            if following.contains(whom) == followed {
+                return Err(Box::new(match followed {
+                    true => TribblerError::AlreadyFollowing(who.to_string(), whom.to_string()),
+                    false => TribblerError::NotFollowing(who.to_string(), whom.to_string()),
+                }));
+            }
+            if followed && following.len() >= MAX_FOLLOWING {
+                return Err(Box::new(TribblerError::FollowingTooMany));
+            }
+            let follow = serde_json::to_string(&Follow {
+                user: whom.to_string(),
+                followed,
+                timestamp: bin.clock(0).await?,
+            })
+            .unwrap();
+            let unchanged = AppendCondition {
+                absent: false,
+                max_len: Some(raw_follows.len() as u32 + 1),
+            };
+            if bin
+                .list_append_if(
+                    &KeyValue {
+                        key: KEY_FOLLOWS.to_string(),
+                        value: follow,
+                    },
+                    &unchanged,
+                )
+                .await?
+            {
+                break;
+            }
         }
-        if !bin
-            .list_append(&KeyValue {
-                key: KEY_USERS.to_string(),
-                value: user.to_string(),
+
+        // Keep the index to the followers there are. Noting the follower
+        // again once logged, and checking the log again once taken out,
+        // keeps a follow racing an unfollow in the index either way.
+        match followed {
+            true => self.index_follower(who, whom).await?,
+            false => self.unindex_follower(who, whom).await?,
+        }
+
+        // Tribs posted before the follow never got copied, so fill them in
+        if self.fanout && followed {
+            let raw_tribs = self
+                .bin_storage
+                .bin(whom)
+                .await?
+                .list_get(KEY_TRIBS)
+                .await?
+                .0;
+            let start = raw_tribs.len().saturating_sub(MAX_TRIB_FETCH);
+            let kvs = raw_tribs[start..]
+                .iter()
+                .map(|t| KeyValue {
+                    key: KEY_HOME.to_string(),
+                    value: t.clone(),
+                })
+                .collect::<Vec<KeyValue>>();
+            bin.list_append_many(&kvs).await?;
+        }
+        Ok(())
+    }
+
+    /// Notes in the reverse index in the bin of `whom` that `who` may be
+    /// following them. Each follower is noted once.
+    async fn index_follower(&self, who: &str, whom: &str) -> TribResult<()> {
+        let follower = follower_entry(who);
+        let absent = AppendCondition {
+            absent: true,
+            max_len: None,
+        };
+        self.bin_storage
+            .bin(whom)
+            .await?
+            .list_append_if(
+                &KeyValue {
+                    key: KEY_FOLLOWERS.to_string(),
+                    value: follower,
+                },
+                &absent,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Takes `who` out of the reverse index in the bin of `whom`, unless the
+    /// follow log of `who` says they follow `whom` after all
+    async fn unindex_follower(&self, who: &str, whom: &str) -> TribResult<()> {
+        self.bin_storage
+            .bin(whom)
+            .await?
+            .list_remove(&KeyValue {
+                key: KEY_FOLLOWERS.to_string(),
+                value: follower_entry(who),
             })
+            .await?;
+        let log = self
+            .bin_storage
+            .bin(who)
             .await?
-        {
-            return Err(Box::new(TribblerError::Unknown(user.to_string())));
+            .list_get(KEY_FOLLOWS)
+            .await?;
+        if followed_users(&log.0).contains(whom) {
+            self.index_follower(who, whom).await?;
         }
         Ok(())
     }
 
-    async fn list_users(&self) -> TribResult<Vec<String>> {
-        let cache = self.users_cache.lock().await;
-        match cache.len().cmp(&MIN_LIST_USER) {
-            Ordering::Less => {
-                std::mem::drop(cache);
-                let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
-                let mut users = bin.list_get(KEY_USERS).await?.0;
-                users.sort();
-                let mut cache = self.users_cache.lock().await;
-                *cache = users[..min(MIN_LIST_USER, users.len())].to_vec();
-                return Ok(cache.clone());
+    /// Fills the reverse index in from the follow logs of everyone, once,
+    /// since follows logged before it was kept never made it in. Racing
+    /// back-fills only note the same followers again. Once it is known to
+    /// be done, nothing is read again.
+    async fn backfill_followers(&self) -> TribResult<()> {
+        if self.followers_indexed.load(atomic::Ordering::Relaxed) {
+            return Ok(());
+        }
+        let base = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if base.get(KEY_FOLLOWERS_INDEXED).await?.is_some() {
+            self.followers_indexed
+                .store(true, atomic::Ordering::Relaxed);
+            return Ok(());
+        }
+        let users = base.list_get(KEY_USERS).await?.0;
+        let logs = self.bin_storage.list_get_bins(&users, KEY_FOLLOWS).await?;
+        for (who, log) in users.iter().zip(logs) {
+            for whom in followed_users(&log.0) {
+                self.index_follower(who, &whom).await?;
             }
-            _ => Ok(cache.clone()),
         }
+        base.set(&KeyValue {
+            key: KEY_FOLLOWERS_INDEXED.to_string(),
+            value: "true".to_string(),
+        })
+        .await?;
+        self.followers_indexed
+            .store(true, atomic::Ordering::Relaxed);
+        Ok(())
     }
 
-    async fn post(&self, who: &str, post: &str, clock: u64) -> TribResult<()> {
-        if post.len() > MAX_TRIB_LEN {
-            return Err(Box::new(TribblerError::TribTooLong));
+    /// Returns the users following `who`. The reverse index kept next to
+    /// their follow logs only names the candidates; the follow log of each
+    /// candidate has the final say.
+    async fn followers_of(&self, who: &str) -> TribResult<Vec<String>> {
+        self.backfill_followers().await?;
+        let bin = self.bin_storage.bin(who).await?;
+        let mut candidates = bin
+            .list_get(KEY_FOLLOWERS)
+            .await?
+            .0
+            .iter()
+            .filter_map(|raw| skip_corrupt(raw, serde_json::from_str::<Follow>(raw)))
+            .map(|fol| fol.user)
+            .collect::<Vec<String>>();
+        candidates.sort();
+        candidates.dedup();
+        let logs = self
+            .bin_storage
+            .list_get_bins(&candidates, KEY_FOLLOWS)
+            .await?;
+        Ok(candidates
+            .into_iter()
+            .zip(logs)
+            .filter(|(_, log)| followed_users(&log.0).contains(who))
+            .map(|(candidate, _)| candidate)
+            .collect())
+    }
+
+    /// Marks `whom` as blocked or muted, or not, in the log `key` of `who`,
+    /// racing appends being settled as in [FrontServer::log_follow].
+    /// Nothing is appended if the log says so already.
+    async fn log_toggle(&self, who: &str, key: &str, whom: &str, on: bool) -> TribResult<()> {
+        let bin = self.bin_storage.bin(who).await?;
+        loop {
+            let raw_log = bin.list_get(key).await?.0;
+            if followed_users(&raw_log).contains(whom) == on {
+                return Ok(());
+            }
+            let record = serde_json::to_string(&Follow {
+                user: whom.to_string(),
+                followed: on,
+                timestamp: bin.clock(0).await?,
+            })
+            .unwrap();
+            let unchanged = AppendCondition {
+                absent: false,
+                max_len: Some(raw_log.len() as u32 + 1),
+            };
+            if bin
+                .list_append_if(
+                    &KeyValue {
+                        key: key.to_string(),
+                        value: record,
+                    },
+                    &unchanged,
+                )
+                .await?
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns an error unless `who` and `whom` are two different users who
+    /// have signed up
+    async fn check_pair(&self, who: &str, whom: &str) -> TribResult<()> {
+        if who == whom {
+            return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
         }
         let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
-        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
+        let users = bin.list_get(KEY_USERS).await?.0;
+        for name in [who, whom] {
+            if !users.contains(&name.to_string()) {
+                return Err(Box::new(TribblerError::UserDoesNotExist(name.to_string())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an error if either of `who` and `whom` has blocked the other
+    async fn check_unblocked(&self, who: &str, whom: &str) -> TribResult<()> {
+        for (blocker, blocked) in [(whom, who), (who, whom)] {
+            let bin = self.bin_storage.bin(blocker).await?;
+            if followed_users(&bin.list_get(KEY_BLOCKS).await?.0).contains(blocked) {
+                return Err(Box::new(TribblerError::Blocked(
+                    blocker.to_string(),
+                    blocked.to_string(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Unfollows `whom` on behalf of `who`, if `who` follows them
+    async fn drop_follow(&self, who: &str, whom: &str) -> TribResult<()> {
+        if !self.is_following(who, whom).await? {
+            return Ok(());
+        }
+        match self.log_follow(who, whom, false).await {
+            // unfollowed by someone else meanwhile
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<TribblerError>(),
+                    Some(TribblerError::NotFollowing(_, _))
+                ) =>
+            {
+                Ok(())
+            }
+            r => r,
+        }
+    }
+
+    /// Returns the users `user` has muted or blocked, whose tribs are kept
+    /// off their home timeline
+    async fn hidden_users(&self, user: &str) -> TribResult<HashSet<String>> {
+        let bin = self.bin_storage.bin(user).await?;
+        let lists = bin
+            .list_get_many(&[KEY_MUTES.to_string(), KEY_BLOCKS.to_string()])
+            .await?;
+        let mut hidden = followed_users(&lists[0].0);
+        hidden.extend(followed_users(&lists[1].0));
+        Ok(hidden)
+    }
+
+    /// Copies a freshly posted trib into the home lists of its author and
+    /// of everyone following them
+    async fn fan_out(&self, who: &str, post: &str) -> TribResult<()> {
+        let mut readers = self.followers_of(who).await?;
+        readers.push(who.to_string());
+        let appends = readers.iter().map(|reader| async move {
+            self.bin_storage
+                .bin(reader)
+                .await?
+                .list_append(&KeyValue {
+                    key: KEY_HOME.to_string(),
+                    value: post.to_string(),
+                })
+                .await
+        });
+        for res in join_all(appends).await {
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Removes every (key, value) of each bin in `removals` from the list
+    /// under that key. The bins are gone through at once, and so are the
+    /// removals from each.
+    async fn remove_all(
+        &self,
+        removals: &HashMap<String, HashSet<(String, String)>>,
+    ) -> TribResult<()> {
+        let bins = removals.iter().map(|(name, kvs)| async move {
+            let bin = self.bin_storage.bin(name).await?;
+            let removes = kvs.iter().map(|(key, value)| {
+                let bin = &bin;
+                async move {
+                    bin.list_remove(&KeyValue {
+                        key: key.clone(),
+                        value: value.clone(),
+                    })
+                    .await
+                }
+            });
+            join_all(removes)
+                .await
+                .into_iter()
+                .collect::<TribResult<Vec<u32>>>()
+        });
+        for res in join_all(bins).await {
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Takes a trib copied by [FrontServer::fan_out] back out of the home
+    /// lists of its author and of everyone following them
+    async fn fan_out_remove(&self, who: &str, post: &str) -> TribResult<()> {
+        let mut readers = self.followers_of(who).await?;
+        readers.push(who.to_string());
+        let removes = readers.iter().map(|reader| async move {
+            self.bin_storage
+                .bin(reader)
+                .await?
+                .list_remove(&KeyValue {
+                    key: KEY_HOME.to_string(),
+                    value: post.to_string(),
+                })
+                .await
+        });
+        for res in join_all(removes).await {
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Posts a trib, in reply to the one whose id is `parent` unless it is
+    /// empty, and indexes it under its parent and the users it mentions
+    async fn post_trib(&self, who: &str, post: &str, parent: &str, clock: u64) -> TribResult<()> {
+        check_trib(post)?;
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        let users = bin.list_get(KEY_USERS).await?.0;
+        if !users.contains(&who.to_string()) {
             return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
         }
+        let parent_ref = match parent {
+            "" => None,
+            _ => match parse_trib_id(parent) {
+                Some((user, clock)) => {
+                    self.find_trib(user, clock).await?;
+                    Some((user, clock))
+                }
+                None => return Err(Box::new(TribblerError::InvalidTribId(parent.to_string()))),
+            },
+        };
         let bin = self.bin_storage.bin(who).await?;
+        let clock = bin.clock(clock).await?;
+        let post_msg = post.to_string();
         let post = serde_json::to_string(&Trib {
             user: who.to_string(),
             message: post.to_string(),
-            clock: bin.clock(clock).await?,
+            clock,
             time: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)?
                 .as_secs(),
+            id: trib_id(who, clock),
+            parent: parent.to_string(),
+            retrib_of: "".to_string(),
+            retrib_by: "".to_string(),
         })
         .unwrap();
         if !bin
             .list_append(&KeyValue {
                 key: KEY_TRIBS.to_string(),
-                value: post,
+                value: post.clone(),
             })
             .await?
         {
@@ -150,183 +714,1389 @@ impl Server for FrontServer {
                 who
             ))));
         }
-        Ok(())
-    }
-
-    async fn tribs(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
-        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
-        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
-            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        if self.fanout {
+            self.fan_out(who, &post).await?;
         }
-        let bin = self.bin_storage.bin(user).await?;
-        let raw_tribs = bin.list_get(KEY_TRIBS).await?.0;
-        let mut stribs = raw_tribs
-            .iter()
-            .map(|t| SortableTrib(Arc::new(serde_json::from_str::<Trib>(t).unwrap())))
-            .collect::<Vec<SortableTrib>>();
-        stribs.sort();
-
-        let mut gc = false;
-        let ntrib = stribs.len();
-        let start = match ntrib.cmp(&MAX_TRIB_FETCH) {
-            Ordering::Greater => {
-                gc = true;
-                ntrib - MAX_TRIB_FETCH
-            }
-            _ => 0,
-        };
-        let tribs = stribs[start..]
-            .to_vec()
-            .iter()
-            .map(|st| st.0.clone())
-            .collect::<Vec<Arc<Trib>>>();
-
-        // Remove old tribbles if needed, i.e, there are > 100 tribbles
-        if gc {
-            tribs[..start].to_vec().iter().for_each(|t| {
-                match bin.list_remove(&KeyValue {
-                    key: KEY_TRIBS.to_string(),
-                    value: serde_json::to_string(t).unwrap(),
-                }) {
-                    _ => {}
-                }
-            })
+        if let Some((user, parent_clock)) = parent_ref {
+            self.bin_storage
+                .bin(user)
+                .await?
+                .list_append(&KeyValue {
+                    key: replies_key(parent_clock),
+                    value: trib_id(who, clock),
+                })
+                .await?;
         }
-        Ok(tribs)
+        self.retag(&trib_id(who, clock), "", &post_msg).await?;
+        self.reindex(&trib_id(who, clock), "", &post_msg).await?;
+        self.index_mentions(who, &trib_id(who, clock), "", &post_msg, &users)
+            .await
     }
 
-    async fn follow(&self, who: &str, whom: &str) -> TribResult<()> {
-        if self.is_following(who, whom).await? {
-            return Err(Box::new(TribblerError::AlreadyFollowing(
-                who.to_string(),
-                whom.to_string(),
-            )));
+    /// Moves the trib whose id is `id` from the search index entries of the
+    /// terms of `old_msg` to those of `new_msg`. Additions to the same
+    /// shard go out in one call.
+    async fn reindex(&self, id: &str, old_msg: &str, new_msg: &str) -> TribResult<()> {
+        let old_terms = tokenize(old_msg);
+        let new_terms = tokenize(new_msg);
+        for term in old_terms.iter().filter(|t| !new_terms.contains(t)) {
+            self.bin_storage
+                .bin(&search_bin(term))
+                .await?
+                .list_remove(&KeyValue {
+                    key: term.to_string(),
+                    value: id.to_string(),
+                })
+                .await?;
         }
-        if self.following(who).await?.len() == MAX_FOLLOWING {
-            return Err(Box::new(TribblerError::FollowingTooMany));
+        let mut shards: HashMap<String, Vec<KeyValue>> = HashMap::new();
+        for term in new_terms.iter().filter(|t| !old_terms.contains(t)) {
+            shards.entry(search_bin(term)).or_default().push(KeyValue {
+                key: term.to_string(),
+                value: id.to_string(),
+            });
         }
-        let bin = self.bin_storage.bin(who).await?;
-        let follow = serde_json::to_string(&Follow {
-            user: whom.to_string(),
-            followed: true,
-            timestamp: bin.clock(0).await?,
-        })
-        .unwrap();
-        if !bin
-            .list_append(&KeyValue {
-                key: KEY_FOLLOWS.to_string(),
-                value: follow,
-            })
-            .await?
-        {
-            return Err(Box::new(TribblerError::Unknown(format!(
-                "failed to follow user: {}",
-                whom
-            ))));
+        let appends = shards.iter().map(|(shard, kvs)| async move {
+            self.bin_storage
+                .bin(shard)
+                .await?
+                .list_append_many(kvs)
+                .await
+        });
+        for res in join_all(appends).await {
+            res?;
         }
         Ok(())
     }
 
-    async fn unfollow(&self, who: &str, whom: &str) -> TribResult<()> {
-        if !self.is_following(who, whom).await? {
-            return Err(Box::new(TribblerError::AlreadyFollowing(
-                who.to_string(),
-                whom.to_string(),
-            )));
+    /// Moves the trib whose id is `id` from the tag bins of the hashtags of
+    /// `old_msg` to those of `new_msg`
+    async fn retag(&self, id: &str, old_msg: &str, new_msg: &str) -> TribResult<()> {
+        let old_tags = hashtags(old_msg);
+        let new_tags = hashtags(new_msg);
+        for tag in old_tags.iter().filter(|t| !new_tags.contains(t)) {
+            self.bin_storage
+                .bin(&tag_bin(tag))
+                .await?
+                .list_remove(&KeyValue {
+                    key: KEY_TAGGED.to_string(),
+                    value: id.to_string(),
+                })
+                .await?;
         }
-        let bin = self.bin_storage.bin(who).await?;
-        let follow = serde_json::to_string(&Follow {
-            user: whom.to_string(),
-            followed: false,
-            timestamp: bin.clock(0).await?,
-        })
-        .unwrap();
-        if !bin
-            .list_append(&KeyValue {
-                key: KEY_FOLLOWS.to_string(),
-                value: follow,
-            })
-            .await?
-        {
-            return Err(Box::new(TribblerError::Unknown(format!(
-                "failed to unfollow user: {}",
-                whom
-            ))));
+        for tag in new_tags.iter().filter(|t| !old_tags.contains(t)) {
+            self.bin_storage
+                .bin(&tag_bin(tag))
+                .await?
+                .list_append(&KeyValue {
+                    key: KEY_TAGGED.to_string(),
+                    value: id.to_string(),
+                })
+                .await?;
         }
         Ok(())
     }
 
-    async fn is_following(&self, who: &str, whom: &str) -> TribResult<bool> {
-        if who == whom {
-            return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
+    /// Moves the trib of `who` whose id is `id` from the mentions lists of
+    /// the signed up users `old_msg` mentions to those `new_msg` mentions
+    async fn index_mentions(
+        &self,
+        who: &str,
+        id: &str,
+        old_msg: &str,
+        new_msg: &str,
+        users: &[String],
+    ) -> TribResult<()> {
+        let mentioned = |msg: &str| {
+            self.username_policy
+                .mentioned_users(msg)
+                .into_iter()
+                .filter(|m| m != who && users.contains(m))
+                .collect::<HashSet<String>>()
+        };
+        let old_mentioned = mentioned(old_msg);
+        let new_mentioned = mentioned(new_msg);
+        let kv = KeyValue {
+            key: KEY_MENTIONS.to_string(),
+            value: id.to_string(),
+        };
+        let absent = AppendCondition {
+            absent: true,
+            max_len: None,
+        };
+        let removes = old_mentioned
+            .difference(&new_mentioned)
+            .map(|m| async { self.bin_storage.bin(m).await?.list_remove(&kv).await });
+        for res in join_all(removes).await {
+            res?;
         }
+        let appends = new_mentioned.difference(&old_mentioned).map(|m| async {
+            self.bin_storage
+                .bin(m)
+                .await?
+                .list_append_if(&kv, &absent)
+                .await
+        });
+        for res in join_all(appends).await {
+            res?;
+        }
+        Ok(())
+    }
+
+    /// Returns the trib `who` posted at `clock`, serialized as it is stored
+    /// in their tribs list
+    async fn find_trib(&self, who: &str, clock: u64) -> TribResult<String> {
         let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
-        let sgdup_users = bin.list_get(KEY_USERS).await?.0;
-        if !sgdup_users.contains(&who.to_string()) || !sgdup_users.contains(&whom.to_string()) {
+        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
             return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
         }
         let bin = self.bin_storage.bin(who).await?;
-        let raw_follows = bin.list_get(KEY_FOLLOWS).await?.0;
-        let mut result = false;
-        for raw_fol in raw_follows.iter().rev() {
-            let fol = serde_json::from_str::<Follow>(raw_fol).unwrap();
-            if fol.user.eq(whom) {
-                result = fol.followed;
-                break;
-            }
+        match bin
+            .list_get(KEY_TRIBS)
+            .await?
+            .0
+            .into_iter()
+            .find(|t| skip_corrupt(t, parse_trib(t)).is_some_and(|t| t.clock == clock))
+        {
+            Some(t) => Ok(t),
+            None => Err(Box::new(TribblerError::TribDoesNotExist(
+                who.to_string(),
+                clock,
+            ))),
         }
-        Ok(result)
     }
 
-    async fn following(&self, who: &str) -> TribResult<Vec<String>> {
+    /// Returns the author and clock of the trib a like by `who` of the one
+    /// whose id is `id` goes to: that trib, or the original when it is a
+    /// retrib
+    async fn like_target(&self, who: &str, id: &str) -> TribResult<(String, u64)> {
         let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
         if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
             return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
         }
-        let bin = self.bin_storage.bin(who).await?;
-        let raw_follows = bin.list_get(KEY_FOLLOWS).await?.0;
-        let mut following: HashSet<String> = HashSet::new();
-        for raw_fol in raw_follows.iter() {
-            let fol = serde_json::from_str::<Follow>(raw_fol).unwrap();
-            if fol.followed {
-                following.insert(fol.user);
-            } else {
-                following.remove(&fol.user);
+        let (user, clock) = match parse_trib_id(id) {
+            Some(x) => x,
+            None => return Err(Box::new(TribblerError::InvalidTribId(id.to_string()))),
+        };
+        let trib = parse_trib(&self.find_trib(user, clock).await?)?;
+        match parse_trib_id(&trib.retrib_of) {
+            Some((author, original_clock)) => {
+                self.find_trib(author, original_clock).await?;
+                Ok((author.to_string(), original_clock))
             }
+            None => Ok((user.to_string(), clock)),
         }
-        Ok(following.into_iter().collect())
     }
 
-    async fn home(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
-        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
-        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
-            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
-        }
-        let mut timeline: Vec<Arc<Trib>> = Vec::new();
-        let mut tribs = self.tribs(user).await?;
-        // consider whether should do it directly
-        timeline.append(&mut tribs);
-        let following = self.following(user).await?;
-        for fol in following {
-            let mut tribs = self.tribs(&fol).await?;
-            timeline.append(&mut tribs);
-        }
-        let mut timeline = timeline
-            .to_vec()
+    /// Builds the home timeline of `user` by merging the tribs of everyone
+    /// followed
+    async fn home_on_read(
+        &self,
+        user: &str,
+        hidden: &HashSet<String>,
+    ) -> TribResult<Vec<Arc<Trib>>> {
+        // Fetch the user's own tribs and those of everyone followed, with
+        // one call per backend
+        let mut authors = self.following(user).await?;
+        authors.retain(|a| !hidden.contains(a));
+        authors.push(user.to_string());
+        let mut timeline = self
+            .bin_storage
+            .list_get_bins(&authors, KEY_TRIBS)
+            .await?
             .into_iter()
-            .map(|t| SortableTrib(t))
+            .flat_map(|l| l.0)
+            .filter_map(|t| skip_corrupt(&t, parse_trib(&t)))
+            .filter(|t| !is_hidden(t, hidden))
+            .map(|t| SortableTrib(Arc::new(t)))
             .collect::<Vec<SortableTrib>>();
         timeline.sort();
+        Ok(latest(&timeline))
+    }
 
-        let ntrib = timeline.len();
-        let start = match ntrib.cmp(&MAX_TRIB_FETCH) {
-            Ordering::Greater => ntrib - MAX_TRIB_FETCH,
-            _ => 0,
-        };
-        Ok(timeline[start..]
-            .to_vec()
+    /// Reads the home timeline of `user` precomputed by [FrontServer::fan_out]
+    async fn home_on_write(
+        &self,
+        user: &str,
+        hidden: &HashSet<String>,
+    ) -> TribResult<Vec<Arc<Trib>>> {
+        let mut authors = self
+            .following(user)
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        authors.insert(user.to_string());
+        let bin = self.bin_storage.bin(user).await?;
+        let raw_home = bin.list_get(KEY_HOME).await?.0;
+        // Tribs of users unfollowed since are left in the list, and a trib
+        // can be copied twice when a follow races a post
+        let mut timeline = parse_tribs(&raw_home)
+            .into_iter()
+            .filter(|st| authors.contains(&st.0.user))
+            .collect::<Vec<SortableTrib>>();
+        timeline.sort();
+        timeline.dedup();
+        // Tribs of hidden users stay in the list, to show again once
+        // unmuted, but are counted apart so they never push out shown ones
+        let (hidden_tribs, shown): (Vec<SortableTrib>, Vec<SortableTrib>) = timeline
             .into_iter()
-            .map(|st| st.0.clone())
-            .collect::<Vec<Arc<Trib>>>())
+            .partition(|st| is_hidden(&st.0, hidden));
+
+        if raw_home.len() > MAX_HOME_LEN {
+            let keep = [&shown, &hidden_tribs]
+                .iter()
+                .flat_map(|part| part[part.len().saturating_sub(MAX_HOME_LEN)..].iter())
+                .map(|st| st.0.id.as_str())
+                .collect::<HashSet<&str>>();
+            let stale = raw_home
+                .iter()
+                .filter(|t| {
+                    skip_corrupt(t, parse_trib(t)).is_none_or(|t| !keep.contains(t.id.as_str()))
+                })
+                .map(|t| (KEY_HOME.to_string(), t.clone()))
+                .collect::<HashSet<(String, String)>>();
+            if !stale.is_empty() {
+                self.remove_all(&HashMap::from([(user.to_string(), stale)]))
+                    .await?;
+            }
+        }
+        Ok(latest(&shown))
+    }
+}
+
+#[async_trait]
+impl Server for FrontServer {
+    async fn sign_up(&self, user: &str) -> TribResult<()> {
+        self.username_policy.check(user)?;
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        // Check and register in one step, so racing sign-ups can't both win
+        let absent = AppendCondition {
+            absent: true,
+            max_len: None,
+        };
+        if !bin
+            .list_append_if(
+                &KeyValue {
+                    key: KEY_USERS.to_string(),
+                    value: user.to_string(),
+                },
+                &absent,
+            )
+            .await?
+        {
+            return Err(Box::new(TribblerError::UsernameTaken(user.to_string())));
+        }
+        // Claim the look of the name as well, in the same way. Names taken
+        // before confusables were rejected have no claim to compare with.
+        if self.username_policy.reject_confusables
+            && !bin
+                .list_append_if(
+                    &KeyValue {
+                        key: KEY_SKELETONS.to_string(),
+                        value: confusable_skeleton(user),
+                    },
+                    &absent,
+                )
+                .await?
+        {
+            bin.list_remove(&KeyValue {
+                key: KEY_USERS.to_string(),
+                value: user.to_string(),
+            })
+            .await?;
+            return Err(Box::new(TribblerError::ConfusableUsername(
+                user.to_string(),
+            )));
+        }
+        let profile = serde_json::to_string(&Profile {
+            joined: now(),
+            ..Profile::default()
+        })?;
+        self.bin_storage
+            .bin(user)
+            .await?
+            .set(&KeyValue {
+                key: KEY_PROFILE.to_string(),
+                value: profile,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn sign_up_with_password(&self, user: &str, password: &str) -> TribResult<()> {
+        check_password(password)?;
+        self.username_policy.check(user)?;
+        let credential = serde_json::to_string(&Credential::new(password))?;
+        // Accounts signed up without a password have no credential to race
+        // for, so refuse taken names before writing anything
+        let users = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if users
+            .list_get(KEY_USERS)
+            .await?
+            .0
+            .contains(&user.to_string())
+        {
+            return Err(Box::new(TribblerError::UsernameTaken(user.to_string())));
+        }
+        // Set before the name is registered, so the account is never listed
+        // without a password. Only set where unset, so it can never replace
+        // the password of an account created by someone else.
+        let bin = self.bin_storage.bin(user).await?;
+        if !bin.compare_and_set(KEY_CREDENTIAL, "", &credential).await? {
+            return Err(Box::new(TribblerError::UsernameTaken(user.to_string())));
+        }
+        if let Err(error) = self.sign_up(user).await {
+            bin.compare_and_set(KEY_CREDENTIAL, &credential, "").await?;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    async fn login(&self, user: &str, password: &str) -> TribResult<String> {
+        if !self.username_policy.is_valid(user) {
+            return Err(Box::new(TribblerError::InvalidCredentials));
+        }
+        let bin = self.bin_storage.bin(user).await?;
+        let verified = match bin.get(KEY_CREDENTIAL).await? {
+            Some(raw) => serde_json::from_str::<Credential>(&raw)?.verify(password),
+            None => {
+                Credential::dummy().verify(password);
+                false
+            }
+        };
+        if !verified {
+            return Err(Box::new(TribblerError::InvalidCredentials));
+        }
+        let token = new_token(user);
+        let (_, digest) = parse_token(&token).unwrap();
+        bin.set(&KeyValue {
+            key: session_key(&digest),
+            value: (now() + SESSION_TTL).to_string(),
+        })
+        .await?;
+        Ok(token)
+    }
+
+    async fn logout(&self, token: &str) -> TribResult<()> {
+        if let Some((user, digest)) = parse_token(token) {
+            if self.username_policy.is_valid(user) {
+                let bin = self.bin_storage.bin(user).await?;
+                bin.set(&KeyValue {
+                    key: session_key(&digest),
+                    value: "".to_string(),
+                })
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn session_user(&self, token: &str) -> TribResult<String> {
+        let (user, digest) = match parse_token(token) {
+            Some((user, digest)) if self.username_policy.is_valid(user) => (user, digest),
+            _ => return Err(Box::new(TribblerError::InvalidSession)),
+        };
+        let bin = self.bin_storage.bin(user).await?;
+        let expiry = match bin.get(&session_key(&digest)).await? {
+            Some(raw) => raw.parse::<u64>().unwrap_or(0),
+            None => return Err(Box::new(TribblerError::InvalidSession)),
+        };
+        if expiry <= now() {
+            self.logout(token).await?;
+            return Err(Box::new(TribblerError::InvalidSession));
+        }
+        Ok(user.to_string())
+    }
+
+    async fn list_users(&self) -> TribResult<Vec<String>> {
+        // Users deleted through any front end may be among the cached ones
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        let deletion = bin.get(KEY_DELETION).await?;
+        let cache = self.users_cache.lock().await;
+        if cache.users.len() >= MIN_LIST_USER && cache.deletion == deletion {
+            return Ok(cache.users.clone());
+        }
+        std::mem::drop(cache);
+        let mut users = bin.list_get(KEY_USERS).await?.0;
+        users.sort();
+        let mut cache = self.users_cache.lock().await;
+        *cache = UsersCache {
+            users: users[..min(MIN_LIST_USER, users.len())].to_vec(),
+            deletion,
+        };
+        Ok(cache.users.clone())
+    }
+
+    async fn post(&self, who: &str, post: &str, clock: u64) -> TribResult<()> {
+        self.post_trib(who, post, "", clock).await
+    }
+
+    async fn reply(&self, who: &str, parent: &str, post: &str, clock: u64) -> TribResult<()> {
+        self.post_trib(who, post, parent, clock).await
+    }
+
+    async fn retrib(&self, who: &str, id: &str, clock: u64) -> TribResult<()> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let (user, original_clock) = match parse_trib_id(id) {
+            Some(x) => x,
+            None => return Err(Box::new(TribblerError::InvalidTribId(id.to_string()))),
+        };
+        let mut original = parse_trib(&self.find_trib(user, original_clock).await?)?;
+        if let Some((user, original_clock)) = parse_trib_id(&original.retrib_of) {
+            original = parse_trib(&self.find_trib(user, original_clock).await?)?;
+        }
+
+        // The retribs list of the author doubles as the guard against
+        // retribbing twice
+        let guard = KeyValue {
+            key: KEY_RETRIBS.to_string(),
+            value: serde_json::to_string(&Retrib {
+                user: who.to_string(),
+                clock: original.clock,
+            })
+            .unwrap(),
+        };
+        let author = self.bin_storage.bin(&original.user).await?;
+        let absent = AppendCondition {
+            absent: true,
+            max_len: None,
+        };
+        if !author.list_append_if(&guard, &absent).await? {
+            return Err(Box::new(TribblerError::AlreadyRetribbed(
+                who.to_string(),
+                original.id,
+            )));
+        }
+
+        let bin = self.bin_storage.bin(who).await?;
+        let posted: TribResult<String> = async {
+            let clock = bin.clock(clock).await?;
+            let post = serde_json::to_string(&Trib {
+                user: who.to_string(),
+                message: "".to_string(),
+                clock,
+                time: SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_secs(),
+                id: trib_id(who, clock),
+                parent: "".to_string(),
+                retrib_of: original.id,
+                retrib_by: "".to_string(),
+            })
+            .unwrap();
+            bin.list_append(&KeyValue {
+                key: KEY_TRIBS.to_string(),
+                value: post.clone(),
+            })
+            .await?;
+            Ok(post)
+        }
+        .await;
+        // Without the retrib, the guard would keep `who` from trying again
+        let post = match posted {
+            Ok(post) => post,
+            Err(e) => {
+                author.list_remove(&guard).await?;
+                return Err(e);
+            }
+        };
+        if self.fanout {
+            self.fan_out(who, &post).await?;
+        }
+        Ok(())
+    }
+
+    async fn like(&self, who: &str, id: &str) -> TribResult<()> {
+        let (author, clock) = self.like_target(who, id).await?;
+        // Kept on both sides: the likers of a trib in the bin of its author
+        // to be counted, and the likes of a user in their own bin, so that
+        // they can be taken back when the user is deleted. Appending only
+        // when absent makes liking twice count once.
+        let absent = AppendCondition {
+            absent: true,
+            max_len: None,
+        };
+        self.bin_storage
+            .bin(&author)
+            .await?
+            .list_append_if(
+                &KeyValue {
+                    key: likes_key(clock),
+                    value: who.to_string(),
+                },
+                &absent,
+            )
+            .await?;
+        self.bin_storage
+            .bin(who)
+            .await?
+            .list_append_if(
+                &KeyValue {
+                    key: KEY_LIKED.to_string(),
+                    value: trib_id(&author, clock),
+                },
+                &absent,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn unlike(&self, who: &str, id: &str) -> TribResult<()> {
+        let (author, clock) = self.like_target(who, id).await?;
+        self.bin_storage
+            .bin(&author)
+            .await?
+            .list_remove(&KeyValue {
+                key: likes_key(clock),
+                value: who.to_string(),
+            })
+            .await?;
+        self.bin_storage
+            .bin(who)
+            .await?
+            .list_remove(&KeyValue {
+                key: KEY_LIKED.to_string(),
+                value: trib_id(&author, clock),
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn like_counts(&self, trib_ids: &[String]) -> TribResult<Vec<u64>> {
+        // Read the likers of the tribs of each author at once
+        let mut by_author: HashMap<&str, Vec<(usize, u64)>> = HashMap::new();
+        for (i, id) in trib_ids.iter().enumerate() {
+            if let Some((author, clock)) = parse_trib_id(id) {
+                by_author.entry(author).or_default().push((i, clock));
+            }
+        }
+        let reads = by_author.iter().map(|(author, tribs)| async move {
+            let keys = tribs
+                .iter()
+                .map(|(_, clock)| likes_key(*clock))
+                .collect::<Vec<String>>();
+            self.bin_storage
+                .bin(author)
+                .await?
+                .list_get_many(&keys)
+                .await
+        });
+        let mut counts = vec![0; trib_ids.len()];
+        for (tribs, res) in by_author.values().zip(join_all(reads).await) {
+            for ((i, _), likers) in tribs.iter().zip(res?) {
+                counts[*i] = likers.0.len() as u64;
+            }
+        }
+        Ok(counts)
+    }
+
+    async fn liked(&self, who: &str, trib_ids: &[String]) -> TribResult<Vec<bool>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let liked = self
+            .bin_storage
+            .bin(who)
+            .await?
+            .list_get(KEY_LIKED)
+            .await?
+            .0
+            .into_iter()
+            .collect::<HashSet<String>>();
+        Ok(trib_ids.iter().map(|id| liked.contains(id)).collect())
+    }
+
+    async fn mentions(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        let bin = self.bin_storage.bin(user).await?;
+        let ids = bin.list_get(KEY_MENTIONS).await?.0;
+        let mut timeline = tribs_by_id(&self.bin_storage, &ids)
+            .await?
+            .into_iter()
+            .map(SortableTrib)
+            .collect::<Vec<SortableTrib>>();
+        timeline.sort();
+        Ok(latest(&timeline))
+    }
+
+    async fn thread(&self, trib_id: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let (user, clock) = match parse_trib_id(trib_id) {
+            Some(x) => x,
+            None => return Err(Box::new(TribblerError::InvalidTribId(trib_id.to_string()))),
+        };
+        let mut root = Arc::new(parse_trib(&self.find_trib(user, clock).await?)?);
+        while let Some(parent) = tribs_by_id(&self.bin_storage, &[root.parent.clone()])
+            .await?
+            .pop()
+        {
+            root = parent;
+        }
+
+        // Walk down the reply lists one level at a time
+        let mut replies: Vec<SortableTrib> = vec![];
+        let mut level = vec![root.clone()];
+        while !level.is_empty() {
+            let reads = level.iter().map(|t| async move {
+                let bin = self.bin_storage.bin(&t.user).await?;
+                bin.list_get(&replies_key(t.clock)).await
+            });
+            let mut ids = vec![];
+            for res in join_all(reads).await {
+                ids.append(&mut res?.0);
+            }
+            level = tribs_by_id(&self.bin_storage, &ids).await?;
+            replies.extend(level.iter().cloned().map(SortableTrib));
+        }
+        replies.sort();
+        let mut thread = vec![root];
+        thread.extend(replies.into_iter().map(|st| st.0));
+        Ok(thread)
+    }
+
+    async fn tag_timeline(&self, tag: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let tag = normalize_tag(tag);
+        let bin = self.bin_storage.bin(&tag_bin(&tag)).await?;
+        let ids = bin.list_get(KEY_TAGGED).await?.0;
+        let mut timeline = tribs_by_id(&self.bin_storage, &ids)
+            .await?
+            .into_iter()
+            .filter(|t| hashtags(&t.message).contains(&tag))
+            .map(SortableTrib)
+            .collect::<Vec<SortableTrib>>();
+        timeline.sort();
+
+        if ids.len() > MAX_TAG_LEN {
+            let keep = timeline[timeline.len().saturating_sub(MAX_TAG_LEN)..]
+                .iter()
+                .map(|st| st.0.id.as_str())
+                .collect::<HashSet<&str>>();
+            let stale = ids
+                .into_iter()
+                .filter(|id| !keep.contains(id.as_str()))
+                .map(|id| (KEY_TAGGED.to_string(), id))
+                .collect::<HashSet<(String, String)>>();
+            if !stale.is_empty() {
+                self.remove_all(&HashMap::from([(tag_bin(&tag), stale)]))
+                    .await?;
+            }
+        }
+        Ok(latest(&timeline))
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> TribResult<Vec<Arc<Trib>>> {
+        let terms = tokenize(query);
+        let limit = min(limit, MAX_TRIB_FETCH);
+        if terms.is_empty() || limit == 0 {
+            return Ok(vec![]);
+        }
+
+        // Read the entries of all the terms, with one call per shard
+        let mut shards: HashMap<String, Vec<String>> = HashMap::new();
+        for term in terms.iter() {
+            shards
+                .entry(search_bin(term))
+                .or_default()
+                .push(term.clone());
+        }
+        let reads = shards.iter().map(|(shard, keys)| async move {
+            self.bin_storage.bin(shard).await?.list_get_many(keys).await
+        });
+        let mut hits: HashMap<String, usize> = HashMap::new();
+        for res in join_all(reads).await {
+            for list in res? {
+                for id in list.0.into_iter().collect::<HashSet<String>>() {
+                    *hits.entry(id).or_default() += 1;
+                }
+            }
+        }
+
+        // Most hits first, then the latest, as the ids carry the clock
+        let mut candidates = hits
+            .into_iter()
+            .filter_map(|(id, n)| {
+                let clock = parse_trib_id(&id)?.1;
+                Some((n, clock, id))
+            })
+            .collect::<Vec<(usize, u64, String)>>();
+        candidates.sort_by(|a, b| b.cmp(a));
+        let mut found: Vec<Arc<Trib>> = vec![];
+        for batch in candidates.chunks(limit) {
+            let ids = batch
+                .iter()
+                .map(|(_, _, id)| id.clone())
+                .collect::<Vec<String>>();
+            // Edits can drop terms without taking the id off their entries
+            found.extend(
+                tribs_by_id(&self.bin_storage, &ids)
+                    .await?
+                    .into_iter()
+                    .filter(|t| score(&terms, &t.message) > 0),
+            );
+            if found.len() >= limit {
+                break;
+            }
+        }
+        Ok(rank(&terms, found, limit))
+    }
+
+    async fn tribs(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        let bin = self.bin_storage.bin(user).await?;
+        let raw_tribs = bin.list_get(KEY_TRIBS).await?.0;
+        let mut stribs = parse_tribs(&raw_tribs);
+        stribs.sort();
+        render(&self.bin_storage, latest(&stribs)).await
+    }
+
+    async fn tribs_before(
+        &self,
+        user: &str,
+        cursor: &str,
+        limit: usize,
+    ) -> TribResult<Vec<Arc<Trib>>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        let bin = self.bin_storage.bin(user).await?;
+        let tribs = bin
+            .list_get(KEY_TRIBS)
+            .await?
+            .0
+            .iter()
+            .filter_map(|t| skip_corrupt(t, parse_trib(t)))
+            .map(Arc::new)
+            .collect::<Vec<Arc<Trib>>>();
+        render(&self.bin_storage, page_before(tribs, cursor, limit)?).await
+    }
+
+    async fn delete_trib(&self, who: &str, clock: u64) -> TribResult<()> {
+        let raw = self.find_trib(who, clock).await?;
+        let bin = self.bin_storage.bin(who).await?;
+        bin.list_remove(&KeyValue {
+            key: KEY_TRIBS.to_string(),
+            value: raw.clone(),
+        })
+        .await?;
+        if self.fanout {
+            self.fan_out_remove(who, &raw).await?;
+        }
+        let trib = parse_trib(&raw)?;
+        self.retag(&trib.id, &trib.message, "").await?;
+        self.reindex(&trib.id, &trib.message, "").await?;
+        let users = self
+            .bin_storage
+            .bin(BIN_USER_BASE)
+            .await?
+            .list_get(KEY_USERS)
+            .await?
+            .0;
+        self.index_mentions(who, &trib.id, &trib.message, "", &users)
+            .await?;
+
+        // Take it out of the replies of its parent, and leave the replies to
+        // it without a parent to be listed under
+        if let Some((author, parent_clock)) = parse_trib_id(&trib.parent) {
+            self.bin_storage
+                .bin(author)
+                .await?
+                .list_remove(&KeyValue {
+                    key: replies_key(parent_clock),
+                    value: trib.id.clone(),
+                })
+                .await?;
+        }
+        let key = replies_key(clock);
+        for reply in bin.list_get(&key).await?.0 {
+            bin.list_remove(&KeyValue {
+                key: key.clone(),
+                value: reply,
+            })
+            .await?;
+        }
+
+        // Neither a deleted retrib nor retribs of a deleted trib count any
+        // more
+        if let Some((author, original_clock)) = parse_trib_id(&trib.retrib_of) {
+            let entry = serde_json::to_string(&Retrib {
+                user: who.to_string(),
+                clock: original_clock,
+            })
+            .unwrap();
+            self.bin_storage
+                .bin(author)
+                .await?
+                .list_remove(&KeyValue {
+                    key: KEY_RETRIBS.to_string(),
+                    value: entry,
+                })
+                .await?;
+        }
+        for entry in bin.list_get(KEY_RETRIBS).await?.0 {
+            let retrib = skip_corrupt(&entry, serde_json::from_str::<Retrib>(&entry));
+            if retrib.is_some_and(|r| r.clock == clock) {
+                bin.list_remove(&KeyValue {
+                    key: KEY_RETRIBS.to_string(),
+                    value: entry,
+                })
+                .await?;
+            }
+        }
+
+        // Nor do its likes, on either side
+        let key = likes_key(clock);
+        for liker in bin.list_get(&key).await?.0 {
+            bin.list_remove(&KeyValue {
+                key: key.clone(),
+                value: liker.clone(),
+            })
+            .await?;
+            self.bin_storage
+                .bin(&liker)
+                .await?
+                .list_remove(&KeyValue {
+                    key: KEY_LIKED.to_string(),
+                    value: trib.id.clone(),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn edit_trib(&self, who: &str, clock: u64, post: &str) -> TribResult<()> {
+        check_trib(post)?;
+        let raw = self.find_trib(who, clock).await?;
+        let trib = parse_trib(&raw)?;
+        // A retrib carries the original's message, which isn't theirs to edit
+        if !trib.retrib_of.is_empty() {
+            return Err(Box::new(TribblerError::RetribNotEditable(
+                who.to_string(),
+                clock,
+            )));
+        }
+        let edited = serde_json::to_string(&Trib {
+            message: post.to_string(),
+            ..trib
+        })
+        .unwrap();
+        if edited == raw {
+            return Ok(());
+        }
+        // Add the new version before dropping the old one, so that the trib
+        // is never missing from a timeline
+        let bin = self.bin_storage.bin(who).await?;
+        bin.list_append(&KeyValue {
+            key: KEY_TRIBS.to_string(),
+            value: edited.clone(),
+        })
+        .await?;
+        bin.list_remove(&KeyValue {
+            key: KEY_TRIBS.to_string(),
+            value: raw.clone(),
+        })
+        .await?;
+        if self.fanout {
+            self.fan_out(who, &edited).await?;
+            self.fan_out_remove(who, &raw).await?;
+        }
+        let users = self
+            .bin_storage
+            .bin(BIN_USER_BASE)
+            .await?
+            .list_get(KEY_USERS)
+            .await?
+            .0;
+        let old_msg = parse_trib(&raw)?.message;
+        self.retag(&trib_id(who, clock), &old_msg, post).await?;
+        self.reindex(&trib_id(who, clock), &old_msg, post).await?;
+        self.index_mentions(who, &trib_id(who, clock), &old_msg, post, &users)
+            .await
+    }
+
+    async fn follow(&self, who: &str, whom: &str) -> TribResult<()> {
+        if self.is_following(who, whom).await? {
+            return Err(Box::new(TribblerError::AlreadyFollowing(
+                who.to_string(),
+                whom.to_string(),
+            )));
+        }
+        self.check_unblocked(who, whom).await?;
+        self.log_follow(who, whom, true).await?;
+        // A block racing this follow may have missed the edge just added
+        if let Err(e) = self.check_unblocked(who, whom).await {
+            self.drop_follow(who, whom).await?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn unfollow(&self, who: &str, whom: &str) -> TribResult<()> {
+        if !self.is_following(who, whom).await? {
+            return Err(Box::new(TribblerError::NotFollowing(
+                who.to_string(),
+                whom.to_string(),
+            )));
+        }
+        self.log_follow(who, whom, false).await
+    }
+
+    async fn is_following(&self, who: &str, whom: &str) -> TribResult<bool> {
+        if who == whom {
+            return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
+        }
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        let sgdup_users = bin.list_get(KEY_USERS).await?.0;
+        if !sgdup_users.contains(&who.to_string()) || !sgdup_users.contains(&whom.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let bin = self.bin_storage.bin(who).await?;
+        let raw_follows = bin.list_get(KEY_FOLLOWS).await?.0;
+        let mut result = false;
+        for raw_fol in raw_follows.iter().rev() {
+            let fol = match skip_corrupt(raw_fol, serde_json::from_str::<Follow>(raw_fol)) {
+                Some(fol) => fol,
+                None => continue,
+            };
+            if fol.user.eq(whom) {
+                result = fol.followed;
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    async fn following(&self, who: &str) -> TribResult<Vec<String>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let bin = self.bin_storage.bin(who).await?;
+        let raw_follows = bin.list_get(KEY_FOLLOWS).await?.0;
+        Ok(followed_users(&raw_follows).into_iter().collect())
+    }
+
+    async fn followers(&self, who: &str) -> TribResult<Vec<String>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        self.followers_of(who).await
+    }
+
+    async fn block(&self, who: &str, whom: &str) -> TribResult<()> {
+        self.check_pair(who, whom).await?;
+        self.log_toggle(who, KEY_BLOCKS, whom, true).await?;
+        self.drop_follow(whom, who).await?;
+        self.drop_follow(who, whom).await
+    }
+
+    async fn unblock(&self, who: &str, whom: &str) -> TribResult<()> {
+        self.check_pair(who, whom).await?;
+        self.log_toggle(who, KEY_BLOCKS, whom, false).await
+    }
+
+    async fn blocked(&self, who: &str) -> TribResult<Vec<String>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let bin = self.bin_storage.bin(who).await?;
+        let mut blocked = followed_users(&bin.list_get(KEY_BLOCKS).await?.0)
+            .into_iter()
+            .collect::<Vec<String>>();
+        blocked.sort();
+        Ok(blocked)
+    }
+
+    async fn mute(&self, who: &str, whom: &str) -> TribResult<()> {
+        self.check_pair(who, whom).await?;
+        self.log_toggle(who, KEY_MUTES, whom, true).await
+    }
+
+    async fn unmute(&self, who: &str, whom: &str) -> TribResult<()> {
+        self.check_pair(who, whom).await?;
+        self.log_toggle(who, KEY_MUTES, whom, false).await
+    }
+
+    async fn muted(&self, who: &str) -> TribResult<Vec<String>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&who.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let bin = self.bin_storage.bin(who).await?;
+        let mut muted = followed_users(&bin.list_get(KEY_MUTES).await?.0)
+            .into_iter()
+            .collect::<Vec<String>>();
+        muted.sort();
+        Ok(muted)
+    }
+
+    async fn user_stats(&self, user: &str) -> TribResult<UserStats> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        // The follow log, the tribs and the retribs all live in the user's own bin
+        let followers = self.followers_of(user).await?.len();
+        let bin = self.bin_storage.bin(user).await?;
+        let lists = bin
+            .list_get_many(&[
+                KEY_FOLLOWS.to_string(),
+                KEY_TRIBS.to_string(),
+                KEY_RETRIBS.to_string(),
+            ])
+            .await?;
+        Ok(UserStats {
+            followers,
+            following: followed_users(&lists[0].0).len(),
+            tribs: lists[1].0.len(),
+            retribbed: lists[2].0.len(),
+        })
+    }
+
+    async fn get_profile(&self, user: &str) -> TribResult<Profile> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        // Users who signed up before profiles were kept have none stored
+        let bin = self.bin_storage.bin(user).await?;
+        match bin.get(KEY_PROFILE).await? {
+            Some(raw) => Ok(serde_json::from_str::<Profile>(&raw)?),
+            None => Ok(Profile::default()),
+        }
+    }
+
+    async fn update_profile(&self, user: &str, profile: &Profile) -> TribResult<()> {
+        check_profile(profile)?;
+        let joined = self.get_profile(user).await?.joined;
+        let profile = serde_json::to_string(&Profile {
+            joined,
+            ..profile.clone()
+        })?;
+        let bin = self.bin_storage.bin(user).await?;
+        bin.set(&KeyValue {
+            key: KEY_PROFILE.to_string(),
+            value: profile,
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_user(&self, user: &str) -> TribResult<()> {
+        let users = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !users
+            .list_get(KEY_USERS)
+            .await?
+            .0
+            .contains(&user.to_string())
+        {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        // Take the tribs out of the tag and search indexes, the mentions and
+        // reply lists and the home lists they were copied into, as
+        // [Server::delete_trib] does, and take back the likes and retribs of
+        // the tribs of others. Every list is read once, and what is left in
+        // the bin of the user goes with the purge below.
+        let everyone = users.list_get(KEY_USERS).await?.0;
+        let bin = self.bin_storage.bin(user).await?;
+        let lists = bin
+            .list_get_many(&[KEY_TRIBS.to_string(), KEY_LIKED.to_string()])
+            .await?;
+        let tribs = lists[0]
+            .0
+            .iter()
+            .filter_map(|raw| skip_corrupt(raw, parse_trib(raw)).map(|t| (raw, t)))
+            .collect::<Vec<(&String, Trib)>>();
+        let likes = tribs
+            .iter()
+            .map(|(_, t)| likes_key(t.clock))
+            .collect::<Vec<String>>();
+        let likers = bin.list_get_many(&likes).await?;
+        let readers = match self.fanout {
+            true => self.followers_of(user).await?,
+            false => vec![],
+        };
+        let mut removals: HashMap<String, HashSet<(String, String)>> = HashMap::new();
+        let mut remove = |name: &str, key: &str, value: &str| {
+            if name != user {
+                removals
+                    .entry(name.to_string())
+                    .or_default()
+                    .insert((key.to_string(), value.to_string()));
+            }
+        };
+        for ((raw, trib), likers) in tribs.iter().zip(likers) {
+            for reader in readers.iter() {
+                remove(reader, KEY_HOME, raw);
+            }
+            for tag in hashtags(&trib.message) {
+                remove(&tag_bin(&tag), KEY_TAGGED, &trib.id);
+            }
+            for term in tokenize(&trib.message) {
+                remove(&search_bin(&term), &term, &trib.id);
+            }
+            for m in self.username_policy.mentioned_users(&trib.message) {
+                if everyone.contains(&m) {
+                    remove(&m, KEY_MENTIONS, &trib.id);
+                }
+            }
+            if let Some((author, parent_clock)) = parse_trib_id(&trib.parent) {
+                remove(author, &replies_key(parent_clock), &trib.id);
+            }
+            if let Some((author, original_clock)) = parse_trib_id(&trib.retrib_of) {
+                let entry = serde_json::to_string(&Retrib {
+                    user: user.to_string(),
+                    clock: original_clock,
+                })
+                .unwrap();
+                remove(author, KEY_RETRIBS, &entry);
+            }
+            for liker in likers.0 {
+                remove(&liker, KEY_LIKED, &trib.id);
+            }
+        }
+        for id in lists[1].0.iter() {
+            if let Some((author, clock)) = parse_trib_id(id) {
+                remove(author, &likes_key(clock), user);
+            }
+        }
+        self.remove_all(&removals).await?;
+
+        // Unblock and unmute them in the logs of everyone else, so that no
+        // one signing up with the name again starts out hidden
+        for key in [KEY_BLOCKS, KEY_MUTES] {
+            let logs = self.bin_storage.list_get_bins(&everyone, key).await?;
+            for (other, log) in everyone.iter().zip(logs) {
+                if followed_users(&log.0).contains(user) {
+                    self.log_toggle(other, key, user, false).await?;
+                }
+            }
+        }
+
+        // Unfollow in the logs of the followers, so that no one signing up
+        // with the name again inherits them. The entries of the user in the
+        // followers indexes of others are checked away once the follow log
+        // is purged below.
+        for follower in self.followers_of(user).await? {
+            self.drop_follow(&follower, user).await?;
+        }
+
+        // Purge the bin before giving up the name, so that no one signing
+        // up with it again finds anything left
+        let all = Pattern {
+            prefix: "".to_string(),
+            suffix: "".to_string(),
+        };
+        let clears = bin.keys(&all).await?.0.into_iter().map(|key| {
+            let bin = &bin;
+            async move {
+                bin.set(&KeyValue {
+                    key,
+                    value: "".to_string(),
+                })
+                .await
+            }
+        });
+        for res in join_all(clears).await {
+            res?;
+        }
+        let list_keys = bin.list_keys(&all).await?.0;
+        let lists = bin.list_get_many(&list_keys).await?;
+        let purge = list_keys
+            .into_iter()
+            .zip(lists)
+            .flat_map(|(key, list)| list.0.into_iter().map(move |value| (key.clone(), value)))
+            .collect::<HashSet<(String, String)>>();
+        self.remove_all(&HashMap::from([(user.to_string(), purge)]))
+            .await?;
+
+        users
+            .list_remove(&KeyValue {
+                key: KEY_USERS.to_string(),
+                value: user.to_string(),
+            })
+            .await?;
+        if self.username_policy.reject_confusables {
+            users
+                .list_remove(&KeyValue {
+                    key: KEY_SKELETONS.to_string(),
+                    value: confusable_skeleton(user),
+                })
+                .await?;
+        }
+        users
+            .set(&KeyValue {
+                key: KEY_DELETION.to_string(),
+                value: users.clock(0).await?.to_string(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn export_user(&self, user: &str) -> TribResult<UserArchive> {
+        let profile = self.get_profile(user).await?;
+        let bin = self.bin_storage.bin(user).await?;
+        let lists = bin
+            .list_get_many(&[
+                KEY_TRIBS.to_string(),
+                KEY_FOLLOWS.to_string(),
+                KEY_LIKED.to_string(),
+            ])
+            .await?;
+        let mut stribs = parse_tribs(&lists[0].0);
+        stribs.sort();
+        let mut following = followed_users(&lists[1].0).into_iter().collect::<Vec<_>>();
+        following.sort();
+        let mut followers = self.followers_of(user).await?;
+        followers.sort();
+        let mut liked = lists[2].0.clone();
+        liked.sort();
+        Ok(UserArchive {
+            user: user.to_string(),
+            profile,
+            tribs: stribs.into_iter().map(|st| st.0).collect(),
+            following,
+            followers,
+            liked,
+        })
+    }
+
+    async fn home(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        let hidden = self.hidden_users(user).await?;
+        let tribs = if self.fanout {
+            self.home_on_write(user, &hidden).await?
+        } else {
+            self.home_on_read(user, &hidden).await?
+        };
+        render(&self.bin_storage, tribs).await
+    }
+
+    async fn home_before(
+        &self,
+        user: &str,
+        cursor: &str,
+        limit: usize,
+    ) -> TribResult<Vec<Arc<Trib>>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        // Precomputed home lists are trimmed, so go back to the tribs of
+        // everyone followed even in fan-out mode
+        let hidden = self.hidden_users(user).await?;
+        let mut authors = self.following(user).await?;
+        authors.retain(|a| !hidden.contains(a));
+        authors.push(user.to_string());
+        let tribs = self
+            .bin_storage
+            .list_get_bins(&authors, KEY_TRIBS)
+            .await?
+            .into_iter()
+            .flat_map(|l| l.0)
+            .filter_map(|t| skip_corrupt(&t, parse_trib(&t)))
+            .filter(|t| !is_hidden(t, &hidden))
+            .map(Arc::new)
+            .collect::<Vec<Arc<Trib>>>();
+        render(&self.bin_storage, page_before(tribs, cursor, limit)?).await
+    }
+
+    async fn watch_home(&self, user: &str) -> TribResult<mpsc::Receiver<Arc<Trib>>> {
+        let bin = self.bin_storage.bin(BIN_USER_BASE).await?;
+        if !bin.list_get(KEY_USERS).await?.0.contains(&user.to_string()) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        // Watch the lists the timeline is read from, and the follow, mute
+        // and block logs, since changing those changes what it shows
+        let hidden = self.hidden_users(user).await?;
+        let mut watches = vec![
+            (user.to_string(), KEY_FOLLOWS),
+            (user.to_string(), KEY_MUTES),
+            (user.to_string(), KEY_BLOCKS),
+        ];
+        if self.fanout {
+            watches.push((user.to_string(), KEY_HOME));
+        } else {
+            watches.push((user.to_string(), KEY_TRIBS));
+            for whom in self.following(user).await? {
+                if !hidden.contains(&whom) {
+                    watches.push((whom, KEY_TRIBS));
+                }
+            }
+        }
+
+        // Each watch sends the tribs appended to its list, then none once
+        // the timeline has to be read again
+        let (raw_tx, mut raw_rx) = mpsc::channel::<Option<Trib>>(WATCH_BUFFER);
+        for (name, key) in watches {
+            let p = Pattern {
+                prefix: key.to_string(),
+                suffix: key.to_string(),
+            };
+            let mut changes = self.bin_storage.bin(&name).await?.watch(&p).await?;
+            let raw_tx = raw_tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let change = tokio::select! {
+                        _ = raw_tx.closed() => return,
+                        change = changes.recv() => change,
+                    };
+                    let trib = match change {
+                        Some(c) if c.key != key || c.kind != ChangeKind::ListAppend => continue,
+                        Some(c) if key == KEY_TRIBS || key == KEY_HOME => {
+                            match skip_corrupt(&c.value, parse_trib(&c.value)) {
+                                Some(trib) => Some(trib),
+                                None => continue,
+                            }
+                        }
+                        _ => None,
+                    };
+                    let last = trib.is_none();
+                    if raw_tx.send(trib).await.is_err() || last {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(raw_tx);
+
+        // Retribs are sent as the tribs they repost
+        let bin_storage = self.bin_storage.clone();
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let trib = tokio::select! {
+                    _ = tx.closed() => return,
+                    trib = raw_rx.recv() => match trib {
+                        Some(Some(trib)) => trib,
+                        _ => return,
+                    },
+                };
+                let tribs = match render(&bin_storage, vec![Arc::new(trib)]).await {
+                    Ok(tribs) => hide(tribs, &hidden),
+                    Err(_) => return,
+                };
+                for t in tribs {
+                    if tx.send(t).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(rx)
     }
 }
@@ -0,0 +1,648 @@
+use async_trait::async_trait;
+use log::{info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use tonic::transport::{Channel, Endpoint};
+
+use tribbler::{
+    err::{TribResult, TribblerError},
+    storage::{KeyList, KeyString, KeyValue, Pattern, Storage},
+};
+
+use crate::binstorage::{
+    bin_prefix, replica_indexes, split_key, Membership, KEY_DIVERGED, KEY_MEMBERSHIP,
+    MEMBERSHIP_TTL,
+};
+use crate::keeper::{
+    keeper_client::KeeperClient, keeper_server::Keeper as KeeperRpc, Heartbeat as RpcHeartbeat,
+};
+use crate::kvstore::client::StorageClient;
+use crate::ring::Ring;
+
+/// How often keepers send heartbeats to each other
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a keeper may go unheard before the others consider it dead
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_millis(1500);
+/// How often the primary keeper runs a round of [Keeper::tick]
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// How many times [Keeper::migrate] copies the bins over while writes keep
+/// landing on them, before it publishes the new view regardless
+const MAX_COPY_PASSES: usize = 5;
+/// How many times [Keeper::publish] retries a backend whose view changed
+/// between reading and writing it
+const MAX_PUBLISH_TRIES: usize = 3;
+
+/// Tracks which backends are alive, keeps their clocks in sync and moves
+/// bin data around whenever the set of live backends changes.
+pub struct Keeper {
+    backs: Vec<String>,
+    ring: Ring,
+    replicas: usize,
+    storages: Vec<StorageClient>,
+    view: Membership,
+    max_timestamp: u64,
+    /// the term this keeper holds as primary, see [Membership::term]
+    term: u64,
+}
+
+impl Keeper {
+    pub fn new(backs: Vec<String>, replicas: usize) -> TribResult<Keeper> {
+        let storages = backs
+            .iter()
+            .map(|back| StorageClient::new(&format!("http://{}", back)))
+            .collect::<TribResult<Vec<StorageClient>>>()?;
+        Ok(Keeper {
+            ring: Ring::new(&backs),
+            backs,
+            replicas,
+            storages,
+            view: Membership::default(),
+            max_timestamp: 0,
+            term: 0,
+        })
+    }
+
+    /// The view last published to the backends
+    pub fn view(&self) -> &Membership {
+        &self.view
+    }
+
+    /// Calls [Storage::clock] on every backend, pushing all of them up to
+    /// the largest clock seen so far. Returns which backends answered.
+    async fn probe(&mut self) -> Vec<bool> {
+        let mut alive = vec![false; self.backs.len()];
+        for (i, stor) in self.storages.iter().enumerate() {
+            if let Ok(clock) = stor.clock(self.max_timestamp).await {
+                alive[i] = true;
+                if clock > self.max_timestamp {
+                    self.max_timestamp = clock;
+                }
+            }
+        }
+        alive
+    }
+
+    /// Adopts the newest view already published on the live backends, e.g.
+    /// by a previous keeper, or else takes the backends alive right now as
+    /// the starting view. Starts a term newer than that of every view seen,
+    /// and publishes the result under it, which fences off the previous
+    /// primary should it still be running.
+    pub async fn init(&mut self) -> TribResult<()> {
+        let alive = self.probe().await;
+        let mut view: Option<Membership> = None;
+        for (i, stor) in self.storages.iter().enumerate() {
+            if !alive[i] {
+                continue;
+            }
+            if let Ok(Some(raw)) = stor.get(KEY_MEMBERSHIP).await {
+                if let Ok(v) = serde_json::from_str::<Membership>(&raw) {
+                    if !matches!(&view, Some(cur) if !v.is_newer_than(cur)) {
+                        view = Some(v);
+                    }
+                }
+            }
+        }
+        self.term = view.as_ref().map_or(0, |v| v.term) + 1;
+        match view {
+            Some(v) if v.alive.len() == self.backs.len() => {
+                self.view = Membership {
+                    term: self.term,
+                    ..v
+                };
+                self.publish().await?;
+                // Reconcile whatever changed while no keeper was watching
+                self.tick_with(alive).await
+            }
+            _ => {
+                self.view = Membership {
+                    version: 1,
+                    term: self.term,
+                    alive,
+                };
+                self.publish().await
+            }
+        }
+    }
+
+    /// Runs one round of the keeper: syncs clocks and, if backends crashed
+    /// or came back since the last round, migrates data and publishes a new
+    /// view. Then catches up the replicas that missed writes, see
+    /// [Keeper::repair].
+    ///
+    /// Returns [TribblerError::StaleTerm] when another keeper has taken
+    /// over since this one did, in which case this one must step down.
+    pub async fn tick(&mut self) -> TribResult<()> {
+        self.check_term().await?;
+        let alive = self.probe().await;
+        self.tick_with(alive).await?;
+        self.repair().await
+    }
+
+    async fn tick_with(&mut self, alive: Vec<bool>) -> TribResult<()> {
+        if alive == self.view.alive {
+            return Ok(());
+        }
+        for (i, (was, is)) in self.view.alive.iter().zip(alive.iter()).enumerate() {
+            match (was, is) {
+                (true, false) => warn!("backend {} is down", self.backs[i]),
+                (false, true) => info!("backend {} is back, catching it up", self.backs[i]),
+                _ => (),
+            }
+        }
+        let next = Membership {
+            version: self.view.version + 1,
+            term: self.term,
+            alive,
+        };
+        self.migrate(&next).await?;
+        let prev = std::mem::replace(&mut self.view, next);
+        self.publish().await?;
+
+        // Clients keep writing through the old view until their copy of it
+        // expires. Once none can, fold what they wrote into the new replicas.
+        tokio::time::sleep(MEMBERSHIP_TTL).await;
+        let next = self.view.clone();
+        self.copy_pass(&prev, &next, true).await?;
+        Ok(())
+    }
+
+    /// Copies every bin onto the backends that hold its replicas under
+    /// `next` but did not under the current view, over and over until a
+    /// pass finds nothing left to copy, so that writes landing during a
+    /// copy are carried over too.
+    async fn migrate(&self, next: &Membership) -> TribResult<()> {
+        for _ in 0..MAX_COPY_PASSES {
+            if !self.copy_pass(&self.view, next, false).await? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies every bin onto the backends that hold its replicas under
+    /// `next` but did not under `prev`, returning whether anything had to
+    /// be copied. Data is read from a backend that was a replica in `prev`
+    /// and is still alive, so a rejoining backend is only ever written to,
+    /// never read from.
+    ///
+    /// Unless `merge`, the copies are made exact, dropping whatever a
+    /// rejoining backend held from before it went down. With `merge`, the
+    /// copies only gain what they lack, keeping writes made to them since.
+    async fn copy_pass(
+        &self,
+        prev: &Membership,
+        next: &Membership,
+        merge: bool,
+    ) -> TribResult<bool> {
+        let mut copied = false;
+        let sources: Vec<usize> = (0..self.backs.len())
+            .filter(|i| prev.is_alive(*i) && next.is_alive(*i))
+            .collect();
+        // Bins are gathered from the destinations too, so that one cleared
+        // on the sources while a destination was down is cleared there
+        let mut names: HashSet<String> = HashSet::new();
+        for (i, stor) in self.storages.iter().enumerate() {
+            if !next.is_alive(i) {
+                continue;
+            }
+            let keys = match stor.keys(&Pattern::default()).await {
+                Ok(keys) => keys.0,
+                Err(_) => continue,
+            };
+            let list_keys = match stor.list_keys(&Pattern::default()).await {
+                Ok(keys) => keys.0,
+                Err(_) => continue,
+            };
+            for key in keys.iter().chain(list_keys.iter()) {
+                if let Some((name, _)) = split_key(key) {
+                    names.insert(name);
+                }
+            }
+        }
+        for name in names {
+            let before = replica_indexes(&self.ring, &name, self.replicas, prev);
+            // Only the first surviving replica copies the bin out
+            let src = match before.iter().find(|i| sources.contains(i)) {
+                Some(src) => &self.storages[*src],
+                None => continue,
+            };
+            for dst in replica_indexes(&self.ring, &name, self.replicas, next) {
+                if !before.contains(&dst) {
+                    copied |= copy_bin(src, &self.storages[dst], &name, merge).await?;
+                }
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Catches up the replicas of every bin a live backend recorded as
+    /// diverged, see [KEY_DIVERGED], copying the bin onto them from that
+    /// backend. A bin that fails to copy stays recorded for the next round.
+    async fn repair(&self) -> TribResult<()> {
+        for (src, stor) in self.storages.iter().enumerate() {
+            if !self.view.is_alive(src) {
+                continue;
+            }
+            let names: HashSet<String> = match stor.list_get(KEY_DIVERGED).await {
+                Ok(names) => names.0.into_iter().collect(),
+                Err(_) => continue,
+            };
+            for name in names {
+                // Taken off first, so that a write missed while the bin is
+                // copied records it again
+                let kv = KeyValue::new(KEY_DIVERGED, &name);
+                stor.list_remove(&kv).await?;
+                if let Err(error) = self.repair_bin(src, &name).await {
+                    warn!("failed to repair bin {}: {}", name, error);
+                    stor.list_append(&kv).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the bin `name` on its other live replicas an exact copy of the
+    /// one on the backend `src`, which took the writes they missed. Missed
+    /// removals and cleared values are carried over as well.
+    async fn repair_bin(&self, src: usize, name: &str) -> TribResult<()> {
+        let stor = &self.storages[src];
+        for dst in replica_indexes(&self.ring, name, self.replicas, &self.view) {
+            if dst != src {
+                copy_bin(stor, &self.storages[dst], name, false).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the current view to every live backend. A backend holding a
+    /// view from a newer term is left alone and fails the call with
+    /// [TribblerError::StaleTerm]. The view is compared and set, so one
+    /// written there by a newer primary meanwhile is never overwritten.
+    async fn publish(&self) -> TribResult<()> {
+        let raw = serde_json::to_string(&self.view)?;
+        for (i, stor) in self.storages.iter().enumerate() {
+            if !self.view.is_alive(i) {
+                continue;
+            }
+            for _ in 0..MAX_PUBLISH_TRIES {
+                let current = match stor.get(KEY_MEMBERSHIP).await {
+                    Ok(current) => current.unwrap_or_default(),
+                    Err(_) => break,
+                };
+                self.check_view(&current)?;
+                if !matches!(
+                    stor.compare_and_set(KEY_MEMBERSHIP, &current, &raw).await,
+                    Ok(false)
+                ) {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with [TribblerError::StaleTerm] if any backend holds a view
+    /// from a newer term than this keeper's
+    async fn check_term(&self) -> TribResult<()> {
+        for stor in self.storages.iter() {
+            if let Ok(Some(raw)) = stor.get(KEY_MEMBERSHIP).await {
+                self.check_view(&raw)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with [TribblerError::StaleTerm] if `raw` is a view from a newer
+    /// term than this keeper's
+    fn check_view(&self, raw: &str) -> TribResult<()> {
+        match serde_json::from_str::<Membership>(raw) {
+            Ok(v) if v.term > self.term => Err(Box::new(TribblerError::StaleTerm(self.term))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Copies every value and list of the bin `name` from `src` to `dst`, see
+/// [copy_value] and [copy_list]. Unless `merge`, also clears the values and
+/// lists of the bin on `dst` that `src` lacks, so the copy is exact.
+/// Returns whether `dst` changed.
+async fn copy_bin(
+    src: &StorageClient,
+    dst: &StorageClient,
+    name: &str,
+    merge: bool,
+) -> TribResult<bool> {
+    let p = Pattern {
+        prefix: bin_prefix(name),
+        suffix: String::new(),
+    };
+    let keys = src.keys(&p).await?.0;
+    let list_keys = src.list_keys(&p).await?.0;
+    let mut changed = false;
+    for key in keys.iter() {
+        changed |= copy_value(src, dst, key, merge).await?;
+    }
+    for key in list_keys.iter() {
+        changed |= copy_list(src, dst, key, merge).await?;
+    }
+    if merge {
+        return Ok(changed);
+    }
+    for key in dst.keys(&p).await?.0 {
+        if !keys.contains(&key) {
+            dst.set(&KeyValue::new(&key, "")).await?;
+            changed = true;
+        }
+    }
+    for key in dst.list_keys(&p).await?.0 {
+        if !list_keys.contains(&key) {
+            let stale: HashSet<String> = dst.list_get(&key).await?.0.into_iter().collect();
+            for value in stale {
+                dst.list_remove(&KeyValue::new(&key, &value)).await?;
+            }
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
+/// Makes the list `key` on `dst` an exact copy of the one on `src`, or
+/// with `merge` appends to it the values of `src` it lacks. Returns whether
+/// `dst` changed.
+async fn copy_list(
+    src: &StorageClient,
+    dst: &StorageClient,
+    key: &str,
+    merge: bool,
+) -> TribResult<bool> {
+    let list = src.list_get(key).await?.0;
+    let existing = dst.list_get(key).await?.0;
+    if list == existing {
+        return Ok(false);
+    }
+    if merge {
+        // Count the values, so that a value appended twice is kept twice
+        let mut have: HashMap<&String, usize> = HashMap::new();
+        for value in existing.iter() {
+            *have.entry(value).or_default() += 1;
+        }
+        let mut changed = false;
+        for value in list.iter() {
+            match have.get_mut(value) {
+                Some(n) if *n > 0 => *n -= 1,
+                _ => {
+                    dst.list_append(&KeyValue::new(key, value)).await?;
+                    changed = true;
+                }
+            }
+        }
+        return Ok(changed);
+    }
+    let stale: HashSet<&String> = existing.iter().collect();
+    for value in stale {
+        dst.list_remove(&KeyValue::new(key, value)).await?;
+    }
+    for value in list.iter() {
+        dst.list_append(&KeyValue::new(key, value)).await?;
+    }
+    Ok(true)
+}
+
+/// Copies the value of `key` on `src` to `dst`, or with `merge` only when
+/// `dst` has none. Returns whether `dst` changed.
+async fn copy_value(
+    src: &StorageClient,
+    dst: &StorageClient,
+    key: &str,
+    merge: bool,
+) -> TribResult<bool> {
+    let value = match src.get(key).await? {
+        Some(value) => value,
+        None => return Ok(false),
+    };
+    match dst.get(key).await? {
+        Some(existing) if existing == value || merge => Ok(false),
+        _ => {
+            dst.set(&KeyValue::new(key, &value)).await?;
+            Ok(true)
+        }
+    }
+}
+
+/// The last heartbeat heard from another keeper
+struct PeerStatus {
+    id: u128,
+    primary: bool,
+    seen: Instant,
+}
+
+/// What a keeper knows about its peers, shared between the heartbeat loop
+/// and the RPC service answering the other keepers.
+///
+/// The primary is the live keeper with the oldest incarnation, i.e. the
+/// smallest `id`. A keeper that restarts therefore never preempts one that
+/// has been running all along.
+pub struct Election {
+    this: usize,
+    id: u128,
+    primary: AtomicBool,
+    peers: Mutex<HashMap<usize, PeerStatus>>,
+}
+
+impl Election {
+    pub fn new(this: usize, id: u128) -> Election {
+        Election {
+            this,
+            id,
+            primary: AtomicBool::new(false),
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether this keeper is currently acting as the primary
+    pub fn is_primary(&self) -> bool {
+        self.primary.load(Ordering::SeqCst)
+    }
+
+    fn heartbeat(&self) -> RpcHeartbeat {
+        RpcHeartbeat {
+            index: self.this as u64,
+            id_high: (self.id >> 64) as u64,
+            id_low: self.id as u64,
+            primary: self.is_primary(),
+        }
+    }
+
+    fn record(&self, hb: &RpcHeartbeat) {
+        let id = (hb.id_high as u128) << 64 | hb.id_low as u128;
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(peer) = peers.get(&(hb.index as usize)) {
+            if peer.id != id {
+                info!("keeper {} restarted", hb.index);
+            }
+        }
+        peers.insert(
+            hb.index as usize,
+            PeerStatus {
+                id,
+                primary: hb.primary,
+                seen: Instant::now(),
+            },
+        );
+    }
+
+    /// Returns the index of the keeper that should be primary
+    pub fn leader(&self) -> usize {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .iter()
+            .filter(|(_, p)| p.seen.elapsed() < HEARTBEAT_TIMEOUT)
+            .map(|(i, p)| (p.id, *i))
+            .chain(std::iter::once((self.id, self.this)))
+            .min()
+            .map_or(self.this, |(_, i)| i)
+    }
+
+    /// Whether another live keeper currently acts as the primary
+    fn peer_primary(&self) -> bool {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .values()
+            .any(|p| p.primary && p.seen.elapsed() < HEARTBEAT_TIMEOUT)
+    }
+}
+
+/// The RPC service other keepers send their heartbeats to
+pub struct KeeperPeer {
+    pub election: Arc<Election>,
+}
+
+#[async_trait]
+impl KeeperRpc for KeeperPeer {
+    async fn heartbeat(
+        &self,
+        request: tonic::Request<RpcHeartbeat>,
+    ) -> Result<tonic::Response<RpcHeartbeat>, tonic::Status> {
+        self.election.record(&request.into_inner());
+        Ok(tonic::Response::new(self.election.heartbeat()))
+    }
+}
+
+/// Stops a spawned task once dropped, so that it does not outlive the
+/// future that spawned it
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Sends heartbeats to the other keepers forever, and steps this keeper
+/// down as soon as another one should be the primary
+async fn exchange_heartbeats(election: Arc<Election>, addrs: Vec<String>) {
+    let mut clients: HashMap<usize, KeeperClient<Channel>> = HashMap::new();
+    loop {
+        for (i, addr) in addrs.iter().enumerate() {
+            if i == election.this {
+                continue;
+            }
+            let client = match clients.get_mut(&i) {
+                Some(client) => client,
+                None => match Endpoint::from_shared(format!("http://{}", addr)) {
+                    Ok(endpoint) => clients.entry(i).or_insert_with(|| {
+                        KeeperClient::new(
+                            endpoint
+                                .connect_timeout(HEARTBEAT_INTERVAL)
+                                .timeout(HEARTBEAT_INTERVAL)
+                                .connect_lazy(),
+                        )
+                    }),
+                    Err(_) => continue,
+                },
+            };
+            match client.heartbeat(election.heartbeat()).await {
+                Ok(reply) => election.record(&reply.into_inner()),
+                Err(_) => {
+                    clients.remove(&i);
+                }
+            }
+        }
+        if election.is_primary() && election.leader() != election.this {
+            info!("keeper {} stepping down", election.this);
+            election.primary.store(false, Ordering::SeqCst);
+        }
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+    }
+}
+
+/// Exchanges heartbeats with the other keepers forever. Whenever this
+/// keeper is elected primary it takes over the [Keeper] duties, adopting
+/// the view the previous primary left on the backends.
+///
+/// Heartbeats go out from a task of their own, so that a long round of
+/// [Keeper::tick] never keeps the other keepers from hearing this one. A
+/// round that outlives this keeper's term anyway, e.g. across a network
+/// partition, is fenced off by the term, see [Keeper::init].
+///
+/// `ready` is notified once some keeper, this one or another, acts as the
+/// primary.
+pub async fn coordinate(
+    election: Arc<Election>,
+    addrs: Vec<String>,
+    mut keeper: Keeper,
+    mut ready: Option<Sender<bool>>,
+) {
+    let started = Instant::now();
+    let _heartbeats = AbortOnDrop(tokio::spawn(exchange_heartbeats(election.clone(), addrs)));
+    let mut last_tick = Instant::now();
+    loop {
+        // Give the other keepers a chance to be heard before electing
+        if started.elapsed() >= HEARTBEAT_TIMEOUT && election.leader() == election.this {
+            if !election.is_primary() {
+                info!("keeper {} taking over as primary", election.this);
+                match keeper.init().await {
+                    Ok(_) => {
+                        election.primary.store(true, Ordering::SeqCst);
+                        last_tick = Instant::now();
+                    }
+                    Err(error) => warn!("keeper failed to take over: {}", error),
+                }
+            } else if last_tick.elapsed() >= TICK_INTERVAL {
+                last_tick = Instant::now();
+                // A failed migration is retried on the next round since
+                // the published view is left unchanged
+                match keeper.tick().await {
+                    Ok(_) => (),
+                    Err(error) if is_stale_term(error.as_ref()) => {
+                        info!("keeper {} superseded, stepping down", election.this);
+                        election.primary.store(false, Ordering::SeqCst);
+                    }
+                    Err(error) => warn!("keeper round failed: {}", error),
+                }
+            }
+        }
+        if election.is_primary() || election.peer_primary() {
+            if let Some(tx) = ready.take() {
+                let _ = tx.send(true);
+            }
+        }
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+    }
+}
+
+/// Whether `error` is [TribblerError::StaleTerm]
+fn is_stale_term(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<TribblerError>(),
+        Some(TribblerError::StaleTerm(_))
+    )
+}
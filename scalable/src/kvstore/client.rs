@@ -1,36 +1,121 @@
 use async_trait::async_trait;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tonic::transport::Channel;
+use std::{future::Future, time::Duration};
+use tokio::sync::mpsc;
+use tonic::{
+    transport::{Channel, Endpoint},
+    Code, Request, Response, Status,
+};
 
 use tribbler::err::TribResult;
 use tribbler::rpc::{
-    trib_storage_client::TribStorageClient, Clock as RpcClock, Key as RpcKey,
-    KeyValue as RpcKeyValue, Pattern as RpcPattern,
+    trib_storage_client::TribStorageClient, ChangeKind as RpcChangeKind, Clock as RpcClock,
+    CompareAndSet as RpcCompareAndSet, ConditionalAppend as RpcConditionalAppend, Key as RpcKey,
+    KeyValue as RpcKeyValue, KeyValues as RpcKeyValues, Keys as RpcKeys, Pattern as RpcPattern,
+};
+use tribbler::storage::{
+    AppendCondition, Change, ChangeKind, KeyList, KeyString, KeyValue, List, Pattern, Storage,
+    WATCH_BUFFER,
 };
-use tribbler::storage::{KeyList, KeyString, KeyValue, List, Pattern, Storage};
 
+/// How long a call may take, by default, before it fails
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(2);
+
+/// How long connecting to the backend may take
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many times a call is retried while the backend cannot be reached
+const RETRIES: u32 = 3;
+
+/// How long to wait before the first retry. Each retry waits twice as long
+/// as the one before.
+const BACKOFF: Duration = Duration::from_millis(20);
+
+/// A client of one backend. Clones share the same connection, which
+/// carries concurrent calls side by side rather than one at a time. The
+/// connection is only made on first use, and is made again whenever it
+/// breaks.
+#[derive(Clone)]
 pub struct StorageClient {
     pub addr: String,
-    pub client: Arc<Mutex<Option<TribStorageClient<Channel>>>>,
+    client: TribStorageClient<Channel>,
+    deadline: Duration,
+}
+
+impl StorageClient {
+    /// Creates a client of the backend at `addr`, e.g. "http://127.0.0.1:3000".
+    /// Nothing is sent over the network until the first call.
+    pub fn new(addr: &str) -> TribResult<StorageClient> {
+        let channel = Endpoint::from_shared(addr.to_string())?
+            .connect_timeout(CONNECT_TIMEOUT)
+            .tcp_nodelay(true)
+            .connect_lazy();
+        Ok(StorageClient {
+            addr: addr.to_string(),
+            client: TribStorageClient::new(channel),
+            deadline: DEFAULT_DEADLINE,
+        })
+    }
+
+    /// Returns a client sharing this one's connection whose calls fail once
+    /// they take longer than `deadline`.
+    pub fn with_deadline(&self, deadline: Duration) -> StorageClient {
+        StorageClient {
+            deadline,
+            ..self.clone()
+        }
+    }
+
+    /// Issues one idempotent call built by `op` under this client's
+    /// deadline. Calls that could not reach the backend are retried with an
+    /// exponential backoff; any other error is returned right away.
+    async fn call<M, T, F, Fut>(&self, msg: M, op: F) -> TribResult<T>
+    where
+        M: Clone,
+        F: Fn(TribStorageClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let mut backoff = BACKOFF;
+        let mut retries = 0;
+        loop {
+            let mut request = Request::new(msg.clone());
+            request.set_timeout(self.deadline);
+            match op(self.client.clone(), request).await {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status) if status.code() == Code::Unavailable && retries < RETRIES => {
+                    retries += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(status) => return Err(Box::new(status)),
+            }
+        }
+    }
+
+    /// Issues one call built by `op` under this client's deadline, without
+    /// retrying it. For calls that are not idempotent, such as appends: a
+    /// call that failed may still have been carried out by the backend, and
+    /// issuing it again would carry it out twice.
+    async fn call_once<M, T, F, Fut>(&self, msg: M, op: F) -> TribResult<T>
+    where
+        F: FnOnce(TribStorageClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let mut request = Request::new(msg);
+        request.set_timeout(self.deadline);
+        Ok(op(self.client.clone(), request).await?.into_inner())
+    }
 }
 
 #[async_trait]
 impl KeyString for StorageClient {
     async fn get(&self, key: &str) -> TribResult<Option<String>> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .get(RpcKey {
-                key: key.to_string(),
-            })
-            .await?;
-        let value = response.into_inner().value;
+        let key = RpcKey {
+            key: key.to_string(),
+        };
+        let value = self
+            .call(key, |mut c, r| async move { c.get(r).await })
+            .await?
+            .value;
         if value.chars().count() > 0 {
             Ok(Some(value))
         } else {
@@ -39,125 +124,188 @@ impl KeyString for StorageClient {
     }
 
     async fn set(&self, kv: &KeyValue) -> TribResult<bool> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .set(RpcKeyValue {
-                key: kv.key.clone(),
-                value: kv.value.clone(),
-            })
+        let kv = RpcKeyValue {
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+        };
+        let response = self
+            .call(kv, |mut c, r| async move { c.set(r).await })
             .await?;
-        Ok(response.into_inner().value)
+        Ok(response.value)
     }
 
     async fn keys(&self, p: &Pattern) -> TribResult<List> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .keys(RpcPattern {
-                prefix: p.prefix.clone(),
-                suffix: p.suffix.clone(),
-            })
+        let p = RpcPattern {
+            prefix: p.prefix.clone(),
+            suffix: p.suffix.clone(),
+        };
+        let response = self
+            .call(p, |mut c, r| async move { c.keys(r).await })
             .await?;
-        Ok(List(response.into_inner().list))
+        Ok(List(response.list))
+    }
+
+    async fn compare_and_set(&self, key: &str, expected: &str, value: &str) -> TribResult<bool> {
+        let cas = RpcCompareAndSet {
+            key: key.to_string(),
+            expected: expected.to_string(),
+            value: value.to_string(),
+        };
+        let response = self
+            .call_once(cas, |mut c, r| async move { c.compare_and_set(r).await })
+            .await?;
+        Ok(response.value)
+    }
+
+    async fn get_many(&self, keys: &[String]) -> TribResult<Vec<Option<String>>> {
+        let keys = RpcKeys {
+            keys: keys.to_vec(),
+        };
+        let response = self
+            .call(keys, |mut c, r| async move { c.get_many(r).await })
+            .await?;
+        Ok(response
+            .list
+            .into_iter()
+            .map(|value| if value.is_empty() { None } else { Some(value) })
+            .collect())
     }
 }
 
 #[async_trait]
 impl KeyList for StorageClient {
     async fn list_get(&self, key: &str) -> TribResult<List> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .list_get(RpcKey {
-                key: key.to_string(),
-            })
+        let key = RpcKey {
+            key: key.to_string(),
+        };
+        let response = self
+            .call(key, |mut c, r| async move { c.list_get(r).await })
             .await?;
-        Ok(List(response.into_inner().list))
+        Ok(List(response.list))
     }
 
     async fn list_append(&self, kv: &KeyValue) -> TribResult<bool> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .list_append(RpcKeyValue {
-                key: kv.key.clone(),
-                value: kv.value.clone(),
-            })
+        let kv = RpcKeyValue {
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+        };
+        let response = self
+            .call_once(kv, |mut c, r| async move { c.list_append(r).await })
             .await?;
-        Ok(response.into_inner().value)
+        Ok(response.value)
     }
 
     async fn list_remove(&self, kv: &KeyValue) -> TribResult<u32> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .list_remove(RpcKeyValue {
-                key: kv.key.clone(),
-                value: kv.value.clone(),
-            })
+        let kv = RpcKeyValue {
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+        };
+        let response = self
+            .call(kv, |mut c, r| async move { c.list_remove(r).await })
             .await?;
-        Ok(response.into_inner().removed)
+        Ok(response.removed)
     }
 
     async fn list_keys(&self, p: &Pattern) -> TribResult<List> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .list_keys(RpcPattern {
-                prefix: p.prefix.clone(),
-                suffix: p.suffix.clone(),
-            })
+        let p = RpcPattern {
+            prefix: p.prefix.clone(),
+            suffix: p.suffix.clone(),
+        };
+        let response = self
+            .call(p, |mut c, r| async move { c.list_keys(r).await })
             .await?;
-        Ok(List(response.into_inner().list))
+        Ok(List(response.list))
+    }
+
+    async fn list_append_if(&self, kv: &KeyValue, cond: &AppendCondition) -> TribResult<bool> {
+        let ca = RpcConditionalAppend {
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+            absent: cond.absent,
+            limited: cond.max_len.is_some(),
+            max_len: cond.max_len.unwrap_or_default(),
+        };
+        let response = self
+            .call_once(ca, |mut c, r| async move { c.list_append_if(r).await })
+            .await?;
+        Ok(response.value)
+    }
+
+    async fn list_get_many(&self, keys: &[String]) -> TribResult<Vec<List>> {
+        let keys = RpcKeys {
+            keys: keys.to_vec(),
+        };
+        let response = self
+            .call(keys, |mut c, r| async move { c.list_get_many(r).await })
+            .await?;
+        Ok(response.lists.into_iter().map(|l| List(l.list)).collect())
+    }
+
+    async fn list_append_many(&self, kvs: &[KeyValue]) -> TribResult<bool> {
+        let kvs = RpcKeyValues {
+            kvs: kvs
+                .iter()
+                .map(|kv| RpcKeyValue {
+                    key: kv.key.clone(),
+                    value: kv.value.clone(),
+                })
+                .collect(),
+        };
+        let response = self
+            .call_once(kvs, |mut c, r| async move { c.list_append_many(r).await })
+            .await?;
+        Ok(response.value)
     }
 }
 
 #[async_trait]
 impl Storage for StorageClient {
     async fn clock(&self, at_least: u64) -> TribResult<u64> {
-        let client = Arc::clone(&self.client);
-        let mut cl_inner = client.lock().await;
-        if cl_inner.is_none() {
-            *cl_inner = Some(TribStorageClient::connect(self.addr.clone()).await?);
-        }
-        let response = cl_inner
-            .as_mut()
-            .unwrap()
-            .clock(RpcClock {
-                timestamp: at_least,
-            })
+        let clock = RpcClock {
+            timestamp: at_least,
+        };
+        let response = self
+            .call(clock, |mut c, r| async move { c.clock(r).await })
             .await?;
-        Ok(response.into_inner().timestamp)
+        Ok(response.timestamp)
+    }
+
+    async fn watch(&self, p: &Pattern) -> TribResult<mpsc::Receiver<Change>> {
+        // The stream lives for as long as the caller watches, so unlike
+        // other calls it is not bounded by the deadline
+        let p = RpcPattern {
+            prefix: p.prefix.clone(),
+            suffix: p.suffix.clone(),
+        };
+        let mut stream = self.client.clone().watch(p).await?.into_inner();
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                // Dropping the stream once the caller stops watching cancels
+                // the call on the backend as well
+                let change = tokio::select! {
+                    _ = tx.closed() => break,
+                    msg = stream.message() => match msg {
+                        Ok(Some(change)) => change,
+                        _ => break,
+                    },
+                };
+                let kind = match RpcChangeKind::from_i32(change.kind) {
+                    Some(RpcChangeKind::Set) => ChangeKind::Set,
+                    Some(RpcChangeKind::ListAppend) => ChangeKind::ListAppend,
+                    Some(RpcChangeKind::ListRemove) => ChangeKind::ListRemove,
+                    None => continue,
+                };
+                let change = Change {
+                    kind,
+                    key: change.key,
+                    value: change.value,
+                };
+                if tx.send(change).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
     }
 }
@@ -1,5 +1,5 @@
 use std::net::ToSocketAddrs;
-use std::{error::Error, sync::Arc};
+use std::error::Error;
 use tonic::transport::Server;
 
 use tribbler::{
@@ -90,8 +90,5 @@ pub async fn serve_back(config: BackConfig) -> TribResult<()> {
 /// trait. It should communicate with the backend that is started in the
 /// [serve_back] function.
 pub async fn new_client(addr: &str) -> TribResult<Box<dyn Storage>> {
-    Ok(Box::new(StorageClient {
-        addr: addr.to_string(),
-        client: Arc::new(tokio::sync::Mutex::new(None)),
-    }))
+    Ok(Box::new(StorageClient::new(addr)?))
 }
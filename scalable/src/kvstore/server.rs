@@ -1,19 +1,40 @@
 use async_trait::async_trait;
+use std::pin::Pin;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tonic;
 
 use tribbler::rpc::{
-    trib_storage_server::TribStorage, Bool as RpcBool, Clock as RpcClock, Key as RpcKey,
-    KeyValue as RpcKeyValue, ListRemoveResponse as RpcListRemoveResponse, Pattern as RpcPattern,
-    StringList as RpcStringList, Value as RpcValue,
+    trib_storage_server::TribStorage, Bool as RpcBool, Change as RpcChange,
+    ChangeKind as RpcChangeKind, Clock as RpcClock, CompareAndSet as RpcCompareAndSet,
+    ConditionalAppend as RpcConditionalAppend, Key as RpcKey, KeyValue as RpcKeyValue,
+    KeyValues as RpcKeyValues, Keys as RpcKeys, ListRemoveResponse as RpcListRemoveResponse,
+    Pattern as RpcPattern, StringList as RpcStringList, StringLists as RpcStringLists,
+    Value as RpcValue,
 };
-use tribbler::storage::{KeyValue, List, Pattern, Storage};
+use tribbler::storage::{AppendCondition, Change, ChangeKind, KeyValue, List, Pattern, Storage};
 
 pub struct StorageServer {
     pub storage: Box<dyn Storage>,
 }
 
+fn to_rpc_change(change: Change) -> RpcChange {
+    let kind = match change.kind {
+        ChangeKind::Set => RpcChangeKind::Set,
+        ChangeKind::ListAppend => RpcChangeKind::ListAppend,
+        ChangeKind::ListRemove => RpcChangeKind::ListRemove,
+    };
+    RpcChange {
+        kind: kind as i32,
+        key: change.key,
+        value: change.value,
+    }
+}
+
 #[async_trait]
 impl TribStorage for StorageServer {
+    #[allow(non_camel_case_types)]
+    type watchStream = Pin<Box<dyn Stream<Item = Result<RpcChange, tonic::Status>> + Send>>;
+
     async fn get(
         &self,
         request: tonic::Request<RpcKey>,
@@ -138,4 +159,115 @@ impl TribStorage for StorageServer {
             Err(error) => Err(tonic::Status::unknown(format!("Error: {}", error))),
         }
     }
+
+    async fn get_many(
+        &self,
+        request: tonic::Request<RpcKeys>,
+    ) -> Result<tonic::Response<RpcStringList>, tonic::Status> {
+        match self.storage.get_many(&request.into_inner().keys).await {
+            // Unset keys come back as empty strings, as they do from get
+            Ok(values) => Ok(tonic::Response::new(RpcStringList {
+                list: values.into_iter().map(Option::unwrap_or_default).collect(),
+            })),
+            Err(error) => Err(tonic::Status::unknown(format!("Error: {}", error))),
+        }
+    }
+
+    async fn list_get_many(
+        &self,
+        request: tonic::Request<RpcKeys>,
+    ) -> Result<tonic::Response<RpcStringLists>, tonic::Status> {
+        match self.storage.list_get_many(&request.into_inner().keys).await {
+            Ok(lists) => Ok(tonic::Response::new(RpcStringLists {
+                lists: lists
+                    .into_iter()
+                    .map(|List(list)| RpcStringList { list })
+                    .collect(),
+            })),
+            Err(error) => Err(tonic::Status::unknown(format!("Error: {}", error))),
+        }
+    }
+
+    async fn list_append_many(
+        &self,
+        request: tonic::Request<RpcKeyValues>,
+    ) -> Result<tonic::Response<RpcBool>, tonic::Status> {
+        let kvs = request
+            .into_inner()
+            .kvs
+            .into_iter()
+            .map(|rpc_kv| KeyValue {
+                key: rpc_kv.key,
+                value: rpc_kv.value,
+            })
+            .collect::<Vec<KeyValue>>();
+        match self.storage.list_append_many(&kvs).await {
+            Ok(value) => Ok(tonic::Response::new(RpcBool { value })),
+            Err(error) => Err(tonic::Status::unknown(format!("Error: {}", error))),
+        }
+    }
+
+    async fn compare_and_set(
+        &self,
+        request: tonic::Request<RpcCompareAndSet>,
+    ) -> Result<tonic::Response<RpcBool>, tonic::Status> {
+        let rpc_cas = request.into_inner();
+        match self
+            .storage
+            .compare_and_set(&rpc_cas.key, &rpc_cas.expected, &rpc_cas.value)
+            .await
+        {
+            Ok(value) => Ok(tonic::Response::new(RpcBool { value })),
+            Err(error) => Err(tonic::Status::unknown(format!("Error: {}", error))),
+        }
+    }
+
+    async fn list_append_if(
+        &self,
+        request: tonic::Request<RpcConditionalAppend>,
+    ) -> Result<tonic::Response<RpcBool>, tonic::Status> {
+        let rpc_ca = request.into_inner();
+        let cond = AppendCondition {
+            absent: rpc_ca.absent,
+            max_len: if rpc_ca.limited {
+                Some(rpc_ca.max_len)
+            } else {
+                None
+            },
+        };
+        match self
+            .storage
+            .list_append_if(
+                &KeyValue {
+                    key: rpc_ca.key,
+                    value: rpc_ca.value,
+                },
+                &cond,
+            )
+            .await
+        {
+            Ok(value) => Ok(tonic::Response::new(RpcBool { value })),
+            Err(error) => Err(tonic::Status::unknown(format!("Error: {}", error))),
+        }
+    }
+
+    async fn watch(
+        &self,
+        request: tonic::Request<RpcPattern>,
+    ) -> Result<tonic::Response<Self::watchStream>, tonic::Status> {
+        let rpc_pat = request.into_inner();
+        match self
+            .storage
+            .watch(&Pattern {
+                prefix: rpc_pat.prefix,
+                suffix: rpc_pat.suffix,
+            })
+            .await
+        {
+            Ok(changes) => Ok(tonic::Response::new(Box::pin(
+                ReceiverStream::new(changes).map(to_rpc_change).map(Ok),
+            ))),
+            Err(error) => Err(tonic::Status::unknown(format!("Error: {}", error))),
+        }
+    }
 }
@@ -1,28 +1,47 @@
-use std::sync::Arc;
-use tokio::{select, sync::Mutex, time};
+use std::{
+    error::Error,
+    net::ToSocketAddrs,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tokio::{select, sync::Mutex};
 
 use tribbler::{
-    config::KeeperConfig,
+    config::{KeeperConfig, DEFAULT_REPLICAS},
     err::TribResult,
-    storage::{BinStorage, Storage},
+    storage::BinStorage,
     trib::Server,
+    validate::UsernamePolicy,
 };
 
 pub mod binstorage;
 mod frontserver;
+/// protobuf-generated keeper RPC stubs and message structs
+pub mod keeper;
+pub mod keeperserver;
 pub mod kvstore;
+pub mod ring;
 mod zookeeper;
 
 use binstorage::BinStorageClient;
-use frontserver::FrontServer;
-use kvstore::client::StorageClient;
+use frontserver::{FrontServer, UsersCache};
+use keeper::keeper_server::KeeperServer;
+use keeperserver::{coordinate, Election, Keeper, KeeperPeer};
 
 /// This function accepts a list of backend addresses, and returns a
 /// type which should implement the [BinStorage] trait to access the
 /// underlying storage system.
 #[allow(unused_variables)]
 pub async fn new_bin_client(backs: Vec<String>) -> TribResult<Box<dyn BinStorage>> {
-    Ok(Box::new(BinStorageClient { backs }))
+    new_replicated_bin_client(backs, DEFAULT_REPLICAS).await
+}
+
+/// Same as [new_bin_client], but every bin is written to `replicas`
+/// backends instead of [DEFAULT_REPLICAS].
+pub async fn new_replicated_bin_client(
+    backs: Vec<String>,
+    replicas: usize,
+) -> TribResult<Box<dyn BinStorage>> {
+    Ok(Box::new(BinStorageClient::new(backs, replicas)?))
 }
 
 /// this async function accepts a [KeeperConfig] that should be used to start
@@ -33,47 +52,67 @@ pub async fn new_bin_client(backs: Vec<String>) -> TribResult<Box<dyn BinStorage
 /// started.
 #[allow(unused_variables)]
 pub async fn serve_keeper(kc: KeeperConfig) -> TribResult<()> {
-    let mut storages: Vec<StorageClient> = Vec::new();
-    kc.backs.into_iter().for_each(|back| {
-        storages.push(StorageClient {
-            addr: format!("http://{}", back),
-            client: Arc::new(tokio::sync::Mutex::new(None)),
-        });
-    });
-    if let Some(tx) = kc.ready.clone() {
-        if let Err(error) = tx.send(true) {
+    // Resolve the address string to a SocketAddr value
+    let addr = match kc.addr().to_socket_addrs().map(|mut it| it.next()) {
+        Ok(Some(socket_addr)) => socket_addr,
+        Ok(None) => {
+            if let Some(tx) = kc.ready {
+                if let Err(error) = tx.send(false) {
+                    return Err(Box::new(error));
+                }
+            }
+            return Err(Box::<dyn Error + Send + Sync>::from(
+                "Error: Bad address".to_string(),
+            ));
+        }
+        Err(error) => {
+            if let Some(tx) = kc.ready {
+                if let Err(error) = tx.send(false) {
+                    return Err(Box::new(error));
+                }
+            }
             return Err(Box::new(error));
         }
-    }
-    select! {
-        _ = async move {
-            let mut max_timestamp: u64 = 0;
-            loop {
-                for stor in storages.iter() {
-                    let clock = match stor.clock(max_timestamp).await {
-                        Ok(clock) => clock,
-                        Err(error) => {
-                            if let Some(tx) = kc.ready.clone() {
-                                if let Err(error) = tx.send(false) {
-                                    return TribResult::<()>::Err(Box::new(error));
-                                }
-                            }
-                            return TribResult::<()>::Err(error);
-                        }
-                    };
-                    if clock > max_timestamp {
-                        max_timestamp = clock;
-                    }
+    };
+
+    let election = Arc::new(Election::new(kc.this, kc.id));
+    let service = KeeperServer::new(KeeperPeer {
+        election: election.clone(),
+    });
+    let keeper = match Keeper::new(kc.backs.clone(), kc.replicas) {
+        Ok(keeper) => keeper,
+        Err(error) => {
+            if let Some(tx) = kc.ready {
+                if let Err(error) = tx.send(false) {
+                    return Err(Box::new(error));
                 }
-                // max_timestamp += 1;
-                time::sleep(time::Duration::from_secs(1)).await;
             }
-        } => {}
-        _ = async {
-            if let Some(mut rx) = kc.shutdown {
+            return Err(error);
+        }
+    };
+    let ready = kc.ready.clone();
+    let shutdown = async {
+        match kc.shutdown {
+            Some(mut rx) => {
                 rx.recv().await;
             }
-        } => {}
+            None => std::future::pending::<()>().await,
+        }
+    };
+    select! {
+        res = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_shutdown(addr, shutdown) => {
+            if let Err(error) = res {
+                if let Some(tx) = kc.ready {
+                    if let Err(error) = tx.send(false) {
+                        return Err(Box::new(error));
+                    }
+                }
+                return Err(Box::new(error));
+            }
+        }
+        _ = coordinate(election, kc.addrs.clone(), keeper, ready) => {}
     }
     Ok(())
 }
@@ -92,7 +131,43 @@ pub async fn new_front(
     bin_storage: Box<dyn BinStorage>,
 ) -> TribResult<Box<dyn Server + Send + Sync>> {
     Ok(Box::new(FrontServer {
-        bin_storage,
-        users_cache: Mutex::<Vec<String>>::new(vec![]),
+        bin_storage: Arc::from(bin_storage),
+        users_cache: Mutex::new(UsersCache::default()),
+        fanout: false,
+        username_policy: UsernamePolicy::default(),
+        followers_indexed: AtomicBool::new(false),
+    }))
+}
+
+/// Same as [new_front], except that home timelines are built on write: a
+/// post is copied into the home list of each follower of its author, so
+/// reading a home timeline takes one list read however many users are
+/// followed, at the cost of slower posts from users with many followers.
+pub async fn new_fanout_front(
+    bin_storage: Box<dyn BinStorage>,
+) -> TribResult<Box<dyn Server + Send + Sync>> {
+    Ok(Box::new(FrontServer {
+        bin_storage: Arc::from(bin_storage),
+        users_cache: Mutex::new(UsersCache::default()),
+        fanout: true,
+        username_policy: UsernamePolicy::default(),
+        followers_indexed: AtomicBool::new(false),
+    }))
+}
+
+/// Same as [new_fanout_front] when `fanout` is set and [new_front]
+/// otherwise, except that usernames are accepted as `username_policy`
+/// says rather than as [tribbler::trib::is_valid_username] does.
+pub async fn new_front_with_policy(
+    bin_storage: Box<dyn BinStorage>,
+    fanout: bool,
+    username_policy: UsernamePolicy,
+) -> TribResult<Box<dyn Server + Send + Sync>> {
+    Ok(Box::new(FrontServer {
+        bin_storage: Arc::from(bin_storage),
+        users_cache: Mutex::new(UsersCache::default()),
+        fanout,
+        username_policy,
+        followers_indexed: AtomicBool::new(false),
     }))
 }
@@ -0,0 +1,125 @@
+//! A consistent hashing ring used to place bins on backends.
+//!
+//! Every backend owns [VIRTUAL_NODES] points on the ring, derived only from
+//! its own address. A bin belongs to the backends owning the first points
+//! found walking clockwise from the bin's hash, so adding or removing one
+//! backend only moves the bins that land next to that backend's points.
+
+/// Number of points each backend owns on the ring
+pub const VIRTUAL_NODES: usize = 64;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A 64-bit hash which, unlike [std::collections::hash_map::DefaultHasher],
+/// is guaranteed to give the same value in every process and on every Rust
+/// release: FNV-1a followed by the MurmurHash3 finalizer, which spreads
+/// the similar inputs FNV-1a is weak on across the whole ring.
+pub fn stable_hash(data: &[u8]) -> u64 {
+    let mut h = FNV_OFFSET_BASIS;
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(FNV_PRIME);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h
+}
+
+/// A consistent hashing ring over a list of backend addresses
+#[derive(Debug, Clone)]
+pub struct Ring {
+    // (point, index of the backend owning it), sorted by point
+    points: Vec<(u64, usize)>,
+    backs: usize,
+}
+
+impl Ring {
+    /// Builds the ring for `backs`. Backends are referred to by their index
+    /// in `backs` everywhere else.
+    pub fn new(backs: &[String]) -> Ring {
+        let mut points = Vec::with_capacity(backs.len() * VIRTUAL_NODES);
+        for (i, back) in backs.iter().enumerate() {
+            for v in 0..VIRTUAL_NODES {
+                points.push((stable_hash(format!("{}#{}", back, v).as_bytes()), i));
+            }
+        }
+        points.sort_unstable();
+        Ring {
+            points,
+            backs: backs.len(),
+        }
+    }
+
+    /// The number of backends on the ring
+    pub fn len(&self) -> usize {
+        self.backs
+    }
+
+    /// Whether the ring has no backends at all
+    pub fn is_empty(&self) -> bool {
+        self.backs == 0
+    }
+
+    /// Returns every backend exactly once, in the order met walking
+    /// clockwise from the hash of `name`.
+    pub fn successors(&self, name: &str) -> Vec<usize> {
+        let h = stable_hash(name.as_bytes());
+        let start = self.points.partition_point(|(p, _)| *p < h);
+        let mut seen = vec![false; self.backs];
+        let mut order = Vec::with_capacity(self.backs);
+        for k in 0..self.points.len() {
+            let (_, i) = self.points[(start + k) % self.points.len()];
+            if !seen[i] {
+                seen[i] = true;
+                order.push(i);
+                if order.len() == self.backs {
+                    break;
+                }
+            }
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{stable_hash, Ring};
+
+    fn backs(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("10.0.0.{}:3000", i)).collect()
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(stable_hash(b"alice"), stable_hash(b"alice"));
+        assert_ne!(stable_hash(b"alice"), stable_hash(b"alicf"));
+        assert_eq!(0xefd0_1f60_ba99_2926, stable_hash(b""));
+        assert_eq!(0x3507_d047_a67c_08f4, stable_hash(b"alice"));
+    }
+
+    #[test]
+    fn successors_cover_every_backend() {
+        let ring = Ring::new(&backs(5));
+        let mut order = ring.successors("alice");
+        assert_eq!(order, Ring::new(&backs(5)).successors("alice"));
+        order.sort();
+        assert_eq!(vec![0, 1, 2, 3, 4], order);
+    }
+
+    #[test]
+    fn adding_a_backend_moves_few_bins() {
+        let before = Ring::new(&backs(10));
+        let after = Ring::new(&backs(11));
+        let moved = (0..10000)
+            .map(|i| format!("user{}", i))
+            .filter(|name| before.successors(name)[0] != after.successors(name)[0])
+            .count();
+        // about 1/11 of the bins should move to the new backend
+        assert!(moved < 1500, "{} bins moved", moved);
+        assert!(moved > 400, "{} bins moved", moved);
+    }
+}
@@ -17,7 +17,7 @@ use tribbler::{
     self,
     config::BackConfig,
     err::{TribResult, TribblerError},
-    storage::{KeyList, KeyString, KeyValue, MemStorage, Pattern, Storage},
+    storage::{AppendCondition, KeyList, KeyString, KeyValue, MemStorage, Pattern, Storage},
 };
 
 const DEFAULT_HOST: &str = "127.0.0.1:3000";
@@ -154,6 +154,31 @@ async fn test_list() -> TribResult<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_conditional_ops() -> TribResult<()> {
+    let addr = format!("127.0.0.1:{}", rand_port());
+    let (client, _handle, _shut) = setup(Some(&addr), None).await?;
+    assert!(client.compare_and_set("h8liu", "", "run").await?);
+    assert!(!client.compare_and_set("h8liu", "", "walk").await?);
+    assert!(client.compare_and_set("h8liu", "run", "walk").await?);
+    assert_eq!(Some("walk".to_string()), client.get("h8liu").await?);
+
+    let absent = AppendCondition {
+        absent: true,
+        max_len: None,
+    };
+    assert!(client.list_append_if(&kv("lst", "a"), &absent).await?);
+    assert!(!client.list_append_if(&kv("lst", "a"), &absent).await?);
+    let bounded = AppendCondition {
+        absent: false,
+        max_len: Some(2),
+    };
+    assert!(client.list_append_if(&kv("lst", "a"), &bounded).await?);
+    assert!(!client.list_append_if(&kv("lst", "b"), &bounded).await?);
+    assert_eq!(vec!["a", "a"], client.list_get("lst").await?.0);
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_list_keys() -> TribResult<()> {
     let (client, _srv, _shut) = setup(None, None).await?;
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     sync::{
         mpsc::{self, Receiver, Sender},
         Arc,
@@ -18,7 +19,7 @@ use tribbler::{
     colon,
     config::BackConfig,
     err::{TribResult, TribblerError},
-    storage::{KeyList, KeyString, KeyValue, MemStorage, Pattern, Storage},
+    storage::{ChangeKind, KeyList, KeyString, KeyValue, MemStorage, Pattern, Storage},
 };
 
 const DEFAULT_HOST: &str = "127.0.0.1:3000";
@@ -58,14 +59,11 @@ async fn setup(
     let name = "jerry".to_string();
     let mut prefix = colon::escape("jerry".clone());
     prefix.push_str(&"::".to_string());
-    let stor = StorageClient {
-        addr: format!("http://{}", addr),
-        client: Arc::new(tokio::sync::Mutex::new(None)),
-    };
+    let stor = StorageClient::new(&format!("http://{}", addr))?;
     let client = Box::<Bin>::new(Bin {
         _name: name,
         prefix,
-        storage: stor,
+        replicas: vec![stor],
     });
     Ok((client, handle, shut_tx.clone()))
 }
@@ -233,14 +231,11 @@ async fn test_store_before_serve() -> TribResult<()> {
     let name = "jerry".to_string();
     let mut prefix = colon::escape("jerry".clone());
     prefix.push_str(&"::".to_string());
-    let stor = StorageClient {
-        addr: format!("http://{}", DEFAULT_HOST),
-        client: Arc::new(tokio::sync::Mutex::new(None)),
-    };
+    let stor = StorageClient::new(&format!("http://{}", DEFAULT_HOST))?;
     let client = Box::<Bin>::new(Bin {
         _name: name,
         prefix,
-        storage: stor,
+        replicas: vec![stor],
     });
     tokio::time::sleep(Duration::from_millis(500)).await;
     assert_ne!(Some("hi".to_string()), client.get("hello").await?);
@@ -310,14 +305,11 @@ async fn test_spawn_same_addr() -> TribResult<()> {
     let name = "jerry".to_string();
     let mut prefix = colon::escape("jerry".clone());
     prefix.push_str(&"::".to_string());
-    let stor = StorageClient {
-        addr: format!("http://{}", addr.clone()),
-        client: Arc::new(tokio::sync::Mutex::new(None)),
-    };
+    let stor = StorageClient::new(&format!("http://{}", addr.clone()))?;
     let client = Box::<Bin>::new(Bin {
         _name: name,
         prefix,
-        storage: stor,
+        replicas: vec![stor],
     });
     tokio::time::sleep(Duration::from_millis(500)).await;
     client.set(&kv("hello", "hi")).await?;
@@ -341,14 +333,11 @@ async fn test_back_spawn_new_storage() -> TribResult<()> {
     let name = "jerry".to_string();
     let mut prefix = colon::escape("jerry".clone());
     prefix.push_str(&"::".to_string());
-    let stor = StorageClient {
-        addr: format!("http://{}", host),
-        client: Arc::new(tokio::sync::Mutex::new(None)),
-    };
+    let stor = StorageClient::new(&format!("http://{}", host))?;
     let client = Box::<Bin>::new(Bin {
         _name: name,
         prefix,
-        storage: stor,
+        replicas: vec![stor],
     });
     tokio::time::sleep(Duration::from_millis(500)).await;
     client.set(&kv("hello", "hi")).await?;
@@ -376,20 +365,17 @@ async fn test_concurrent_cli_ops() -> TribResult<()> {
     tokio::time::sleep(Duration::from_millis(500)).await;
     let client = Arc::new(client);
     let mut handles = vec![];
+    let stor = StorageClient::new(&format!("http://{}", DEFAULT_HOST))?;
     for _ in 0..5 {
-        let addr = format!("http://{}", DEFAULT_HOST);
+        let stor = stor.clone();
         let jh = tokio::spawn(async move {
             let name = "jerry".to_string();
             let mut prefix = colon::escape("jerry".clone());
             prefix.push_str(&"::".to_string());
-            let stor = StorageClient {
-                addr: addr,
-                client: Arc::new(tokio::sync::Mutex::new(None)),
-            };
             let client = Box::<Bin>::new(Bin {
                 _name: name,
                 prefix,
-                storage: stor,
+                replicas: vec![stor],
             });
             for _ in 0..10 {
                 if let Err(e) = client.list_append(&kv("lst", "item")).await {
@@ -425,3 +411,941 @@ async fn test_shutdown() -> TribResult<()> {
     };
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_replica_fallthrough() -> TribResult<()> {
+    let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+    let mut backs = vec![];
+    let mut shutdowns = vec![];
+    for _ in 0..2 {
+        let host = format!("127.0.0.1:{}", rand_port());
+        let (shut_tx, shut_rx) = tokio::sync::mpsc::channel(1);
+        let cfg = BackConfig {
+            addr: host.clone(),
+            storage: Box::new(MemStorage::default()),
+            ready: Some(tx.clone()),
+            shutdown: Some(shut_rx),
+        };
+        let handle = spawn_back(cfg);
+        assert!(rx.recv_timeout(Duration::from_secs(2))?);
+        backs.push(host);
+        shutdowns.push((shut_tx, handle));
+    }
+    let bc = scalable::new_replicated_bin_client(backs, 2).await?;
+    let client = bc.bin("jerry").await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    client.set(&kv("hello", "hi")).await?;
+    client.list_append(&kv("lst", "item")).await?;
+
+    let (shut_tx, handle) = shutdowns.remove(0);
+    let _ = shut_tx.send(()).await;
+    let _ = handle.await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(Some("hi".to_string()), client.get("hello").await?);
+    assert_eq!(vec!["item".to_string()], client.list_get("lst").await?.0);
+    assert!(client.set(&kv("hello", "bye")).await?);
+    assert_eq!(Some("bye".to_string()), client.get("hello").await?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_reconnect() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, srv, shutdown) = setup(Some(&host), None).await?;
+    let stor = StorageClient::new(&format!("http://{}", host))?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(stor.set(&kv("hello", "hi")).await?);
+    assert_eq!(Some("hi".to_string()), stor.get("hello").await?);
+
+    let _ = shutdown.send(()).await;
+    let _ = srv.await;
+    assert!(stor.get("hello").await.is_err());
+
+    // A backend coming back on the same address is picked up again
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(None, stor.get("hello").await?);
+    assert!(stor.set(&kv("hello", "back")).await?);
+    assert_eq!(Some("back".to_string()), stor.get("hello").await?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_batch_ops() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let alice = bc.bin("alice").await?;
+    let bob = bc.bin("bob").await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    alice.set(&kv("hello", "hi")).await?;
+    let keys = vec!["hello".to_string(), "bye".to_string()];
+    assert_eq!(
+        vec![Some("hi".to_string()), None],
+        alice.get_many(&keys).await?
+    );
+    assert_eq!(vec![None, None], bob.get_many(&keys).await?);
+
+    let kvs = vec![kv("lst", "a"), kv("lst", "b"), kv("other", "c")];
+    assert!(alice.list_append_many(&kvs).await?);
+    bob.list_append(&kv("lst", "d")).await?;
+    let lists = alice
+        .list_get_many(&["lst".to_string(), "other".to_string()])
+        .await?;
+    assert_eq!(vec!["a", "b"], lists[0].0);
+    assert_eq!(vec!["c"], lists[1].0);
+
+    let names = vec!["alice".to_string(), "bob".to_string(), "carol".to_string()];
+    let lists = bc.list_get_bins(&names, "lst").await?;
+    assert_eq!(vec!["a", "b"], lists[0].0);
+    assert_eq!(vec!["d"], lists[1].0);
+    assert!(lists[2].0.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_sign_up() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = Arc::new(scalable::new_front(bc).await?);
+    let mut handles = vec![];
+    for _ in 0..10 {
+        let front = front.clone();
+        handles.push(tokio::spawn(
+            async move { front.sign_up("alice").await.is_ok() },
+        ));
+    }
+    let mut signed_up = 0;
+    for handle in handles {
+        if handle.await? {
+            signed_up += 1;
+        }
+    }
+    assert_eq!(1, signed_up);
+
+    let mut handles = vec![];
+    for user in ["bob", "carol", "dave", "erin"] {
+        front.sign_up(user).await?;
+        let front = front.clone();
+        handles.push(tokio::spawn(async move {
+            front.follow("alice", user).await.is_ok()
+        }));
+    }
+    for handle in handles {
+        assert!(handle.await?);
+    }
+    let mut following = front.following("alice").await?;
+    following.sort();
+    assert_eq!(vec!["bob", "carol", "dave", "erin"], following);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_watch_bin() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let alice = bc.bin("alice").await?;
+    let bob = bc.bin("bob").await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let mut changes = alice.watch(&Pattern::default()).await?;
+    bob.list_append(&kv("tribs", "not alice")).await?;
+    alice.list_append(&kv("tribs", "hello")).await?;
+    alice.set(&kv("name", "Alice")).await?;
+
+    let change = changes.recv().await.unwrap();
+    assert_eq!(ChangeKind::ListAppend, change.kind);
+    assert_eq!("tribs", change.key);
+    assert_eq!("hello", change.value);
+    let change = changes.recv().await.unwrap();
+    assert_eq!(ChangeKind::Set, change.kind);
+    assert_eq!("name", change.key);
+    assert_eq!("Alice", change.value);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_fanout_home() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_fanout_front(bc).await?;
+    front.sign_up("alice").await?;
+    front.sign_up("bob").await?;
+    front.sign_up("carol").await?;
+
+    front.post("bob", "before", 0).await?;
+    front.follow("alice", "bob").await?;
+    front.follow("alice", "carol").await?;
+    let clock = front.home("alice").await?[0].clock;
+    front.post("carol", "after", clock).await?;
+    front.post("alice", "mine", clock).await?;
+    let home = front.home("alice").await?;
+    let messages = home.iter().map(|t| t.message.as_str()).collect::<Vec<_>>();
+    assert_eq!(3, messages.len());
+    assert_eq!("before", messages[0]);
+    assert!(messages.contains(&"after"));
+    assert!(messages.contains(&"mine"));
+    assert_eq!(1, front.home("bob").await?.len());
+
+    front.unfollow("alice", "bob").await?;
+    let home = front.home("alice").await?;
+    assert!(home.iter().all(|t| t.user != "bob"));
+    assert_eq!(2, home.len());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_fanout_home_trim() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+    let front = scalable::new_fanout_front(bc).await?;
+    for user in ["alice", "bob", "carol"] {
+        front.sign_up(user).await?;
+    }
+    front.follow("alice", "bob").await?;
+    front.follow("alice", "carol").await?;
+    front.mute("alice", "carol").await?;
+    for i in 0..101 {
+        front.post("bob", &format!("bob {}", i), 0).await?;
+    }
+    for i in 0..450 {
+        front.post("carol", &format!("carol {}", i), 0).await?;
+    }
+
+    // Trimming drops hidden tribs past the window, never shown ones
+    let home = front.home("alice").await?;
+    assert_eq!(100, home.len());
+    assert!(home.iter().all(|t| t.user == "bob"));
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let bin = bc.bin("alice").await?;
+    assert_eq!(101 + 400, bin.list_get("home").await?.0.len());
+
+    // and leaves enough behind to fill the timeline after a delete
+    front.delete_trib("bob", home[99].clock).await?;
+    assert_eq!(100, front.home("alice").await?.len());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_delete_edit_trib() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+    let pull = scalable::new_front(bc).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let push = scalable::new_fanout_front(bc).await?;
+    for (front, alice, bob) in [(&pull, "alice", "bob"), (&push, "carol", "dave")] {
+        front.sign_up(alice).await?;
+        front.sign_up(bob).await?;
+        front.follow(bob, alice).await?;
+        front.post(alice, "first", 0).await?;
+        front.post(alice, "second", 0).await?;
+        let tribs = front.tribs(alice).await?;
+        let (first, second) = (tribs[0].clock, tribs[1].clock);
+
+        front.edit_trib(alice, first, "first, edited").await?;
+        let home = front.home(bob).await?;
+        assert_eq!(2, home.len());
+        assert_eq!("first, edited", home[0].message);
+        assert_eq!(first, home[0].clock);
+        assert_eq!(tribs[0].time, home[0].time);
+
+        front.delete_trib(alice, second).await?;
+        assert_eq!(1, front.tribs(alice).await?.len());
+        assert_eq!(1, front.home(alice).await?.len());
+        assert_eq!(1, front.home(bob).await?.len());
+        assert!(front.delete_trib(alice, second).await.is_err());
+        assert!(front.edit_trib(bob, first, "not mine").await.is_err());
+        assert!(front
+            .edit_trib(alice, first, &"x".repeat(141))
+            .await
+            .is_err());
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_paging() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    front.sign_up("alice").await?;
+    front.sign_up("bob").await?;
+    front.follow("alice", "bob").await?;
+    for i in 0..130 {
+        front
+            .post(["alice", "bob"][i % 2], &format!("{}", i), 0)
+            .await?;
+    }
+    assert_eq!(65, front.tribs("alice").await?.len());
+    assert_eq!(100, front.home("alice").await?.len());
+
+    let mut seen = HashSet::new();
+    let mut cursor = "".to_string();
+    loop {
+        let page = front.home_before("alice", &cursor, 40).await?;
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() <= 40);
+        for t in page.iter() {
+            assert!(seen.insert(t.id.clone()));
+        }
+        cursor = page[0].id.clone();
+    }
+    assert_eq!(130, seen.len());
+
+    let page = front.tribs_before("bob", "", 1000).await?;
+    assert_eq!(65, page.len());
+    let older = front.tribs_before("bob", &page[10].id, 1000).await?;
+    assert_eq!(10, older.len());
+    assert_eq!(page[9].id, older[9].id);
+    assert!(front.tribs_before("bob", "bogus", 10).await.is_err());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_followers_and_stats() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let raw = scalable::new_bin_client(vec![host.clone()]).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    for user in ["alice", "bob", "carol", "dave"] {
+        front.sign_up(user).await?;
+    }
+    front.follow("bob", "alice").await?;
+    front.follow("carol", "alice").await?;
+    front.follow("dave", "alice").await?;
+    front.follow("alice", "bob").await?;
+    front.unfollow("dave", "alice").await?;
+    let err = front.unfollow("dave", "alice").await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TribblerError>(),
+        Some(TribblerError::NotFollowing(_, _))
+    ));
+    front.post("alice", "hello", 0).await?;
+    front.post("alice", "world", 0).await?;
+
+    let mut followers = front.followers("alice").await?;
+    followers.sort();
+    assert_eq!(vec!["bob", "carol"], followers);
+    assert_eq!(vec!["alice"], front.followers("bob").await?);
+    assert!(front.followers("dave").await?.is_empty());
+    assert!(front.followers("erin").await.is_err());
+    // unfollowing takes the follower out of the index
+    let index = raw.bin("alice").await?.list_get("followers").await?.0;
+    assert_eq!(2, index.len());
+    assert!(index.iter().all(|f| !f.contains("dave")));
+
+    let stats = front.user_stats("alice").await?;
+    assert_eq!(2, stats.followers);
+    assert_eq!(1, stats.following);
+    assert_eq!(2, stats.tribs);
+    let stats = front.user_stats("dave").await?;
+    assert_eq!((0, 0, 0), (stats.followers, stats.following, stats.tribs));
+    assert!(front.user_stats("erin").await.is_err());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_followers_backfill() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let raw = scalable::new_bin_client(vec![host.clone()]).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    for user in ["alice", "bob", "carol"] {
+        front.sign_up(user).await?;
+    }
+    // A follow logged before the followers index was kept
+    let follow = r#"{"user":"alice","followed":true,"timestamp":1}"#;
+    raw.bin("bob").await?.list_append(&kv("follows", follow)).await?;
+    // An index entry whose follow never made it into the log
+    let follower = r#"{"user":"carol","followed":true,"timestamp":0}"#;
+    raw.bin("alice").await?.list_append(&kv("followers", follower)).await?;
+
+    assert_eq!(vec!["bob"], front.followers("alice").await?);
+    assert_eq!(1, front.user_stats("alice").await?.followers);
+    front.follow("carol", "alice").await?;
+    let mut followers = front.followers("alice").await?;
+    followers.sort();
+    assert_eq!(vec!["bob", "carol"], followers);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_mentions_and_threads() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+    let front = scalable::new_front(bc).await?;
+    for user in ["alice", "bob", "carol"] {
+        front.sign_up(user).await?;
+    }
+    front
+        .post("alice", "hi @bob and @carol, @nobody", 0)
+        .await?;
+    front.post("bob", "talking to myself @bob", 0).await?;
+    let root = front.tribs("alice").await?[0].clone();
+    assert_eq!(vec![root.id.clone()], ids(front.mentions("bob").await?));
+    assert_eq!(1, front.mentions("carol").await?.len());
+    assert!(front.mentions("alice").await?.is_empty());
+
+    front
+        .reply("bob", &root.id, "hey @alice", root.clock)
+        .await?;
+    let reply = front.tribs("bob").await?[1].clone();
+    assert_eq!(root.id, reply.parent);
+    assert_eq!(vec![reply.id.clone()], ids(front.mentions("alice").await?));
+    front
+        .reply("carol", &reply.id, "me too", reply.clock)
+        .await?;
+    let nested = front.tribs("carol").await?[0].clone();
+    front
+        .reply("alice", &root.id, "anyone?", nested.clock)
+        .await?;
+    let last = front.tribs("alice").await?[1].clone();
+
+    let expected = vec![
+        root.id.clone(),
+        reply.id.clone(),
+        nested.id.clone(),
+        last.id.clone(),
+    ];
+    assert_eq!(expected, ids(front.thread(&nested.id).await?));
+    assert_eq!(expected, ids(front.thread(&root.id).await?));
+
+    front.edit_trib("alice", root.clock, "hi @carol").await?;
+    assert!(front.mentions("bob").await?.is_empty());
+    assert_eq!(1, front.mentions("carol").await?.len());
+    front.edit_trib("alice", root.clock, "hello @carol").await?;
+    let bin = |user: &str| {
+        let user = user.to_string();
+        let host = host.clone();
+        async move { scalable::new_bin_client(vec![host]).await?.bin(&user).await }
+    };
+    assert!(bin("bob").await?.list_get("mentions").await?.0.is_empty());
+    assert_eq!(
+        vec![root.id.clone()],
+        bin("carol").await?.list_get("mentions").await?.0
+    );
+
+    // deleting a trib takes it out of the mentions and reply lists
+    front.delete_trib("bob", reply.clock).await?;
+    assert!(bin("alice").await?.list_get("mentions").await?.0.is_empty());
+    let replies = format!("replies::{}", root.clock);
+    assert_eq!(
+        vec![last.id.clone()],
+        bin("alice").await?.list_get(&replies).await?.0
+    );
+    assert_eq!(
+        vec![root.id.clone(), last.id.clone()],
+        ids(front.thread(&root.id).await?)
+    );
+    assert!(front.reply("bob", "alice-999", "?", 0).await.is_err());
+    assert!(front.thread("bogus").await.is_err());
+    Ok(())
+}
+
+fn ids(tribs: Vec<Arc<tribbler::trib::Trib>>) -> Vec<String> {
+    tribs.iter().map(|t| t.id.clone()).collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_retrib() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    for user in ["alice", "bob", "carol"] {
+        front.sign_up(user).await?;
+    }
+    front.follow("carol", "bob").await?;
+    front.post("alice", "original", 0).await?;
+    let original = front.tribs("alice").await?[0].clone();
+
+    front.retrib("bob", &original.id, original.clock).await?;
+    assert!(front.retrib("bob", &original.id, 0).await.is_err());
+    let home = front.home("carol").await?;
+    assert_eq!(1, home.len());
+    assert_eq!("alice", home[0].user);
+    assert_eq!("original", home[0].message);
+    assert_eq!(original.clock, home[0].clock);
+    assert_eq!("bob", home[0].retrib_by);
+    assert_eq!(original.id, home[0].retrib_of);
+
+    // retribbing a retrib reposts the original
+    front.retrib("carol", &home[0].id, 0).await?;
+    assert_eq!(original.id, front.tribs("carol").await?[0].retrib_of);
+    assert_eq!(2, front.user_stats("alice").await?.retribbed);
+
+    front.edit_trib("alice", original.clock, "edited").await?;
+    assert_eq!("edited", front.home("carol").await?[0].message);
+    let record = front.tribs("bob").await?[0].clone();
+    // a retrib's message is the original author's
+    assert!(front
+        .edit_trib("bob", parse_clock(&record.id), "#mine")
+        .await
+        .is_err());
+    assert!(front.tag_timeline("mine").await?.is_empty());
+    assert_eq!("edited", front.home("carol").await?[0].message);
+    front.delete_trib("bob", parse_clock(&record.id)).await?;
+    assert_eq!(1, front.user_stats("alice").await?.retribbed);
+    front.delete_trib("alice", original.clock).await?;
+    assert!(front.home("carol").await?.is_empty());
+    assert_eq!(0, front.user_stats("alice").await?.retribbed);
+    Ok(())
+}
+
+fn parse_clock(id: &str) -> u64 {
+    tribbler::trib::parse_trib_id(id).unwrap().1
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_tag_timeline() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    front.sign_up("alice").await?;
+    front.sign_up("bob").await?;
+    front.post("alice", "learning #Rust", 0).await?;
+    front.post("bob", "#rust and #tokio", 0).await?;
+    front.post("bob", "nothing here", 0).await?;
+
+    let rust = front.tag_timeline("rust").await?;
+    assert_eq!(2, rust.len());
+    assert!(rust[0].clock < rust[1].clock);
+    assert_eq!("alice", rust[0].user);
+    assert_eq!(1, front.tag_timeline("#Tokio").await?.len());
+    assert!(front.tag_timeline("nothing").await?.is_empty());
+
+    let first = rust[0].clock;
+    front.edit_trib("alice", first, "learning #go").await?;
+    assert_eq!(1, front.tag_timeline("rust").await?.len());
+    assert_eq!(1, front.tag_timeline("go").await?.len());
+    front.delete_trib("bob", rust[1].clock).await?;
+    assert!(front.tag_timeline("rust").await?.is_empty());
+    assert!(front.tag_timeline("tokio").await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_tag_trim() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let raw = scalable::new_bin_client(vec![host.clone()]).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    front.sign_up("alice").await?;
+    for i in 0..401 {
+        front.post("alice", &format!("#busy {}", i), 0).await?;
+    }
+
+    let busy = front.tag_timeline("busy").await?;
+    assert_eq!(100, busy.len());
+    assert_eq!("#busy 400", busy[99].message);
+    let ids = raw.bin("#busy").await?.list_get("tagged").await?.0;
+    assert_eq!(400, ids.len());
+
+    // the window left behind still fills the timeline after a delete
+    front.delete_trib("alice", busy[99].clock).await?;
+    let busy = front.tag_timeline("busy").await?;
+    assert_eq!(100, busy.len());
+    assert_eq!("#busy 399", busy[99].message);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_search() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    front.sign_up("alice").await?;
+    front.sign_up("bob").await?;
+    front.post("alice", "Rust is fun", 0).await?;
+    front.post("bob", "rust and #tokio", 0).await?;
+    front.post("bob", "tokio only", 0).await?;
+    front.post("alice", "nothing to see", 0).await?;
+
+    let found = front.search("tokio RUST", 10).await?;
+    let messages = found.iter().map(|t| t.message.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        vec!["rust and #tokio", "tokio only", "Rust is fun"],
+        messages
+    );
+    assert_eq!(1, front.search("rust", 1).await?.len());
+    assert!(front.search("", 10).await?.is_empty());
+    assert!(front.search("missing", 10).await?.is_empty());
+
+    let clock = found[1].clock;
+    front.edit_trib("bob", clock, "changed my mind").await?;
+    assert_eq!(2, front.search("tokio rust", 10).await?.len());
+    assert_eq!(1, front.search("mind", 10).await?.len());
+    front.delete_trib("bob", found[0].clock).await?;
+    assert_eq!(1, front.search("tokio rust", 10).await?.len());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_password_login() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+    let front = scalable::new_front(bc).await?;
+    assert!(front.sign_up_with_password("alice", "short").await.is_err());
+    front.sign_up_with_password("alice", "hunter22").await?;
+    assert!(front.sign_up_with_password("alice", "hunter33").await.is_err());
+    front.sign_up("bob").await?;
+    assert!(front.sign_up_with_password("bob", "hunter22").await.is_err());
+
+    // a taken name never gets a credential, not even for a moment
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let bob = bc.bin("bob").await?;
+    assert!(bob.get("credential").await?.unwrap_or_default().is_empty());
+
+    // losing the race for the credential registers nothing
+    bc.bin("dave")
+        .await?
+        .set(&kv("credential", "taken"))
+        .await?;
+    assert!(front.sign_up_with_password("dave", "hunter22").await.is_err());
+    assert!(!front.list_users().await?.contains(&"dave".to_string()));
+
+    assert!(front.login("alice", "hunter33").await.is_err());
+    assert!(front.login("bob", "").await.is_err());
+    assert!(front.login("bob", "hunter22").await.is_err());
+    assert!(front.login("carol", "hunter22").await.is_err());
+    let token = front.login("alice", "hunter22").await?;
+    let other = front.login("alice", "hunter22").await?;
+    assert_ne!(token, other);
+    assert_eq!("alice", front.session_user(&token).await?);
+
+    // the user part of a token can't be swapped for another user
+    let forged = token.replacen("alice", "bob", 1);
+    assert!(front.session_user(&forged).await.is_err());
+    assert!(front.session_user("").await.is_err());
+
+    front.logout(&token).await?;
+    front.logout(&token).await?;
+    assert!(front.session_user(&token).await.is_err());
+    assert_eq!("alice", front.session_user(&other).await?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_username_policy() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+    let ascii = scalable::new_front(bc).await?;
+    assert!(ascii.sign_up("élodie").await.is_err());
+    ascii.sign_up("paypal").await?;
+    ascii.post("paypal", &"👍".repeat(140), 0).await?;
+    assert!(ascii.post("paypal", &"👍".repeat(141), 0).await.is_err());
+
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let policy = tribbler::validate::UsernamePolicy {
+        unicode: true,
+        reject_confusables: true,
+    };
+    let front = scalable::new_front_with_policy(bc, false, policy).await?;
+    front.sign_up("élodie").await?;
+    assert!(front.sign_up("Élodie").await.is_err());
+    // a Cyrillic "а", mixing scripts
+    assert!(front.sign_up("pаypal").await.is_err());
+    front.sign_up("alice1").await?;
+    assert!(front.sign_up("alicel").await.is_err());
+    assert!(front.list_users().await?.contains(&"élodie".to_string()));
+    assert!(!front.list_users().await?.contains(&"alicel".to_string()));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_profiles() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host]).await?;
+    let front = scalable::new_front(bc).await?;
+    front.sign_up("alice").await?;
+    assert!(front.get_profile("bob").await.is_err());
+    let joined = front.get_profile("alice").await?.joined;
+    assert!(joined > 0);
+    assert_eq!("", front.get_profile("alice").await?.bio);
+
+    let profile = tribbler::trib::Profile {
+        display_name: "Alice 🌻".to_string(),
+        bio: "👍".repeat(tribbler::trib::MAX_BIO_LEN),
+        avatar_url: "https://example.com/alice.png".to_string(),
+        joined: 42,
+    };
+    front.update_profile("alice", &profile).await?;
+    let stored = front.get_profile("alice").await?;
+    assert_eq!(profile.display_name, stored.display_name);
+    assert_eq!(profile.bio, stored.bio);
+    assert_eq!(profile.avatar_url, stored.avatar_url);
+    // the join time can't be rewritten
+    assert_eq!(joined, stored.joined);
+
+    let mut too_long = profile.clone();
+    too_long.bio.push('!');
+    assert!(front.update_profile("alice", &too_long).await.is_err());
+    let mut unsafe_url = profile.clone();
+    unsafe_url.avatar_url = "javascript:alert(1)".to_string();
+    assert!(front.update_profile("alice", &unsafe_url).await.is_err());
+    assert!(front.update_profile("bob", &profile).await.is_err());
+    assert_eq!(stored, front.get_profile("alice").await?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_delete_user() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    for fanout in [false, true] {
+        let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+        let front = match fanout {
+            false => scalable::new_front(bc).await?,
+            true => scalable::new_fanout_front(bc).await?,
+        };
+        let (alice, bob, carol, tag) = match fanout {
+            false => ("alice", "bob", "carol", "gone"),
+            true => ("alice2", "bob2", "carol2", "gone2"),
+        };
+        front.sign_up_with_password(alice, "hunter22").await?;
+        front.sign_up(bob).await?;
+        front.sign_up(carol).await?;
+        front.follow(bob, alice).await?;
+        front.follow(alice, carol).await?;
+        front.post(carol, "hello", 0).await?;
+        front
+            .post(alice, &format!("farewell #{} @{}", tag, carol), 0)
+            .await?;
+        let id = front.tribs(alice).await?[0].id.clone();
+        front.retrib(bob, &id, 0).await?;
+        front.retrib(alice, &front.tribs(carol).await?[0].id, 0).await?;
+        let hello = front.tribs(carol).await?[0].id.clone();
+        front.like(alice, &hello).await?;
+        front.like(carol, &id).await?;
+        let token = front.login(alice, "hunter22").await?;
+
+        let archive = front.export_user(alice).await?;
+        assert_eq!(alice, archive.user);
+        assert_eq!(2, archive.tribs.len());
+        assert_eq!(vec![carol.to_string()], archive.following);
+        assert_eq!(vec![bob.to_string()], archive.followers);
+        assert!(archive.profile.joined > 0);
+
+        front.delete_user(alice).await?;
+        assert!(front.delete_user(alice).await.is_err());
+        assert!(front.export_user(alice).await.is_err());
+        assert!(front.get_profile(alice).await.is_err());
+        assert!(front.session_user(&token).await.is_err());
+        assert!(!front.list_users().await?.contains(&alice.to_string()));
+        assert!(front.following(bob).await?.is_empty());
+        assert!(front.followers(carol).await?.is_empty());
+        assert!(front.home(bob).await?.is_empty());
+        assert_eq!(1, front.home(carol).await?.len());
+        assert_eq!(0, front.user_stats(carol).await?.retribbed);
+        assert!(front.tag_timeline(tag).await?.is_empty());
+        assert!(front.search("farewell", 10).await?.is_empty());
+        assert!(front.mentions(carol).await?.is_empty());
+        assert_eq!(vec![0], front.like_counts(std::slice::from_ref(&hello)).await?);
+        assert_eq!(vec![false], front.liked(carol, std::slice::from_ref(&id)).await?);
+
+        // the name is free again, with nothing left over
+        front.sign_up(alice).await?;
+        assert!(front.tribs(alice).await?.is_empty());
+        assert!(front.home(alice).await?.is_empty());
+        assert_eq!(tribbler::trib::UserStats::default(), front.user_stats(alice).await?);
+        assert!(front.login(alice, "hunter22").await.is_err());
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_delete_user_elsewhere() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let raw = scalable::new_bin_client(vec![host.clone()]).await?;
+    let front = scalable::new_front(scalable::new_bin_client(vec![host.clone()]).await?).await?;
+    let other = scalable::new_front(scalable::new_bin_client(vec![host]).await?).await?;
+    for i in 0..25 {
+        front.sign_up(&format!("user{:02}", i)).await?;
+    }
+    // A follow logged before the followers index was kept
+    let follow = r#"{"user":"user00","followed":true,"timestamp":1}"#;
+    raw.bin("user01").await?.list_append(&kv("follows", follow)).await?;
+    assert!(other.list_users().await?.contains(&"user00".to_string()));
+
+    front.delete_user("user00").await?;
+    assert!(!other.list_users().await?.contains(&"user00".to_string()));
+    front.sign_up("user00").await?;
+    assert!(other.followers("user00").await?.is_empty());
+    assert!(other.following("user01").await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_block_mute() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    for fanout in [false, true] {
+        let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+        let front = match fanout {
+            false => scalable::new_front(bc).await?,
+            true => scalable::new_fanout_front(bc).await?,
+        };
+        let (alice, bob, carol) = match fanout {
+            false => ("alice", "bob", "carol"),
+            true => ("alice2", "bob2", "carol2"),
+        };
+        for user in [alice, bob, carol] {
+            front.sign_up(user).await?;
+        }
+        front.follow(alice, bob).await?;
+        front.follow(alice, carol).await?;
+        front.follow(bob, alice).await?;
+        front.post(carol, "from carol", 0).await?;
+        front.post(bob, "from bob", 0).await?;
+        let id = front.tribs(carol).await?[0].id.clone();
+        front.retrib(bob, &id, 0).await?;
+        assert_eq!(3, front.home(alice).await?.len());
+
+        // muting hides their tribs and retribs of them, but keeps following
+        front.mute(alice, carol).await?;
+        front.mute(alice, carol).await?;
+        assert_eq!(vec![carol.to_string()], front.muted(alice).await?);
+        assert!(front.is_following(alice, carol).await?);
+        let home = front.home(alice).await?;
+        assert_eq!(1, home.len());
+        assert_eq!("from bob", home[0].message);
+        assert_eq!(1, front.home_before(alice, "", 10).await?.len());
+        front.unmute(alice, carol).await?;
+        assert!(front.muted(alice).await?.is_empty());
+        assert_eq!(3, front.home(alice).await?.len());
+
+        // blocking undoes follows both ways and keeps them undone
+        front.block(alice, bob).await?;
+        assert_eq!(vec![bob.to_string()], front.blocked(alice).await?);
+        assert!(!front.is_following(alice, bob).await?);
+        assert!(!front.is_following(bob, alice).await?);
+        assert!(front.follow(bob, alice).await.is_err());
+        assert!(front.follow(alice, bob).await.is_err());
+        let home = front.home(alice).await?;
+        assert_eq!(1, home.len());
+        assert_eq!("from carol", home[0].message);
+        front.unblock(alice, bob).await?;
+        front.follow(bob, alice).await?;
+
+        // a deleted user does not stay blocked or muted under a reused name
+        front.block(alice, carol).await?;
+        front.mute(bob, carol).await?;
+        front.delete_user(carol).await?;
+        front.sign_up(carol).await?;
+        assert!(front.blocked(alice).await?.is_empty());
+        assert!(front.muted(bob).await?.is_empty());
+
+        assert!(front.block(alice, alice).await.is_err());
+        assert!(front.mute(alice, "nobody").await.is_err());
+        assert!(front.blocked("nobody").await.is_err());
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_likes() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+    let front = scalable::new_front(bc).await?;
+    for user in ["alice", "bob", "carol"] {
+        front.sign_up(user).await?;
+    }
+    front.post("alice", "like me", 0).await?;
+    let id = front.tribs("alice").await?[0].id.clone();
+    front.retrib("bob", &id, 0).await?;
+    let retrib_id = front.tribs("bob").await?[0].id.clone();
+
+    // one like per user, however many times they like it
+    front.like("bob", &id).await?;
+    front.like("bob", &id).await?;
+    front.like("carol", &retrib_id).await?;
+    let ids = vec![id.clone(), retrib_id.clone(), "nobody-1".to_string()];
+    assert_eq!(vec![2, 0, 0], front.like_counts(&ids).await?);
+    assert_eq!(vec![true, false, false], front.liked("carol", &ids).await?);
+    assert_eq!(vec![false, false, false], front.liked("alice", &ids).await?);
+    assert_eq!(vec![id.clone()], front.export_user("bob").await?.liked);
+
+    front.unlike("bob", &id).await?;
+    front.unlike("bob", &id).await?;
+    assert_eq!(vec![1], front.like_counts(&ids[..1]).await?);
+    assert!(front.like("nobody", &id).await.is_err());
+    assert!(front.like("bob", "alice-999").await.is_err());
+    assert!(front.liked("nobody", &ids).await.is_err());
+
+    // likes go with the trib, and with the user
+    front.delete_user("carol").await?;
+    assert_eq!(vec![0], front.like_counts(&ids[..1]).await?);
+    front.like("bob", &id).await?;
+    let clock = front.tribs("alice").await?[0].clock;
+    front.delete_trib("alice", clock).await?;
+    assert!(front.export_user("bob").await?.liked.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_watch_home() -> TribResult<()> {
+    let host = format!("127.0.0.1:{}", rand_port());
+    let (_client, _srv, _shut) = setup(Some(&host), None).await?;
+    for fanout in [false, true] {
+        let bc = scalable::new_bin_client(vec![host.clone()]).await?;
+        let front = match fanout {
+            false => scalable::new_front(bc).await?,
+            true => scalable::new_fanout_front(bc).await?,
+        };
+        let (alice, bob, carol) = match fanout {
+            false => ("alice", "bob", "carol"),
+            true => ("alice2", "bob2", "carol2"),
+        };
+        front.sign_up(alice).await?;
+        front.sign_up(bob).await?;
+        front.sign_up(carol).await?;
+        front.follow(alice, bob).await?;
+        assert!(front.watch_home("nobody").await.is_err());
+
+        let mut home = front.watch_home(alice).await?;
+        front.post(bob, "from bob", 0).await?;
+        assert_eq!("from bob", next_trib(&mut home).await?.unwrap().message);
+        front.post(carol, "not followed", 0).await?;
+        front.post(alice, "from alice", 0).await?;
+        assert_eq!("from alice", next_trib(&mut home).await?.unwrap().message);
+
+        let id = front.tribs(carol).await?[0].id.clone();
+        front.retrib(bob, &id, 0).await?;
+        let retrib = next_trib(&mut home).await?.unwrap();
+        assert_eq!(carol, retrib.user);
+        assert_eq!(bob, retrib.retrib_by);
+
+        // following someone else ends the stream
+        front.follow(alice, carol).await?;
+        assert!(next_trib(&mut home).await?.is_none());
+    }
+    Ok(())
+}
+
+/// waits for the next trib on a stream returned by `watch_home`
+async fn next_trib(
+    home: &mut tokio::sync::mpsc::Receiver<Arc<tribbler::trib::Trib>>,
+) -> TribResult<Option<Arc<tribbler::trib::Trib>>> {
+    Ok(tokio::time::timeout(Duration::from_secs(5), home.recv()).await?)
+}
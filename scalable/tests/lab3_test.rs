@@ -0,0 +1,354 @@
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::Duration,
+};
+
+use log::LevelFilter;
+use tokio::{sync::mpsc::Sender as MpscSender, task::JoinHandle};
+
+use scalable::{
+    self,
+    binstorage::{
+        bin_prefix, replica_indexes, Bin, BinStorageClient, Membership, KEY_DIVERGED,
+        KEY_MEMBERSHIP,
+    },
+    keeperserver::Keeper,
+    kvstore::{self, client::StorageClient},
+    ring::Ring,
+};
+use tribbler::addr::rand::rand_port;
+use tribbler::{
+    config::{BackConfig, Config},
+    disk::DiskStorage,
+    err::{TribResult, TribblerError},
+    storage::{KeyList, KeyString, KeyValue, MemStorage, Storage},
+};
+
+fn kv(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn spawn_back(addr: &str, tx: Sender<bool>) -> (JoinHandle<TribResult<()>>, MpscSender<()>) {
+    spawn_back_with(addr, Box::new(MemStorage::default()), tx)
+}
+
+fn spawn_back_with(
+    addr: &str,
+    storage: Box<dyn Storage>,
+    tx: Sender<bool>,
+) -> (JoinHandle<TribResult<()>>, MpscSender<()>) {
+    let (shut_tx, shut_rx) = tokio::sync::mpsc::channel(1);
+    let cfg = BackConfig {
+        addr: addr.to_string(),
+        storage,
+        ready: Some(tx),
+        shutdown: Some(shut_rx),
+    };
+    (tokio::spawn(kvstore::serve_back(cfg)), shut_tx)
+}
+
+fn spawn_keeper(cfg: &Config, i: usize, tx: Sender<bool>) -> TribResult<MpscSender<()>> {
+    let (shut_tx, shut_rx) = tokio::sync::mpsc::channel(1);
+    let kc = cfg.keeper_config(i, Some(tx), Some(shut_rx))?;
+    tokio::spawn(scalable::serve_keeper(kc));
+    Ok(shut_tx)
+}
+
+async fn setup(
+    backs: usize,
+    replicas: usize,
+) -> TribResult<(Config, Vec<(JoinHandle<TribResult<()>>, MpscSender<()>)>)> {
+    let _ = env_logger::builder()
+        .default_format()
+        .filter_level(LevelFilter::Error)
+        .try_init();
+    let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+    let cfg = Config {
+        backs: (0..backs)
+            .map(|_| format!("127.0.0.1:{}", rand_port()))
+            .collect(),
+        keepers: vec![format!("127.0.0.1:{}", rand_port())],
+        replicas,
+    };
+    let mut handles = vec![];
+    for back in cfg.backs.iter() {
+        handles.push(spawn_back(back, tx.clone()));
+        assert!(rx.recv_timeout(Duration::from_secs(2))?);
+    }
+    let kc = cfg.keeper_config(0, Some(tx), None)?;
+    tokio::spawn(scalable::serve_keeper(kc));
+    assert!(rx.recv_timeout(Duration::from_secs(5))?);
+    Ok((cfg, handles))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_migrate_on_crash() -> TribResult<()> {
+    let (cfg, handles) = setup(3, 2).await?;
+    let bc = scalable::new_replicated_bin_client(cfg.backs.clone(), 2).await?;
+    let bin = bc.bin("alice").await?;
+    bin.set(&kv("name", "alice")).await?;
+    bin.list_append(&kv("tribs", "hello")).await?;
+
+    // Crash the bin's first replica, and let the keeper move the bin onto
+    // the third backend
+    let ring = Ring::new(&cfg.backs);
+    let placed = replica_indexes(&ring, "alice", 2, &Membership::default());
+    let _ = handles[placed[0]].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    // Crash the second replica as well: the bin must have been copied
+    let _ = handles[placed[1]].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let bin = bc.bin("alice").await?;
+    assert_eq!(Some("alice".to_string()), bin.get("name").await?);
+    assert_eq!(vec!["hello".to_string()], bin.list_get("tribs").await?.0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_catch_up_on_rejoin() -> TribResult<()> {
+    let (cfg, mut handles) = setup(2, 2).await?;
+    let bc = scalable::new_replicated_bin_client(cfg.backs.clone(), 2).await?;
+    let bin = bc.bin("bob").await?;
+    bin.list_append(&kv("tribs", "one")).await?;
+
+    // Restart a backend with empty storage
+    let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+    let _ = handles[0].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    handles[0] = spawn_back(&cfg.backs[0], tx);
+    assert!(rx.recv_timeout(Duration::from_secs(2))?);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    // Once the other one is gone, the rejoined backend serves the bin
+    let _ = handles[1].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let bin = bc.bin("bob").await?;
+    assert_eq!(vec!["one".to_string()], bin.list_get("tribs").await?.0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_clear_on_rejoin() -> TribResult<()> {
+    let (cfg, mut handles) = setup(2, 2).await?;
+    let dir = std::env::temp_dir().join(format!("scalable-rejoin-{}", rand_port()));
+    let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+
+    // Move the first backend onto disk, so it keeps its data across restarts
+    let _ = handles[0].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    handles[0] = spawn_back_with(
+        &cfg.backs[0],
+        Box::new(DiskStorage::open(&dir).await?),
+        tx.clone(),
+    );
+    assert!(rx.recv_timeout(Duration::from_secs(2))?);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    let bc = scalable::new_replicated_bin_client(cfg.backs.clone(), 2).await?;
+    let front = scalable::new_front(bc).await?;
+    front.sign_up("alice").await?;
+    front.post("alice", "hello", 0).await?;
+
+    // Delete the user while the disk-backed backend is down
+    let _ = handles[0].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    front.delete_user("alice").await?;
+    handles[0] = spawn_back_with(&cfg.backs[0], Box::new(DiskStorage::open(&dir).await?), tx);
+    assert!(rx.recv_timeout(Duration::from_secs(2))?);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    // Once the other one is gone, nothing of the user comes back
+    let _ = handles[1].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let bc = scalable::new_replicated_bin_client(cfg.backs.clone(), 2).await?;
+    assert!(bc.bin("alice").await?.list_get("tribs").await?.0.is_empty());
+    let front = scalable::new_front(bc).await?;
+    assert!(!front.list_users().await?.contains(&"alice".to_string()));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_repair_missed_write() -> TribResult<()> {
+    let (cfg, _handles) = setup(2, 2).await?;
+    let ring = Ring::new(&cfg.backs);
+    let placed = replica_indexes(&ring, "carol", 2, &Membership::default());
+    let client = |i: usize| StorageClient::new(&format!("http://{}", cfg.backs[i]));
+    let gone = StorageClient::new(&format!("http://127.0.0.1:{}", rand_port()))?;
+
+    // A replica failing without being marked dead misses the write, which
+    // the keeper then copies onto the bin's other replica
+    let bin = Bin {
+        _name: "carol".to_string(),
+        prefix: bin_prefix("carol"),
+        replicas: vec![client(placed[0])?, gone.clone()],
+    };
+    assert!(bin.list_append(&kv("tribs", "one")).await?);
+    assert_eq!(
+        vec!["carol".to_string()],
+        client(placed[0])?.list_get(KEY_DIVERGED).await?.0
+    );
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let key = format!("{}tribs", bin_prefix("carol"));
+    assert_eq!(
+        vec!["one".to_string()],
+        client(placed[1])?.list_get(&key).await?.0
+    );
+    assert!(client(placed[0])?
+        .list_get(KEY_DIVERGED)
+        .await?
+        .0
+        .is_empty());
+
+    // The same goes for the replica serving reads
+    let bin = Bin {
+        _name: "carol".to_string(),
+        prefix: bin_prefix("carol"),
+        replicas: vec![gone.clone(), client(placed[1])?],
+    };
+    assert!(bin.list_append(&kv("tribs", "two")).await?);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(
+        vec!["one".to_string(), "two".to_string()],
+        client(placed[0])?.list_get(&key).await?.0
+    );
+
+    // And so do removals, which must not come back from the replica that
+    // missed them
+    let both = Bin {
+        _name: "carol".to_string(),
+        prefix: bin_prefix("carol"),
+        replicas: vec![client(placed[0])?, client(placed[1])?],
+    };
+    assert!(both.set(&kv("session", "live")).await?);
+    let bin = Bin {
+        _name: "carol".to_string(),
+        prefix: bin_prefix("carol"),
+        replicas: vec![client(placed[0])?, gone],
+    };
+    assert_eq!(1, bin.list_remove(&kv("tribs", "one")).await?);
+    assert!(bin.set(&kv("session", "")).await?);
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(
+        vec!["two".to_string()],
+        client(placed[1])?.list_get(&key).await?.0
+    );
+    let session = format!("{}session", bin_prefix("carol"));
+    assert_eq!(None, client(placed[1])?.get(&session).await?);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_keeper_failover() -> TribResult<()> {
+    let _ = env_logger::builder()
+        .default_format()
+        .filter_level(LevelFilter::Error)
+        .try_init();
+    let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+    let cfg = Config {
+        backs: (0..2)
+            .map(|_| format!("127.0.0.1:{}", rand_port()))
+            .collect(),
+        keepers: (0..2)
+            .map(|_| format!("127.0.0.1:{}", rand_port()))
+            .collect(),
+        replicas: 2,
+    };
+    let mut backs = vec![];
+    for back in cfg.backs.iter() {
+        backs.push(spawn_back(back, tx.clone()));
+        assert!(rx.recv_timeout(Duration::from_secs(2))?);
+    }
+    // The first keeper started is the oldest, so it becomes the primary
+    let primary = spawn_keeper(&cfg, 0, tx.clone())?;
+    assert!(rx.recv_timeout(Duration::from_secs(5))?);
+    let _standby = spawn_keeper(&cfg, 1, tx)?;
+    assert!(rx.recv_timeout(Duration::from_secs(5))?);
+
+    let _ = primary.send(()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    // The standby must have taken over and noticed the crashed backend
+    let _ = backs[0].1.send(()).await;
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    let client = kvstore::new_client(&format!("http://{}", cfg.backs[1])).await?;
+    let view = client.get(KEY_MEMBERSHIP).await?.unwrap_or_default();
+    let view = serde_json::from_str::<Membership>(&view)?;
+    assert_eq!(vec![false, true], view.alive);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_newest_view_wins() -> TribResult<()> {
+    let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+    let backs = (0..2)
+        .map(|_| format!("127.0.0.1:{}", rand_port()))
+        .collect::<Vec<String>>();
+    let mut handles = vec![];
+    for back in backs.iter() {
+        handles.push(spawn_back(back, tx.clone()));
+        assert!(rx.recv_timeout(Duration::from_secs(2))?);
+    }
+
+    // The first backend missed the view that marked it down
+    let views = [
+        Membership {
+            version: 1,
+            alive: vec![true, true],
+            ..Membership::default()
+        },
+        Membership {
+            version: 2,
+            alive: vec![false, true],
+            ..Membership::default()
+        },
+    ];
+    for (back, view) in backs.iter().zip(views.iter()) {
+        let client = kvstore::new_client(&format!("http://{}", back)).await?;
+        client
+            .set(&kv(KEY_MEMBERSHIP, &serde_json::to_string(view)?))
+            .await?;
+    }
+    let bc = BinStorageClient::new(backs.clone(), 2)?;
+    assert_eq!(vec![backs[1].clone()], bc.replica_addrs("alice").await);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_stale_keeper_fenced() -> TribResult<()> {
+    let (tx, rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
+    let backs = (0..2)
+        .map(|_| format!("127.0.0.1:{}", rand_port()))
+        .collect::<Vec<String>>();
+    let mut handles = vec![];
+    for back in backs.iter() {
+        handles.push(spawn_back(back, tx.clone()));
+        assert!(rx.recv_timeout(Duration::from_secs(2))?);
+    }
+
+    // A second keeper takes over while the first still thinks it leads
+    let mut old = Keeper::new(backs.clone(), 2)?;
+    old.init().await?;
+    let mut new = Keeper::new(backs.clone(), 2)?;
+    new.init().await?;
+    assert!(new.view().term > old.view().term);
+
+    // The old one must neither run a round nor publish its view of a crash
+    let _ = handles[0].1.send(()).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let err = old.tick().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TribblerError>(),
+        Some(TribblerError::StaleTerm(_))
+    ));
+    let client = kvstore::new_client(&format!("http://{}", backs[1])).await?;
+    let view = client.get(KEY_MEMBERSHIP).await?.unwrap_or_default();
+    let view = serde_json::from_str::<Membership>(&view)?;
+    assert_eq!(new.view().term, view.term);
+    assert_eq!(vec![true, true], view.alive);
+    Ok(())
+}
@@ -3,6 +3,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build_server(true)
         .build_client(true)
         .format(true)
+        // a streaming rpc's stream type is named after the rpc, as in
+        // `watchStream`
+        .server_mod_attribute(
+            "rpc",
+            "#[allow(non_camel_case_types, clippy::mixed_attributes_style)]",
+        )
         .out_dir("src")
         .compile(&["proto/rpc.proto"], &["proto"])?;
     Ok(())
@@ -0,0 +1,148 @@
+//! Password hashing and session tokens used for password sign-up and login,
+//! shared by the [crate::trib::Server] implementations.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::Hmac;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::err::{TribResult, TribblerError};
+
+/// Minimum length of a password
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// Seconds a session stays valid after logging in
+pub const SESSION_TTL: u64 = 7 * 24 * 60 * 60;
+
+/// PBKDF2 rounds used for new credentials. Each [Credential] records its
+/// own count, so raising this does not lock out existing users.
+const HASH_ROUNDS: u32 = 10_000;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const SECRET_LEN: usize = 32;
+
+/// A salted hash of a user's password, as stored by a server. The password
+/// itself is never stored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    pub salt: String,
+    pub hash: String,
+    pub rounds: u32,
+}
+
+impl Credential {
+    /// Hashes `password` with a new random salt
+    pub fn new(password: &str) -> Credential {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let salt = to_hex(&salt);
+        let hash = hash_password(password, &salt, HASH_ROUNDS);
+        Credential {
+            salt,
+            hash,
+            rounds: HASH_ROUNDS,
+        }
+    }
+
+    /// A credential no password matches, to verify against when a user has
+    /// none. Verifying it costs the same as a real one, so the time a failed
+    /// login takes doesn't tell whether the account exists.
+    pub fn dummy() -> Credential {
+        Credential {
+            salt: "0".repeat(SALT_LEN * 2),
+            hash: "0".repeat(HASH_LEN * 2),
+            rounds: HASH_ROUNDS,
+        }
+    }
+
+    /// Checks `password` against this credential
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = hash_password(password, &self.salt, self.rounds);
+        // compare every byte, so the time taken leaks nothing about the hash
+        hash.len() == self.hash.len()
+            && hash
+                .bytes()
+                .zip(self.hash.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+fn hash_password(password: &str, salt: &str, rounds: u32) -> String {
+    let mut out = [0u8; HASH_LEN];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt.as_bytes(), rounds, &mut out);
+    to_hex(&out)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns an error when `password` can't be used for a new account
+pub fn check_password(password: &str) -> TribResult<()> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(Box::new(TribblerError::PasswordTooShort));
+    }
+    Ok(())
+}
+
+/// Returns a new session token for `user`. A token is the user followed
+/// by a random secret, so the session can be looked up in the storage of
+/// that user; clients should treat it as opaque.
+pub fn new_token(user: &str) -> String {
+    let mut secret = [0u8; SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    format!("{}:{}", user, to_hex(&secret))
+}
+
+/// Splits a token built by [new_token] into the user and the digest the
+/// session is stored under. Servers store digests rather than tokens, so
+/// that reading the storage is not enough to take over a session.
+pub fn parse_token(token: &str) -> Option<(&str, String)> {
+    let (user, secret) = token.split_once(':')?;
+    if secret.len() != 2 * SECRET_LEN {
+        return None;
+    }
+    Some((user, to_hex(&Sha256::digest(secret.as_bytes()))))
+}
+
+/// Returns the current time in seconds since the Unix epoch, as used for
+/// session expiry
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_password, new_token, parse_token, Credential};
+
+    #[test]
+    fn credentials() {
+        let c = Credential::new("correct horse");
+        assert!(c.verify("correct horse"));
+        assert!(!c.verify("correct horsE"));
+        assert!(!c.verify(""));
+        // salted, so the same password never hashes the same twice
+        assert_ne!(c, Credential::new("correct horse"));
+        assert!(!Credential::dummy().verify(""));
+        assert!(!Credential::dummy().verify("correct horse"));
+        assert!(check_password("1234567").is_err());
+        assert!(check_password("12345678").is_ok());
+    }
+
+    #[test]
+    fn tokens() {
+        let t = new_token("alice");
+        let (user, digest) = parse_token(&t).unwrap();
+        assert_eq!("alice", user);
+        assert!(!t.contains(&digest));
+        assert_ne!(t, new_token("alice"));
+        assert!(parse_token("alice").is_none());
+        assert!(parse_token("alice:1234").is_none());
+    }
+}
@@ -15,6 +15,9 @@ use crate::storage::Storage;
 
 pub const DEFAULT_CONFIG_LOCATION: &str = "bins.json";
 
+/// the default number of backends every bin is replicated on
+pub const DEFAULT_REPLICAS: usize = 3;
+
 /// a struct which represents the configuration for a particular storage backend
 pub struct BackConfig {
     /// the address `<host>:<port>` combination to serve on
@@ -50,6 +53,8 @@ impl Debug for BackConfig {
 pub struct KeeperConfig {
     /// The addresses of back-ends
     pub backs: Vec<String>,
+    /// The number of back-ends each bin is replicated on
+    pub replicas: usize,
     /// The addresses of keepers
     pub addrs: Vec<String>,
     /// The index of this back-end
@@ -72,11 +77,29 @@ impl KeeperConfig {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 /// A config file defining the backend and keeper network addresses
 pub struct Config {
     pub backs: Vec<String>,
     pub keepers: Vec<String>,
+    /// the number of backends each bin is written to. Config files without
+    /// this field use [DEFAULT_REPLICAS].
+    #[serde(default = "default_replicas")]
+    pub replicas: usize,
+}
+
+fn default_replicas() -> usize {
+    DEFAULT_REPLICAS
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backs: vec![],
+            keepers: vec![],
+            replicas: DEFAULT_REPLICAS,
+        }
+    }
 }
 
 impl Config {
@@ -126,6 +149,12 @@ impl Config {
         self.keepers.len()
     }
 
+    /// gets the number of backends each bin is actually replicated on, which
+    /// is never more than the number of backends in the config.
+    pub fn replica_count(&self) -> usize {
+        self.replicas.clamp(1, self.backs.len().max(1))
+    }
+
     /// build a [BackConfig] for the given index `i` in the list of backend
     /// addresses. `i` must be a valid index in the list of backends.
     ///
@@ -161,6 +190,7 @@ impl Config {
     ) -> TribResult<KeeperConfig> {
         Ok(KeeperConfig {
             backs: self.backs.clone(),
+            replicas: self.replica_count(),
             addrs: self.keepers.clone(),
             this: i,
             id: SystemTime::now()
@@ -0,0 +1,456 @@
+//! module containing a durable [Storage] implementation which keeps its data
+//! in memory, and on disk as a snapshot plus an append-only write-ahead log.
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::err::TribResult;
+use crate::storage::{
+    AppendCondition, Change, KeyList, KeyString, KeyValue, List, MemStorage, Pattern, Storage,
+};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const WAL_FILE: &str = "wal.log";
+
+/// number of log entries after which the log is folded into a new snapshot
+pub const COMPACT_EVERY: usize = 10_000;
+
+/// how many clock values are reserved with a single log entry. The clock
+/// restarts from the end of the last reservation, so it never goes
+/// backwards even though not every [Storage::clock] call is logged.
+const CLOCK_RESERVATION: u64 = 1_000;
+
+/// a single mutation recorded in the write-ahead log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum LogEntry {
+    Set(KeyValuePair),
+    ListAppend(KeyValuePair),
+    /// a [KeyList::list_append_many], which is replayed all or nothing
+    ListAppendMany(Vec<KeyValuePair>),
+    ListRemove(KeyValuePair),
+    /// no clock value at or above this one has been handed out yet
+    ClockReserve(u64),
+}
+
+/// a [LogEntry] along with its position in the log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LogRecord {
+    seq: u64,
+    entry: LogEntry,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct KeyValuePair {
+    key: String,
+    value: String,
+}
+
+impl From<&KeyValue> for KeyValuePair {
+    fn from(kv: &KeyValue) -> Self {
+        KeyValuePair {
+            key: kv.key.clone(),
+            value: kv.value.clone(),
+        }
+    }
+}
+
+impl From<KeyValuePair> for KeyValue {
+    fn from(kv: KeyValuePair) -> Self {
+        KeyValue {
+            key: kv.key,
+            value: kv.value,
+        }
+    }
+}
+
+/// the full contents of a [DiskStorage] at the time of the last compaction
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Snapshot {
+    kvs: HashMap<String, String>,
+    kv_list: HashMap<String, Vec<String>>,
+    clock: u64,
+    /// sequence number of the last log record folded into this snapshot
+    seq: u64,
+}
+
+/// the open log file along with the bookkeeping needed to compact it
+#[derive(Debug)]
+struct Wal {
+    /// shared with the blocking tasks doing the file I/O, see [blocking]
+    file: Arc<File>,
+    entries: usize,
+    seq: u64,
+    clock_reserved: u64,
+}
+
+/// A [Storage] which survives restarts. Every mutation is appended to a
+/// write-ahead log and synced to disk before it is applied to an in-memory
+/// [MemStorage], which serves all reads. Once the log grows past
+/// [COMPACT_EVERY] entries, the state is written out as a snapshot and the
+/// log starts over. The disk is only ever touched from blocking tasks, so
+/// the async runtime keeps serving while a write is synced.
+///
+/// ```rust
+/// use tribbler::disk::DiskStorage;
+/// use tribbler::storage::{KeyString, KeyValue};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let dir = std::env::temp_dir().join("tribbler-disk-doctest");
+///     let storage = DiskStorage::open(&dir).await.unwrap();
+///     storage.set(&KeyValue::new("hello", "world")).await.unwrap();
+///     drop(storage);
+///
+///     let storage = DiskStorage::open(&dir).await.unwrap();
+///     assert_eq!(Some("world".to_string()), storage.get("hello").await.unwrap());
+/// #   std::fs::remove_dir_all(&dir).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DiskStorage {
+    dir: PathBuf,
+    mem: Arc<MemStorage>,
+    wal: Mutex<Wal>,
+}
+
+impl DiskStorage {
+    /// Opens the storage kept in `dir`, creating the directory if needed.
+    /// The last snapshot is loaded and the log replayed on top of it, after
+    /// which the two are compacted into a fresh snapshot.
+    pub async fn open<P: AsRef<Path>>(dir: P) -> TribResult<DiskStorage> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mem = MemStorage::new();
+        let mut clock_reserved = 0;
+        let mut seq = 0;
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let snapshot = serde_json::from_slice::<Snapshot>(&fs::read(&snapshot_path)?)?;
+            clock_reserved = snapshot.clock;
+            seq = snapshot.seq;
+            *mem.kvs.write().map_err(|e| e.to_string())? = snapshot.kvs;
+            *mem.kv_list.write().map_err(|e| e.to_string())? = snapshot
+                .kv_list
+                .into_iter()
+                .map(|(k, v)| (k, List(v)))
+                .collect();
+        }
+
+        let wal_path = dir.join(WAL_FILE);
+        if wal_path.exists() {
+            for line in BufReader::new(File::open(&wal_path)?).lines() {
+                // A torn write at the tail of the log is a mutation that was
+                // never acknowledged, so it is dropped along with the rest
+                let record = match serde_json::from_str::<LogRecord>(&line?) {
+                    Ok(record) => record,
+                    Err(_) => break,
+                };
+                // Records already in the snapshot are left over from a crash
+                // between writing the snapshot and emptying the log
+                if record.seq <= seq {
+                    continue;
+                }
+                seq = record.seq;
+                match record.entry {
+                    LogEntry::Set(kv) => {
+                        mem.set(&kv.into()).await?;
+                    }
+                    LogEntry::ListAppend(kv) => {
+                        mem.list_append(&kv.into()).await?;
+                    }
+                    LogEntry::ListAppendMany(kvs) => {
+                        let kvs = kvs.into_iter().map(KeyValue::from).collect::<Vec<_>>();
+                        mem.list_append_many(&kvs).await?;
+                    }
+                    LogEntry::ListRemove(kv) => {
+                        mem.list_remove(&kv.into()).await?;
+                    }
+                    LogEntry::ClockReserve(c) => clock_reserved = clock_reserved.max(c),
+                }
+            }
+        }
+        *mem.clock.write().map_err(|e| e.to_string())? = clock_reserved;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)?;
+        let storage = DiskStorage {
+            dir,
+            mem: Arc::new(mem),
+            wal: Mutex::new(Wal {
+                file: Arc::new(file),
+                entries: 0,
+                seq,
+                clock_reserved,
+            }),
+        };
+        storage.compact().await?;
+        Ok(storage)
+    }
+
+    /// Writes the current state out as a new snapshot and empties the log.
+    pub async fn compact(&self) -> TribResult<()> {
+        let mut wal = self.wal.lock().await;
+        self.compact_locked(&mut wal).await
+    }
+
+    async fn compact_locked(&self, wal: &mut Wal) -> TribResult<()> {
+        let mem = self.mem.clone();
+        let dir = self.dir.clone();
+        let file = wal.file.clone();
+        let (clock, seq) = (wal.clock_reserved, wal.seq);
+        // The log lock is held throughout, so no mutation lands on the
+        // storage while the snapshot is taken
+        blocking(move || {
+            let snapshot = Snapshot {
+                kvs: mem.kvs.read().map_err(|e| e.to_string())?.clone(),
+                kv_list: mem
+                    .kv_list
+                    .read()
+                    .map_err(|e| e.to_string())?
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.0.clone()))
+                    .collect(),
+                clock,
+                seq,
+            };
+            // Write the snapshot next to the old one and swap it in, so a
+            // crash half way through leaves the old snapshot and log intact
+            let tmp_path = dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&serde_json::to_vec(&snapshot)?)?;
+            tmp.sync_all()?;
+            fs::rename(&tmp_path, dir.join(SNAPSHOT_FILE))?;
+
+            file.set_len(0)?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .await?;
+        wal.entries = 0;
+        Ok(())
+    }
+
+    /// Appends `entry` to the log and syncs it to disk
+    async fn log(&self, wal: &mut Wal, entry: LogEntry) -> TribResult<()> {
+        let record = LogRecord {
+            seq: wal.seq + 1,
+            entry,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        let file = wal.file.clone();
+        blocking(move || {
+            file.as_ref().write_all(&line)?;
+            file.sync_data()?;
+            Ok(())
+        })
+        .await?;
+        wal.seq = record.seq;
+        wal.entries += 1;
+        Ok(())
+    }
+
+    /// Compacts the log if it has grown past [COMPACT_EVERY] entries
+    async fn maybe_compact(&self, wal: &mut Wal) -> TribResult<()> {
+        if wal.entries >= COMPACT_EVERY {
+            self.compact_locked(wal).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the disk I/O `f` on the blocking thread pool, off the async runtime
+async fn blocking<T, F>(f: F) -> TribResult<T>
+where
+    F: FnOnce() -> TribResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await?
+}
+
+#[async_trait]
+impl KeyString for DiskStorage {
+    async fn get(&self, key: &str) -> TribResult<Option<String>> {
+        self.mem.get(key).await
+    }
+
+    async fn set(&self, kv: &KeyValue) -> TribResult<bool> {
+        let mut wal = self.wal.lock().await;
+        self.log(&mut wal, LogEntry::Set(kv.into())).await?;
+        let res = self.mem.set(kv).await?;
+        self.maybe_compact(&mut wal).await?;
+        Ok(res)
+    }
+
+    async fn keys(&self, p: &Pattern) -> TribResult<List> {
+        self.mem.keys(p).await
+    }
+
+    async fn compare_and_set(&self, key: &str, expected: &str, value: &str) -> TribResult<bool> {
+        // Every mutation holds the log lock, so nothing can change the value
+        // between the check and the write
+        let mut wal = self.wal.lock().await;
+        if self.mem.get(key).await?.unwrap_or_default() != expected {
+            return Ok(false);
+        }
+        let kv = KeyValue::new(key, value);
+        self.log(&mut wal, LogEntry::Set((&kv).into())).await?;
+        let res = self.mem.set(&kv).await?;
+        self.maybe_compact(&mut wal).await?;
+        Ok(res)
+    }
+
+    async fn get_many(&self, keys: &[String]) -> TribResult<Vec<Option<String>>> {
+        self.mem.get_many(keys).await
+    }
+}
+
+#[async_trait]
+impl KeyList for DiskStorage {
+    async fn list_get(&self, key: &str) -> TribResult<List> {
+        self.mem.list_get(key).await
+    }
+
+    async fn list_append(&self, kv: &KeyValue) -> TribResult<bool> {
+        let mut wal = self.wal.lock().await;
+        self.log(&mut wal, LogEntry::ListAppend(kv.into())).await?;
+        let res = self.mem.list_append(kv).await?;
+        self.maybe_compact(&mut wal).await?;
+        Ok(res)
+    }
+
+    async fn list_remove(&self, kv: &KeyValue) -> TribResult<u32> {
+        let mut wal = self.wal.lock().await;
+        self.log(&mut wal, LogEntry::ListRemove(kv.into())).await?;
+        let res = self.mem.list_remove(kv).await?;
+        self.maybe_compact(&mut wal).await?;
+        Ok(res)
+    }
+
+    async fn list_keys(&self, p: &Pattern) -> TribResult<List> {
+        self.mem.list_keys(p).await
+    }
+
+    async fn list_append_if(&self, kv: &KeyValue, cond: &AppendCondition) -> TribResult<bool> {
+        let mut wal = self.wal.lock().await;
+        if !cond.allows(&self.mem.list_get(&kv.key).await?.0, &kv.value) {
+            return Ok(false);
+        }
+        self.log(&mut wal, LogEntry::ListAppend(kv.into())).await?;
+        let res = self.mem.list_append(kv).await?;
+        self.maybe_compact(&mut wal).await?;
+        Ok(res)
+    }
+
+    async fn list_get_many(&self, keys: &[String]) -> TribResult<Vec<List>> {
+        self.mem.list_get_many(keys).await
+    }
+
+    async fn list_append_many(&self, kvs: &[KeyValue]) -> TribResult<bool> {
+        let mut wal = self.wal.lock().await;
+        let entry = LogEntry::ListAppendMany(kvs.iter().map(KeyValuePair::from).collect());
+        self.log(&mut wal, entry).await?;
+        let res = self.mem.list_append_many(kvs).await?;
+        self.maybe_compact(&mut wal).await?;
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Storage for DiskStorage {
+    async fn clock(&self, at_least: u64) -> TribResult<u64> {
+        let mut wal = self.wal.lock().await;
+        let ret = self.mem.clock(at_least).await?;
+        if ret >= wal.clock_reserved {
+            let reserved = ret.saturating_add(CLOCK_RESERVATION);
+            self.log(&mut wal, LogEntry::ClockReserve(reserved)).await?;
+            wal.clock_reserved = reserved;
+        }
+        Ok(ret)
+    }
+
+    async fn watch(&self, p: &Pattern) -> TribResult<mpsc::Receiver<Change>> {
+        self.mem.watch(p).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::{
+        err::TribResult,
+        storage::{KeyList, KeyString, KeyValue, Storage},
+    };
+
+    use super::DiskStorage;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("tribbler-disk-{}-{}", name, rand::random::<u64>()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn disk_survives_reopen() -> TribResult<()> {
+        let dir = temp_dir("reopen");
+        let storage = DiskStorage::open(&dir).await?;
+        storage.set(&KeyValue::new("k", "v")).await?;
+        storage.set(&KeyValue::new("gone", "v")).await?;
+        storage.set(&KeyValue::new("gone", "")).await?;
+        storage.list_append(&KeyValue::new("l", "a")).await?;
+        storage.list_append(&KeyValue::new("l", "b")).await?;
+        storage.list_remove(&KeyValue::new("l", "a")).await?;
+        let kvs = vec![KeyValue::new("l", "c"), KeyValue::new("m", "a")];
+        storage.list_append_many(&kvs).await?;
+        drop(storage);
+
+        let storage = DiskStorage::open(&dir).await?;
+        assert_eq!(Some("v".to_string()), storage.get("k").await?);
+        assert_eq!(None, storage.get("gone").await?);
+        assert_eq!(vec!["b", "c"], storage.list_get("l").await?.0);
+        assert_eq!(vec!["a"], storage.list_get("m").await?.0);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disk_clock_never_goes_back() -> TribResult<()> {
+        let dir = temp_dir("clock");
+        let storage = DiskStorage::open(&dir).await?;
+        storage.clock(5000).await?;
+        let last = storage.clock(0).await?;
+        drop(storage);
+
+        let storage = DiskStorage::open(&dir).await?;
+        assert!(storage.clock(0).await? > last);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disk_compact() -> TribResult<()> {
+        let dir = temp_dir("compact");
+        let storage = DiskStorage::open(&dir).await?;
+        storage.list_append(&KeyValue::new("l", "a")).await?;
+        storage.compact().await?;
+        storage.list_append(&KeyValue::new("l", "b")).await?;
+        drop(storage);
+
+        let storage = DiskStorage::open(&dir).await?;
+        assert_eq!(2, storage.list_get("l").await?.0.len());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -12,6 +12,9 @@ pub enum TribblerError {
     UsernameTaken(String),
     /// when a username is invalid in any way
     InvalidUsername(String),
+    /// when a username looks like one already taken, see
+    /// [crate::validate::UsernamePolicy::reject_confusables]
+    ConfusableUsername(String),
     /// generic error for anything that occurs with RPC communication
     RpcError(String),
     /// raised when too a user tries to follow more than
@@ -21,12 +24,44 @@ pub enum TribblerError {
     AlreadyFollowing(String, String),
     /// raised when a user tries to unfollow a user they are not following
     NotFollowing(String, String),
+    /// raised when a user tries to follow a user who blocked them, or whom
+    /// they blocked. Holds who blocked whom.
+    Blocked(String, String),
     /// raised when a trib message exceeds [crate::trib::MAX_TRIB_LEN]
     TribTooLong,
+    /// raised when a field of a profile exceeds its limit, e.g.
+    /// [crate::trib::MAX_BIO_LEN]
+    ProfileFieldTooLong(String),
+    /// when an avatar URL is not an http or https one
+    InvalidAvatarUrl(String),
+    /// when a user has posted no trib with the given clock
+    TribDoesNotExist(String, u64),
+    /// raised when a user tries to retrib a trib they already retribbed
+    AlreadyRetribbed(String, String),
+    /// raised when a user tries to edit one of their retribs, whose message
+    /// is the original author's
+    RetribNotEditable(String, u64),
+    /// when a password is shorter than [crate::auth::MIN_PASSWORD_LEN]
+    PasswordTooShort,
+    /// when logging in with a user that does not exist, has no password or
+    /// has a different password
+    InvalidCredentials,
+    /// when a session token is unknown, expired or was logged out
+    InvalidSession,
+    /// when a request acts on behalf of a user it holds no session of
+    NotSignedIn(String),
+    /// when a request acts on behalf of a user while holding a session of
+    /// someone else. Holds the two, in that order.
+    SignedInAsOther(String, String),
+    /// when a trib id or pagination cursor can't be parsed
+    InvalidTribId(String),
     /// when someone tries to follow or check if a user is following themselves
     WhoWhom(String),
     /// when there are no more seq numbers to give out
     MaxedSeq,
+    /// when a keeper finds that another one took over as primary after the
+    /// term it holds began
+    StaleTerm(u64),
     /// catch-all error for other issues
     Unknown(String),
 }
@@ -37,14 +72,43 @@ impl Display for TribblerError {
             TribblerError::UserDoesNotExist(x) => format!("user \"{}\" does not exist", x),
             TribblerError::UsernameTaken(x) => format!("username \"{}\" already taken", x),
             TribblerError::InvalidUsername(x) => format!("username \"{}\" is invalid", x),
+            TribblerError::ConfusableUsername(x) => {
+                format!("username \"{}\" looks like one already taken", x)
+            }
             TribblerError::RpcError(x) => format!("rpc error: {}", x),
             TribblerError::FollowingTooMany => "following too many users".to_string(),
             TribblerError::AlreadyFollowing(who, whom) => {
                 format!("{} already following {}", who, whom)
             }
             TribblerError::NotFollowing(who, whom) => format!("{} doesn't follow {}", who, whom),
+            TribblerError::Blocked(who, whom) => format!("{} has blocked {}", who, whom),
             TribblerError::TribTooLong => "tribbler post exceed character limit".to_string(),
+            TribblerError::ProfileFieldTooLong(x) => {
+                format!("profile {} exceeds character limit", x)
+            }
+            TribblerError::InvalidAvatarUrl(x) => format!("avatar url \"{}\" is invalid", x),
+            TribblerError::TribDoesNotExist(who, clock) => {
+                format!("{} has no trib with clock {}", who, clock)
+            }
+            TribblerError::AlreadyRetribbed(who, id) => {
+                format!("{} already retribbed {}", who, id)
+            }
+            TribblerError::RetribNotEditable(who, clock) => {
+                format!("the trib of {} with clock {} is a retrib", who, clock)
+            }
+            TribblerError::PasswordTooShort => format!(
+                "password must be at least {} characters",
+                crate::auth::MIN_PASSWORD_LEN
+            ),
+            TribblerError::InvalidCredentials => "wrong username or password".to_string(),
+            TribblerError::InvalidSession => "session is invalid or expired".to_string(),
+            TribblerError::NotSignedIn(x) => format!("not signed in as {}", x),
+            TribblerError::SignedInAsOther(who, other) => {
+                format!("signed in as {}, not as {}", other, who)
+            }
+            TribblerError::InvalidTribId(x) => format!("trib id \"{}\" is invalid", x),
             TribblerError::WhoWhom(x) => format!("user {} can't follow themself", x),
+            TribblerError::StaleTerm(x) => format!("keeper term {} was superseded", x),
             TribblerError::Unknown(x) => format!("unknown error: {}", x),
             x => format!("{:?}", x),
         };
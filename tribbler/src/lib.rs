@@ -13,11 +13,15 @@
     html_favicon_url = "https://upload.wikimedia.org/wikipedia/commons/thumb/f/f8/Creative-Tail-Animal-penguin.svg/128px-Creative-Tail-Animal-penguin.svg.png?20160314145218"
 )]
 pub mod addr;
+pub mod auth;
 pub mod colon;
 pub mod config;
+pub mod disk;
 pub mod err;
 pub mod ref_impl;
 /// protobuf-generated RPC stubs and message structs
 pub mod rpc;
+pub mod search;
 pub mod storage;
 pub mod trib;
+pub mod validate;
@@ -12,10 +12,18 @@ use std::{
 };
 
 use async_trait::async_trait;
+use tokio::sync::{broadcast, mpsc};
 
 use crate::{
+    auth::{check_password, new_token, now, parse_token, Credential, SESSION_TTL},
     err::{TribResult, TribblerError},
-    trib::{is_valid_username, Server, Trib, MAX_TRIB_FETCH, MAX_TRIB_LEN, MIN_LIST_USER},
+    search::{rank, tokenize},
+    storage::WATCH_BUFFER,
+    trib::{
+        as_retrib, hashtags, normalize_tag, page_before, parse_trib_id, trib_id, Profile, Server,
+        Trib, UserArchive, UserStats, MAX_TRIB_FETCH, MIN_LIST_USER,
+    },
+    validate::{check_profile, check_trib, confusable_skeleton, UsernamePolicy},
 };
 
 /// The [User] type holds the data on tribs the user has posted along with
@@ -24,8 +32,15 @@ use crate::{
 struct User {
     following: HashSet<String>,
     followers: HashSet<String>,
+    // (who, clock of the trib of this user they retribbed)
+    retribbed: HashSet<(String, u64)>,
     seq_tribs: Vec<SeqTrib>,
     tribs: Vec<Arc<Trib>>,
+    // none when the user signed up without a password
+    credential: Option<Credential>,
+    profile: Profile,
+    blocked: HashSet<String>,
+    muted: HashSet<String>,
 }
 
 /// A [Trib] type with an additional sequence number
@@ -61,8 +76,13 @@ impl User {
         User {
             following: HashSet::new(),
             followers: HashSet::new(),
+            retribbed: HashSet::new(),
             seq_tribs: vec![],
             tribs: vec![],
+            credential: None,
+            profile: Profile::default(),
+            blocked: HashSet::new(),
+            muted: HashSet::new(),
         }
     }
 
@@ -91,23 +111,50 @@ impl User {
         self.followers.remove(who);
     }
 
+    /// Checks whether this user has muted or blocked who posted or
+    /// reposted `trib`, or whose trib it reposts
+    fn hides(&self, trib: &Trib) -> bool {
+        let original = parse_trib_id(&trib.retrib_of).map(|(author, _)| author);
+        [trib.user.as_str(), trib.retrib_by.as_str()]
+            .into_iter()
+            .chain(original)
+            .any(|u| self.muted.contains(u) || self.blocked.contains(u))
+    }
+
     /// lists the [User]s that this user follows
     fn list_following(&self) -> Vec<String> {
         self.following.iter().map(String::clone).collect()
     }
 
+    /// lists the [User]s that follow this user
+    fn list_followers(&self) -> Vec<String> {
+        self.followers.iter().map(String::clone).collect()
+    }
+
     /// instructs this [User] to post a new [Trib] with the given parameters
     /// returns a reference to the posted [Trib]
     ///
     /// Note: `time` refers to Unix time. In other words, time since epoch in
     /// milliseconds
-    fn post(&mut self, who: &str, msg: &str, seq: u64, time: u64) -> Arc<Trib> {
+    fn post(
+        &mut self,
+        who: &str,
+        msg: &str,
+        parent: &str,
+        retrib_of: &str,
+        seq: u64,
+        time: u64,
+    ) -> Arc<Trib> {
         // make the new trib
         let trib = Arc::new(Trib {
             user: who.to_string(),
             message: msg.to_string(),
             time,
             clock: seq,
+            id: trib_id(who, seq),
+            parent: parent.to_string(),
+            retrib_of: retrib_of.to_string(),
+            retrib_by: "".to_string(),
         });
         // append sequential number
         let seq_trib = SeqTrib {
@@ -121,6 +168,30 @@ impl User {
         trib
     }
 
+    /// removes the [Trib] posted at `clock`
+    /// returns the removed [Trib], if there was one
+    fn delete_trib(&mut self, clock: u64) -> Option<Arc<Trib>> {
+        let pos = self.tribs.iter().position(|t| t.clock == clock)?;
+        self.seq_tribs.retain(|st| st.seq != clock);
+        self.retribbed.retain(|(_, c)| *c != clock);
+        Some(self.tribs.remove(pos))
+    }
+
+    /// replaces the [Trib] posted at `clock` with one carrying `msg`
+    /// returns a reference to the new [Trib], if there was one to replace
+    fn edit_trib(&mut self, clock: u64, msg: &str) -> Option<Arc<Trib>> {
+        let pos = self.tribs.iter().position(|t| t.clock == clock)?;
+        let trib = Arc::new(Trib {
+            message: msg.to_string(),
+            ..(*self.tribs[pos]).clone()
+        });
+        self.tribs[pos] = trib.clone();
+        for st in self.seq_tribs.iter_mut().filter(|st| st.seq == clock) {
+            st.trib = trib.clone();
+        }
+        Some(trib)
+    }
+
     /// Gets the list of [Trib]s posted by this [User]
     fn list_tribs(&self) -> &[Arc<Trib>] {
         let ntrib = self.tribs.len();
@@ -132,6 +203,38 @@ impl User {
     }
 }
 
+/// Finds the [Trib] whose id is `id`
+fn find_trib(users: &HashMap<String, User>, id: &str) -> TribResult<Arc<Trib>> {
+    let (user, clock) = match parse_trib_id(id) {
+        Some(x) => x,
+        None => return Err(Box::new(TribblerError::InvalidTribId(id.to_string()))),
+    };
+    match users
+        .get(user)
+        .and_then(|u| u.tribs.iter().find(|t| t.clock == clock))
+    {
+        Some(t) => Ok(t.clone()),
+        None => Err(Box::new(TribblerError::TribDoesNotExist(
+            user.to_string(),
+            clock,
+        ))),
+    }
+}
+
+/// Shows retribs among `tribs` as the tribs they repost, dropping those
+/// whose original has been deleted
+fn render(users: &HashMap<String, User>, tribs: Vec<Arc<Trib>>) -> Vec<Arc<Trib>> {
+    tribs
+        .into_iter()
+        .filter_map(|t| match t.retrib_of.as_str() {
+            "" => Some(t),
+            id => find_trib(users, id)
+                .ok()
+                .map(|original| Arc::new(as_retrib(&t, &original))),
+        })
+        .collect()
+}
+
 /// The [RefServer] is a reference implementation for the [crate::trib::Server]
 ///
 /// This struct should be able to be used across threads when wrapped with an
@@ -164,6 +267,15 @@ impl User {
 pub struct RefServer {
     users: Arc<RwLock<HashMap<String, User>>>,
     homes: Arc<RwLock<HashMap<String, Vec<Arc<Trib>>>>>,
+    // ids of the tribs tagged with each hashtag
+    tags: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    // the users liking each trib, by its id
+    likes: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    // (user, expiry) of each session, by the digest of its token
+    sessions: Arc<RwLock<HashMap<String, (String, u64)>>>,
+    // every trib posted or edited, for [Server::watch_home]
+    posts: broadcast::Sender<Arc<Trib>>,
+    policy: UsernamePolicy,
     seq: AtomicU64,
 }
 
@@ -173,10 +285,166 @@ impl RefServer {
         RefServer {
             users: Arc::new(RwLock::new(HashMap::new())),
             homes: Arc::new(RwLock::new(HashMap::new())),
+            tags: Arc::new(RwLock::new(HashMap::new())),
+            likes: Arc::new(RwLock::new(HashMap::new())),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            posts: broadcast::channel(WATCH_BUFFER).0,
+            policy: UsernamePolicy::default(),
             seq: AtomicU64::new(0),
         }
     }
 
+    /// Creates a [RefServer] with no data, which accepts the usernames
+    /// `policy` accepts
+    pub fn with_policy(policy: UsernamePolicy) -> RefServer {
+        RefServer {
+            policy,
+            ..RefServer::new()
+        }
+    }
+
+    /// registers a new user, who can log in with the password `credential`
+    /// was made from unless it is none
+    fn add_user(&self, user: &str, credential: Option<Credential>) -> TribResult<()> {
+        let mut users = self.users.write().unwrap();
+        self.policy.check(user)?;
+        if users.contains_key(user) {
+            return Err(Box::new(TribblerError::UsernameTaken(user.to_string())));
+        }
+        if self.policy.reject_confusables {
+            let skeleton = confusable_skeleton(user);
+            if users.keys().any(|u| confusable_skeleton(u) == skeleton) {
+                return Err(Box::new(TribblerError::ConfusableUsername(
+                    user.to_string(),
+                )));
+            }
+        }
+        let mut u = User::new();
+        u.credential = credential;
+        u.profile.joined = now();
+        users.insert(user.to_string(), u);
+        let mut homes = self.homes.write().unwrap();
+        homes.insert(user.to_string(), vec![]);
+        Ok(())
+    }
+
+    /// adds the like of `who` to the trib whose id is `trib_id`, or to the
+    /// original when it is a retrib, or takes it back unless `like`
+    fn set_like(&self, who: &str, trib_id: &str, like: bool) -> TribResult<()> {
+        let users = self.users.read().unwrap();
+        if !users.contains_key(who) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let trib = find_trib(&users, trib_id)?;
+        let id = match trib.retrib_of.as_str() {
+            "" => trib.id.clone(),
+            original => find_trib(&users, original)?.id.clone(),
+        };
+        let mut likes = self.likes.write().unwrap();
+        if like {
+            likes.entry(id).or_default().insert(who.to_string());
+        } else if let Some(likers) = likes.get_mut(&id) {
+            likers.remove(who);
+        }
+        Ok(())
+    }
+
+    /// posts a [Trib], in reply to the one whose id is `parent` unless it is
+    /// empty, or as a retrib of the one whose id is `retrib_of` unless that
+    /// is empty
+    fn post_trib(
+        &self,
+        who: &str,
+        post: &str,
+        parent: &str,
+        retrib_of: &str,
+        clock: u64,
+    ) -> TribResult<()> {
+        check_trib(post)?;
+        let mut users = self.users.write().unwrap();
+        if !parent.is_empty() {
+            find_trib(&users, parent)?;
+        }
+        // Checked before anything is recorded, so that a failed post leaves
+        // nothing behind, such as the guard against retribbing twice
+        if self.seq.load(atomic::Ordering::SeqCst) == u64::MAX {
+            return Err(Box::new(TribblerError::MaxedSeq));
+        }
+        let mut retrib_of = retrib_of.to_string();
+        if !retrib_of.is_empty() {
+            if !users.contains_key(who) {
+                return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+            }
+            let mut original = find_trib(&users, &retrib_of)?;
+            if !original.retrib_of.is_empty() {
+                original = find_trib(&users, &original.retrib_of)?;
+            }
+            let author = users.get_mut(&original.user).unwrap();
+            if !author.retribbed.insert((who.to_string(), original.clock)) {
+                return Err(Box::new(TribblerError::AlreadyRetribbed(
+                    who.to_string(),
+                    original.id.clone(),
+                )));
+            }
+            retrib_of = original.id.clone();
+        }
+        match users.get_mut(who) {
+            Some(user) => {
+                let _ = self.seq.fetch_update(
+                    atomic::Ordering::SeqCst,
+                    atomic::Ordering::SeqCst,
+                    |v| {
+                        if v < clock {
+                            Some(clock)
+                        } else {
+                            None
+                        }
+                    },
+                );
+
+                let trib = user.post(
+                    who,
+                    post,
+                    parent,
+                    &retrib_of,
+                    self.seq.fetch_add(1, atomic::Ordering::SeqCst),
+                    SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)?
+                        .as_secs(),
+                );
+                // add it to the timeline of my followers
+                let mut homes = self.homes.write().unwrap();
+                for follower in user.followers.iter() {
+                    homes
+                        .entry(follower.to_string())
+                        .and_modify(|e| e.push(trib.clone()));
+                }
+                // add it to my own timeline
+                homes
+                    .entry(who.to_string())
+                    .and_modify(|e| e.push(trib.clone()));
+                self.retag(&trib.id, &trib.message);
+                for t in render(&users, vec![trib]) {
+                    let _ = self.posts.send(t);
+                }
+                Ok(())
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+    }
+
+    /// files the trib whose id is `id` under the hashtags of `msg`, and
+    /// only those
+    fn retag(&self, id: &str, msg: &str) {
+        let mut tags = self.tags.write().unwrap();
+        for ids in tags.values_mut() {
+            ids.retain(|x| x != id);
+        }
+        for tag in hashtags(msg) {
+            tags.entry(tag).or_default().push(id.to_string());
+        }
+    }
+
     /// rebuilds the users' homepage based on the current set of [SeqTrib]s and
     /// other users' tribs
     fn rebuild_home(&self, who: &User, users: &HashMap<String, User>) -> Vec<Arc<Trib>> {
@@ -206,18 +474,53 @@ impl Default for RefServer {
 #[async_trait]
 impl Server for RefServer {
     async fn sign_up(&self, user: &str) -> TribResult<()> {
-        let mut users = self.users.write().unwrap();
-        if !is_valid_username(user) {
-            return Err(Box::new(TribblerError::InvalidUsername(user.to_string())));
+        self.add_user(user, None)
+    }
+
+    async fn sign_up_with_password(&self, user: &str, password: &str) -> TribResult<()> {
+        check_password(password)?;
+        self.add_user(user, Some(Credential::new(password)))
+    }
+
+    async fn login(&self, user: &str, password: &str) -> TribResult<String> {
+        let users = self.users.read().unwrap();
+        let verified = match users.get(user).and_then(|u| u.credential.as_ref()) {
+            Some(c) => c.verify(password),
+            None => {
+                Credential::dummy().verify(password);
+                false
+            }
+        };
+        if !verified {
+            return Err(Box::new(TribblerError::InvalidCredentials));
         }
-        match users.contains_key(user) {
-            true => Err(Box::new(TribblerError::UsernameTaken(user.to_string()))),
-            false => {
-                users.insert(user.to_string(), User::new());
-                let mut homes = self.homes.write().unwrap();
-                homes.insert(user.to_string(), vec![]);
-                Ok(())
+        let token = new_token(user);
+        let (_, digest) = parse_token(&token).unwrap();
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.insert(digest, (user.to_string(), now() + SESSION_TTL));
+        Ok(token)
+    }
+
+    async fn logout(&self, token: &str) -> TribResult<()> {
+        if let Some((_, digest)) = parse_token(token) {
+            self.sessions.write().unwrap().remove(&digest);
+        }
+        Ok(())
+    }
+
+    async fn session_user(&self, token: &str) -> TribResult<String> {
+        let (user, digest) = match parse_token(token) {
+            Some(x) => x,
+            None => return Err(Box::new(TribblerError::InvalidSession)),
+        };
+        let mut sessions = self.sessions.write().unwrap();
+        match sessions.get(&digest) {
+            Some((u, expiry)) if u == user && *expiry > now() => Ok(u.clone()),
+            Some(_) => {
+                sessions.remove(&digest);
+                Err(Box::new(TribblerError::InvalidSession))
             }
+            None => Err(Box::new(TribblerError::InvalidSession)),
         }
     }
 
@@ -234,57 +537,218 @@ impl Server for RefServer {
     }
 
     async fn post(&self, who: &str, post: &str, clock: u64) -> TribResult<()> {
-        if post.len() > MAX_TRIB_LEN {
-            return Err(Box::new(TribblerError::TribTooLong));
+        self.post_trib(who, post, "", "", clock)
+    }
+
+    async fn reply(&self, who: &str, parent: &str, post: &str, clock: u64) -> TribResult<()> {
+        self.post_trib(who, post, parent, "", clock)
+    }
+
+    async fn retrib(&self, who: &str, trib_id: &str, clock: u64) -> TribResult<()> {
+        self.post_trib(who, "", "", trib_id, clock)
+    }
+
+    async fn like(&self, who: &str, trib_id: &str) -> TribResult<()> {
+        self.set_like(who, trib_id, true)
+    }
+
+    async fn unlike(&self, who: &str, trib_id: &str) -> TribResult<()> {
+        self.set_like(who, trib_id, false)
+    }
+
+    async fn like_counts(&self, trib_ids: &[String]) -> TribResult<Vec<u64>> {
+        let likes = self.likes.read().unwrap();
+        Ok(trib_ids
+            .iter()
+            .map(|id| likes.get(id).map_or(0, |likers| likers.len() as u64))
+            .collect())
+    }
+
+    async fn liked(&self, who: &str, trib_ids: &[String]) -> TribResult<Vec<bool>> {
+        let users = self.users.read().unwrap();
+        if !users.contains_key(who) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        }
+        let likes = self.likes.read().unwrap();
+        Ok(trib_ids
+            .iter()
+            .map(|id| likes.get(id).is_some_and(|likers| likers.contains(who)))
+            .collect())
+    }
+
+    async fn mentions(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
+        if !users.contains_key(user) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        let mut tribs = users
+            .values()
+            .flat_map(|u| u.tribs.iter())
+            .filter(|t| {
+                self.policy
+                    .mentioned_users(&t.message)
+                    .iter()
+                    .any(|m| m == user)
+            })
+            .cloned()
+            .collect::<Vec<Arc<Trib>>>();
+        tribs.sort_by_key(|t| t.clock);
+        let start = tribs.len().saturating_sub(MAX_TRIB_FETCH);
+        Ok(tribs.split_off(start))
+    }
+
+    async fn thread(&self, trib_id: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
+        let mut all = users
+            .values()
+            .flat_map(|u| u.tribs.iter())
+            .cloned()
+            .collect::<Vec<Arc<Trib>>>();
+        let by_id = all
+            .iter()
+            .map(|t| (t.id.as_str(), t.clone()))
+            .collect::<HashMap<&str, Arc<Trib>>>();
+        let mut root = find_trib(&users, trib_id)?;
+        while let Some(parent) = by_id.get(root.parent.as_str()) {
+            root = parent.clone();
+        }
+        // a reply always has a larger seq than what it replies to, so one
+        // pass in seq order picks up every reply under the root
+        all.sort_by_key(|t| t.clock);
+        let mut ids: HashSet<String> = HashSet::new();
+        ids.insert(root.id.clone());
+        let mut thread = vec![root.clone()];
+        for t in all.iter() {
+            if ids.contains(&t.parent) {
+                ids.insert(t.id.clone());
+                thread.push(t.clone());
+            }
         }
+        Ok(thread)
+    }
+
+    async fn tag_timeline(&self, tag: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
+        let tags = self.tags.read().unwrap();
+        let mut tribs = match tags.get(&normalize_tag(tag)) {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| find_trib(&users, id).ok())
+                .collect::<Vec<Arc<Trib>>>(),
+            None => vec![],
+        };
+        tribs.sort_by(|a, b| {
+            (a.clock, a.time, &a.user, &a.message).cmp(&(b.clock, b.time, &b.user, &b.message))
+        });
+        let start = tribs.len().saturating_sub(MAX_TRIB_FETCH);
+        Ok(tribs.split_off(start))
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
+        let tribs = users
+            .values()
+            .flat_map(|u| u.tribs.iter())
+            .cloned()
+            .collect::<Vec<Arc<Trib>>>();
+        Ok(rank(&tokenize(query), tribs, limit))
+    }
+
+    async fn tribs(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(u) => Ok(render(&users, u.list_tribs().to_vec())),
+            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+        }
+    }
+
+    async fn tribs_before(
+        &self,
+        user: &str,
+        cursor: &str,
+        limit: usize,
+    ) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(u) => Ok(render(&users, page_before(u.tribs.clone(), cursor, limit)?)),
+            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+        }
+    }
+
+    async fn delete_trib(&self, who: &str, clock: u64) -> TribResult<()> {
         let mut users = self.users.write().unwrap();
         match users.get_mut(who) {
             Some(user) => {
-                if self.seq.load(atomic::Ordering::SeqCst) == u64::MAX {
-                    return Err(Box::new(TribblerError::MaxedSeq));
+                let trib = match user.delete_trib(clock) {
+                    Some(trib) => trib,
+                    None => {
+                        return Err(Box::new(TribblerError::TribDoesNotExist(
+                            who.to_string(),
+                            clock,
+                        )))
+                    }
+                };
+                // a deleted retrib no longer counts toward the original
+                if let Some((author, c)) = parse_trib_id(&trib.retrib_of) {
+                    if let Some(author) = users.get_mut(author) {
+                        author.retribbed.remove(&(who.to_string(), c));
+                    }
                 }
-                let _ = self.seq.fetch_update(
-                    atomic::Ordering::SeqCst,
-                    atomic::Ordering::SeqCst,
-                    |v| {
-                        if v < clock {
-                            Some(clock)
-                        } else {
-                            None
-                        }
-                    },
-                );
-
-                let trib = user.post(
-                    who,
-                    post,
-                    self.seq.fetch_add(1, atomic::Ordering::SeqCst),
-                    SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)?
-                        .as_secs(),
-                );
-                // add it to the timeline of my followers
+                // take it off every timeline it was added to
                 let mut homes = self.homes.write().unwrap();
-                for follower in user.followers.iter() {
-                    homes
-                        .entry(follower.to_string())
-                        .and_modify(|e| e.push(trib.clone()));
+                for home in homes.values_mut() {
+                    home.retain(|t| t.user != who || t.clock != clock);
                 }
-                // add it to my own timeline
-                homes
-                    .entry(who.to_string())
-                    .and_modify(|e| e.push(trib.clone()));
+                self.retag(&trib.id, "");
+                self.likes.write().unwrap().remove(&trib.id);
                 Ok(())
             }
             None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
         }
     }
 
-    async fn tribs(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
-        let users = self.users.read().unwrap();
-        match users.get(user) {
-            Some(user) => Ok(user.list_tribs().to_vec()),
-            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+    async fn edit_trib(&self, who: &str, clock: u64, post: &str) -> TribResult<()> {
+        check_trib(post)?;
+        let mut users = self.users.write().unwrap();
+        match users.get_mut(who) {
+            Some(user) => {
+                // A retrib carries the original's message, which isn't
+                // theirs to edit
+                if user
+                    .tribs
+                    .iter()
+                    .any(|t| t.clock == clock && !t.retrib_of.is_empty())
+                {
+                    return Err(Box::new(TribblerError::RetribNotEditable(
+                        who.to_string(),
+                        clock,
+                    )));
+                }
+                let trib = match user.edit_trib(clock, post) {
+                    Some(trib) => trib,
+                    None => {
+                        return Err(Box::new(TribblerError::TribDoesNotExist(
+                            who.to_string(),
+                            clock,
+                        )))
+                    }
+                };
+                // swap it in on every timeline it was added to
+                let mut homes = self.homes.write().unwrap();
+                for home in homes.values_mut() {
+                    for t in home.iter_mut() {
+                        if t.user == who && t.clock == clock {
+                            *t = trib.clone();
+                        }
+                    }
+                }
+                self.retag(&trib.id, &trib.message);
+                for t in render(&users, vec![trib]) {
+                    let _ = self.posts.send(t);
+                }
+                Ok(())
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
         }
     }
 
@@ -293,11 +757,24 @@ impl Server for RefServer {
             return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
         }
         let mut users = self.users.write().unwrap();
-        if !users.contains_key(whom) {
-            return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string())));
+        match users.get(whom) {
+            Some(u) if u.blocked.contains(who) => {
+                return Err(Box::new(TribblerError::Blocked(
+                    whom.to_string(),
+                    who.to_string(),
+                )))
+            }
+            Some(_) => (),
+            None => return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
         }
         match users.get_mut(who) {
             Some(u) => {
+                if u.blocked.contains(whom) {
+                    return Err(Box::new(TribblerError::Blocked(
+                        who.to_string(),
+                        whom.to_string(),
+                    )));
+                }
                 if u.is_following(whom) {
                     return Err(Box::new(TribblerError::AlreadyFollowing(
                         who.to_string(),
@@ -378,19 +855,331 @@ impl Server for RefServer {
         }
     }
 
+    async fn followers(&self, who: &str) -> TribResult<Vec<String>> {
+        let users = self.users.read().unwrap();
+        match users.get(who) {
+            Some(user) => Ok(user.list_followers()),
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+    }
+
+    async fn block(&self, who: &str, whom: &str) -> TribResult<()> {
+        if who == whom {
+            return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
+        }
+        let mut users = self.users.write().unwrap();
+        if !users.contains_key(whom) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(whom.to_string())));
+        }
+        match users.get_mut(who) {
+            Some(u) => {
+                u.blocked.insert(whom.to_string());
+            }
+            None => return Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+        // undo the follows between them, both ways
+        for (a, b) in [(who, whom), (whom, who)] {
+            if let Some(u) = users.get_mut(a) {
+                u.unfollow(b);
+                u.remove_follower(b);
+            }
+        }
+        let mut homes = self.homes.write().unwrap();
+        for name in [who, whom] {
+            if let Some(user) = users.get(name) {
+                homes.insert(name.to_string(), self.rebuild_home(user, &users));
+            }
+        }
+        Ok(())
+    }
+
+    async fn unblock(&self, who: &str, whom: &str) -> TribResult<()> {
+        if who == whom {
+            return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
+        }
+        let mut users = self.users.write().unwrap();
+        if !users.contains_key(whom) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(whom.to_string())));
+        }
+        match users.get_mut(who) {
+            Some(u) => {
+                u.blocked.remove(whom);
+                Ok(())
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+    }
+
+    async fn blocked(&self, who: &str) -> TribResult<Vec<String>> {
+        let users = self.users.read().unwrap();
+        match users.get(who) {
+            Some(u) => {
+                let mut blocked = u.blocked.iter().cloned().collect::<Vec<String>>();
+                blocked.sort();
+                Ok(blocked)
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+    }
+
+    async fn mute(&self, who: &str, whom: &str) -> TribResult<()> {
+        if who == whom {
+            return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
+        }
+        let mut users = self.users.write().unwrap();
+        if !users.contains_key(whom) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(whom.to_string())));
+        }
+        match users.get_mut(who) {
+            Some(u) => {
+                u.muted.insert(whom.to_string());
+                Ok(())
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+    }
+
+    async fn unmute(&self, who: &str, whom: &str) -> TribResult<()> {
+        if who == whom {
+            return Err(Box::new(TribblerError::WhoWhom(who.to_string())));
+        }
+        let mut users = self.users.write().unwrap();
+        if !users.contains_key(whom) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(whom.to_string())));
+        }
+        match users.get_mut(who) {
+            Some(u) => {
+                u.muted.remove(whom);
+                Ok(())
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+    }
+
+    async fn muted(&self, who: &str) -> TribResult<Vec<String>> {
+        let users = self.users.read().unwrap();
+        match users.get(who) {
+            Some(u) => {
+                let mut muted = u.muted.iter().cloned().collect::<Vec<String>>();
+                muted.sort();
+                Ok(muted)
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(who.to_string()))),
+        }
+    }
+
+    async fn user_stats(&self, user: &str) -> TribResult<UserStats> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(u) => Ok(UserStats {
+                followers: u.followers.len(),
+                following: u.following.len(),
+                tribs: u.tribs.len(),
+                retribbed: u.retribbed.len(),
+            }),
+            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+        }
+    }
+
+    async fn get_profile(&self, user: &str) -> TribResult<Profile> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(u) => Ok(u.profile.clone()),
+            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+        }
+    }
+
+    async fn update_profile(&self, user: &str, profile: &Profile) -> TribResult<()> {
+        check_profile(profile)?;
+        let mut users = self.users.write().unwrap();
+        match users.get_mut(user) {
+            Some(u) => {
+                u.profile = Profile {
+                    joined: u.profile.joined,
+                    ..profile.clone()
+                };
+                Ok(())
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+        }
+    }
+
+    async fn delete_user(&self, user: &str) -> TribResult<()> {
+        let mut users = self.users.write().unwrap();
+        if users.remove(user).is_none() {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        // take them out of the follow graph, the block and mute lists of
+        // others, and their retribs out of the counts of the tribs they
+        // retribbed
+        for other in users.values_mut() {
+            other.unfollow(user);
+            other.remove_follower(user);
+            other.blocked.remove(user);
+            other.muted.remove(user);
+            other.retribbed.retain(|(who, _)| who != user);
+        }
+        let mut homes = self.homes.write().unwrap();
+        homes.remove(user);
+        for home in homes.values_mut() {
+            home.retain(|t| t.user != user);
+        }
+        let mut tags = self.tags.write().unwrap();
+        for ids in tags.values_mut() {
+            ids.retain(|id| !matches!(parse_trib_id(id), Some((u, _)) if u == user));
+        }
+        let mut likes = self.likes.write().unwrap();
+        likes.retain(|id, _| !matches!(parse_trib_id(id), Some((u, _)) if u == user));
+        for likers in likes.values_mut() {
+            likers.remove(user);
+        }
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, (u, _)| u != user);
+        Ok(())
+    }
+
+    async fn export_user(&self, user: &str) -> TribResult<UserArchive> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(u) => {
+                let mut following = u.list_following();
+                following.sort();
+                let mut followers = u.list_followers();
+                followers.sort();
+                let mut liked = self
+                    .likes
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, likers)| likers.contains(user))
+                    .map(|(id, _)| id.clone())
+                    .collect::<Vec<String>>();
+                liked.sort();
+                Ok(UserArchive {
+                    user: user.to_string(),
+                    profile: u.profile.clone(),
+                    tribs: u.tribs.clone(),
+                    following,
+                    followers,
+                    liked,
+                })
+            }
+            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+        }
+    }
+
     async fn home(&self, user: &str) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
         let homes = self.homes.read().unwrap();
-        match homes.get(user) {
-            Some(home) => {
+        match (homes.get(user), users.get(user)) {
+            (Some(home), Some(u)) => {
+                let home = home
+                    .iter()
+                    .filter(|t| !u.hides(t))
+                    .cloned()
+                    .collect::<Vec<Arc<Trib>>>();
                 let ntrib = home.len();
                 let start = match ntrib.cmp(&MAX_TRIB_FETCH) {
                     Ordering::Greater => ntrib - MAX_TRIB_FETCH,
                     _ => 0,
                 };
-                // let hm = &home[start..];
-                Ok(home[start..].to_vec())
+                Ok(render(&users, home[start..].to_vec()))
             }
-            None => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+            _ => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
+        }
+    }
+
+    async fn home_before(
+        &self,
+        user: &str,
+        cursor: &str,
+        limit: usize,
+    ) -> TribResult<Vec<Arc<Trib>>> {
+        let users = self.users.read().unwrap();
+        let homes = self.homes.read().unwrap();
+        match (homes.get(user), users.get(user)) {
+            (Some(home), Some(u)) => {
+                let home = home
+                    .iter()
+                    .filter(|t| !u.hides(t))
+                    .cloned()
+                    .collect::<Vec<Arc<Trib>>>();
+                Ok(render(&users, page_before(home, cursor, limit)?))
+            }
+            _ => Err(Box::new(TribblerError::UserDoesNotExist(user.to_string()))),
         }
     }
+
+    async fn watch_home(&self, user: &str) -> TribResult<mpsc::Receiver<Arc<Trib>>> {
+        if !self.users.read().unwrap().contains_key(user) {
+            return Err(Box::new(TribblerError::UserDoesNotExist(user.to_string())));
+        }
+        let mut posts = self.posts.subscribe();
+        let users = self.users.clone();
+        let user = user.to_string();
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let trib = tokio::select! {
+                    _ = tx.closed() => break,
+                    trib = posts.recv() => match trib {
+                        Ok(trib) => trib,
+                        Err(_) => break,
+                    },
+                };
+                // a retrib joins the timelines of who reposted it
+                let poster = match trib.retrib_by.as_str() {
+                    "" => trib.user.as_str(),
+                    by => by,
+                };
+                let wanted = match users.read().unwrap().get(&user) {
+                    Some(u) => (poster == user || u.is_following(poster)) && !u.hides(&trib),
+                    None => false,
+                };
+                if wanted && tx.send(trib).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        err::TribResult,
+        trib::{parse_trib_id, Server},
+    };
+
+    use super::RefServer;
+
+    #[tokio::test]
+    async fn thread_of_padded_id() -> TribResult<()> {
+        let server = RefServer::new();
+        server.sign_up("alice").await?;
+        server.post("alice", "root", 0).await?;
+        let root = server.tribs("alice").await?[0].clone();
+        server.reply("alice", &root.id, "reply", root.clock).await?;
+        let padded = format!("alice-0{}", root.clock);
+        assert_eq!(2, server.thread(&padded).await?.len());
+        assert!(server.thread("alice-999").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retribs_not_editable() -> TribResult<()> {
+        let server = RefServer::new();
+        server.sign_up("alice").await?;
+        server.sign_up("bob").await?;
+        server.post("alice", "original", 0).await?;
+        let original = server.tribs("alice").await?[0].clone();
+        server.retrib("bob", &original.id, original.clock).await?;
+        let record = server.tribs("bob").await?[0].clone();
+        let clock = parse_trib_id(&record.id).unwrap().1;
+        assert!(server.edit_trib("bob", clock, "#mine").await.is_err());
+        assert!(server.tag_timeline("mine").await?.is_empty());
+        assert_eq!("original", server.tribs("bob").await?[0].message);
+        Ok(())
+    }
 }
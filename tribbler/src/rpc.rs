@@ -42,6 +42,59 @@ pub struct ListRemoveResponse {
     #[prost(uint32, tag = "1")]
     pub removed: u32,
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Keys {
+    #[prost(string, repeated, tag = "1")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyValues {
+    #[prost(message, repeated, tag = "1")]
+    pub kvs: ::prost::alloc::vec::Vec<KeyValue>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StringLists {
+    #[prost(message, repeated, tag = "1")]
+    pub lists: ::prost::alloc::vec::Vec<StringList>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CompareAndSet {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub expected: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub value: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Change {
+    #[prost(enumeration = "ChangeKind", tag = "1")]
+    pub kind: i32,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub value: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ConditionalAppend {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub value: ::prost::alloc::string::String,
+    #[prost(bool, tag = "3")]
+    pub absent: bool,
+    #[prost(bool, tag = "4")]
+    pub limited: bool,
+    #[prost(uint32, tag = "5")]
+    pub max_len: u32,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ChangeKind {
+    Set = 0,
+    ListAppend = 1,
+    ListRemove = 2,
+}
 #[doc = r" Generated client implementations."]
 pub mod trib_storage_client {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
@@ -214,9 +267,97 @@ pub mod trib_storage_client {
             let path = http::uri::PathAndQuery::from_static("/rpc.TribStorage/clock");
             self.inner.unary(request.into_request(), path, codec).await
         }
+        pub async fn get_many(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Keys>,
+        ) -> Result<tonic::Response<super::StringList>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/rpc.TribStorage/getMany");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn list_get_many(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Keys>,
+        ) -> Result<tonic::Response<super::StringLists>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/rpc.TribStorage/listGetMany");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn list_append_many(
+            &mut self,
+            request: impl tonic::IntoRequest<super::KeyValues>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/rpc.TribStorage/listAppendMany");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn compare_and_set(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CompareAndSet>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/rpc.TribStorage/compareAndSet");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn list_append_if(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ConditionalAppend>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/rpc.TribStorage/listAppendIf");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn watch(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Pattern>,
+        ) -> Result<tonic::Response<tonic::codec::Streaming<super::Change>>, tonic::Status>
+        {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/rpc.TribStorage/watch");
+            self.inner
+                .server_streaming(request.into_request(), path, codec)
+                .await
+        }
     }
 }
 #[doc = r" Generated server implementations."]
+#[allow(non_camel_case_types, clippy::mixed_attributes_style)]
 pub mod trib_storage_server {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
     use tonic::codegen::*;
@@ -255,6 +396,34 @@ pub mod trib_storage_server {
             &self,
             request: tonic::Request<super::Clock>,
         ) -> Result<tonic::Response<super::Clock>, tonic::Status>;
+        async fn get_many(
+            &self,
+            request: tonic::Request<super::Keys>,
+        ) -> Result<tonic::Response<super::StringList>, tonic::Status>;
+        async fn list_get_many(
+            &self,
+            request: tonic::Request<super::Keys>,
+        ) -> Result<tonic::Response<super::StringLists>, tonic::Status>;
+        async fn list_append_many(
+            &self,
+            request: tonic::Request<super::KeyValues>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status>;
+        async fn compare_and_set(
+            &self,
+            request: tonic::Request<super::CompareAndSet>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status>;
+        async fn list_append_if(
+            &self,
+            request: tonic::Request<super::ConditionalAppend>,
+        ) -> Result<tonic::Response<super::Bool>, tonic::Status>;
+        #[doc = "Server streaming response type for the watch method."]
+        type watchStream: futures_core::Stream<Item = Result<super::Change, tonic::Status>>
+            + Send
+            + 'static;
+        async fn watch(
+            &self,
+            request: tonic::Request<super::Pattern>,
+        ) -> Result<tonic::Response<Self::watchStream>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct TribStorageServer<T: TribStorage> {
@@ -534,6 +703,188 @@ pub mod trib_storage_server {
                     };
                     Box::pin(fut)
                 }
+                "/rpc.TribStorage/getMany" => {
+                    #[allow(non_camel_case_types)]
+                    struct getManySvc<T: TribStorage>(pub Arc<T>);
+                    impl<T: TribStorage> tonic::server::UnaryService<super::Keys> for getManySvc<T> {
+                        type Response = super::StringList;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Keys>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_many(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = getManySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/rpc.TribStorage/listGetMany" => {
+                    #[allow(non_camel_case_types)]
+                    struct listGetManySvc<T: TribStorage>(pub Arc<T>);
+                    impl<T: TribStorage> tonic::server::UnaryService<super::Keys> for listGetManySvc<T> {
+                        type Response = super::StringLists;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Keys>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_get_many(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = listGetManySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/rpc.TribStorage/listAppendMany" => {
+                    #[allow(non_camel_case_types)]
+                    struct listAppendManySvc<T: TribStorage>(pub Arc<T>);
+                    impl<T: TribStorage> tonic::server::UnaryService<super::KeyValues> for listAppendManySvc<T> {
+                        type Response = super::Bool;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::KeyValues>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_append_many(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = listAppendManySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/rpc.TribStorage/compareAndSet" => {
+                    #[allow(non_camel_case_types)]
+                    struct compareAndSetSvc<T: TribStorage>(pub Arc<T>);
+                    impl<T: TribStorage> tonic::server::UnaryService<super::CompareAndSet> for compareAndSetSvc<T> {
+                        type Response = super::Bool;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CompareAndSet>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).compare_and_set(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = compareAndSetSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/rpc.TribStorage/listAppendIf" => {
+                    #[allow(non_camel_case_types)]
+                    struct listAppendIfSvc<T: TribStorage>(pub Arc<T>);
+                    impl<T: TribStorage> tonic::server::UnaryService<super::ConditionalAppend> for listAppendIfSvc<T> {
+                        type Response = super::Bool;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ConditionalAppend>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_append_if(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = listAppendIfSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/rpc.TribStorage/watch" => {
+                    #[allow(non_camel_case_types)]
+                    struct watchSvc<T: TribStorage>(pub Arc<T>);
+                    impl<T: TribStorage> tonic::server::ServerStreamingService<super::Pattern> for watchSvc<T> {
+                        type Response = super::Change;
+                        type ResponseStream = T::watchStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Pattern>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).watch(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = watchSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => Box::pin(async move {
                     Ok(http::Response::builder()
                         .status(200)
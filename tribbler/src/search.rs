@@ -0,0 +1,88 @@
+//! Tokenizing and ranking used for full-text search over tribs, shared by
+//! the [crate::trib::Server] implementations.
+use std::{cmp::min, sync::Arc};
+
+use crate::trib::{Trib, MAX_TRIB_FETCH};
+
+/// Splits a text into its search terms: lowercased runs of letters and
+/// digits, each once, in the order they first appear.
+///
+/// ```rust
+/// use tribbler::search::tokenize;
+/// assert_eq!(vec!["just", "tribble", "it"], tokenize("Just #tribble it, just."));
+/// ```
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms: Vec<String> = vec![];
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        let term = word.to_lowercase();
+        if !term.is_empty() && !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+/// Scores a trib message against the terms of a query: the number of
+/// query terms found in the message.
+pub fn score(query: &[String], message: &str) -> usize {
+    let terms = tokenize(message);
+    query.iter().filter(|q| terms.contains(q)).count()
+}
+
+/// Returns the best `limit` (at most [MAX_TRIB_FETCH]) of `tribs` for the
+/// query terms `query`, best first. Tribs are ranked by [score], then the
+/// latest first; those matching no term are left out.
+pub fn rank(query: &[String], tribs: Vec<Arc<Trib>>, limit: usize) -> Vec<Arc<Trib>> {
+    let mut scored = tribs
+        .into_iter()
+        .map(|t| (score(query, &t.message), t))
+        .filter(|(s, _)| *s > 0)
+        .collect::<Vec<(usize, Arc<Trib>)>>();
+    scored.sort_by(|(sa, a), (sb, b)| (sb, b.clock, &b.user).cmp(&(sa, a.clock, &a.user)));
+    scored.truncate(min(limit, MAX_TRIB_FETCH));
+    scored.into_iter().map(|(_, t)| t).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{rank, tokenize};
+    use crate::trib::{trib_id, Trib};
+
+    fn trib(user: &str, clock: u64, message: &str) -> Arc<Trib> {
+        Arc::new(Trib {
+            user: user.to_string(),
+            message: message.to_string(),
+            time: 0,
+            clock,
+            id: trib_id(user, clock),
+            parent: "".to_string(),
+            retrib_of: "".to_string(),
+            retrib_by: "".to_string(),
+        })
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(vec!["ünïcode", "42"], tokenize("ÜNÏCODE... 42!"));
+        assert!(tokenize(" ,.!? ").is_empty());
+    }
+
+    #[test]
+    fn ranking() {
+        let tribs = vec![
+            trib("alice", 1, "rust is fun"),
+            trib("bob", 2, "Rust and tokio"),
+            trib("carol", 3, "tokio only"),
+            trib("dave", 4, "nothing at all"),
+        ];
+        let query = tokenize("rust tokio");
+        let ranked = rank(&query, tribs.clone(), 10)
+            .iter()
+            .map(|t| t.user.clone())
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["bob", "carol", "alice"], ranked);
+        assert_eq!(1, rank(&query, tribs, 1).len());
+    }
+}
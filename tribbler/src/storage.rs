@@ -2,6 +2,7 @@
 //! module containing Tribbler storage-related structs and implementations
 use async_trait::async_trait;
 use std::{collections::HashMap, sync::RwLock};
+use tokio::sync::{broadcast, mpsc};
 
 use crate::err::TribResult;
 
@@ -46,6 +47,54 @@ impl Pattern {
 /// A wrapper type around a [Vec<String>]
 pub struct List(pub Vec<String>);
 
+/// how many changes a watcher may fall behind before it is cut off
+pub const WATCH_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The kinds of mutation reported by [Storage::watch]
+pub enum ChangeKind {
+    /// [KeyString::set], with an empty value when the key was cleared
+    Set,
+    /// [KeyList::list_append]
+    ListAppend,
+    /// [KeyList::list_remove] that removed at least one element
+    ListRemove,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single mutation reported by [Storage::watch]
+pub struct Change {
+    /// what kind of mutation this was
+    pub kind: ChangeKind,
+    /// the key that was changed
+    pub key: String,
+    /// the value that was set, appended or removed
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default)]
+/// The conditions under which [KeyList::list_append_if] appends. The
+/// default appends unconditionally.
+pub struct AppendCondition {
+    /// only append if the value is not in the list yet
+    pub absent: bool,
+    /// only append if the list holds fewer elements than this
+    pub max_len: Option<u32>,
+}
+
+impl AppendCondition {
+    /// this function returns true if appending to `list` meets the condition
+    pub fn allows(&self, list: &[String], value: &str) -> bool {
+        if self.absent && list.iter().any(|v| v == value) {
+            return false;
+        }
+        match self.max_len {
+            Some(max_len) => list.len() < max_len as usize,
+            None => true,
+        }
+    }
+}
+
 #[async_trait]
 /// Key-value pair interfaces
 /// Default value for all keys is empty string
@@ -59,6 +108,20 @@ pub trait KeyString {
     /// List all the keys of non-empty pairs where the key matches
     /// the given pattern.
     async fn keys(&self, p: &Pattern) -> TribResult<List>;
+
+    /// Atomically sets `key` to `value` if its current value is `expected`,
+    /// where an empty string stands for an unset key. return true when the
+    /// value was set.
+    async fn compare_and_set(&self, key: &str, expected: &str, value: &str) -> TribResult<bool>;
+
+    /// Gets the values of several keys at once, in the order of `keys`.
+    async fn get_many(&self, keys: &[String]) -> TribResult<Vec<Option<String>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(values)
+    }
 }
 
 #[async_trait]
@@ -77,6 +140,28 @@ pub trait KeyList {
     /// List all the keys of non-empty lists, where the key matches
     /// the given pattern.
     async fn list_keys(&self, p: &Pattern) -> TribResult<List>;
+
+    /// Atomically appends a string to the list if the list meets `cond`.
+    /// return true when the string was appended.
+    async fn list_append_if(&self, kv: &KeyValue, cond: &AppendCondition) -> TribResult<bool>;
+
+    /// Gets several lists at once, in the order of `keys`.
+    async fn list_get_many(&self, keys: &[String]) -> TribResult<Vec<List>> {
+        let mut lists = Vec::with_capacity(keys.len());
+        for key in keys {
+            lists.push(self.list_get(key).await?);
+        }
+        Ok(lists)
+    }
+
+    /// Appends each `kv.value` to its list `kv.key`, in order. return true
+    /// when no error.
+    async fn list_append_many(&self, kvs: &[KeyValue]) -> TribResult<bool> {
+        for kv in kvs {
+            self.list_append(kv).await?;
+        }
+        Ok(true)
+    }
 }
 
 #[async_trait]
@@ -89,17 +174,36 @@ pub trait Storage: KeyString + KeyList + Send + Sync {
     /// be unique, no smaller than `at_least`, and strictly larger than the
     /// value returned last time, unless it was [u64::MAX]
     async fn clock(&self, at_least: u64) -> TribResult<u64>;
+
+    /// Subscribes to the changes made from now on to the keys and lists
+    /// matching `p`, in the order they were made. The channel is closed if
+    /// the watcher falls more than [WATCH_BUFFER] changes behind, or the
+    /// storage goes away, after which the caller should read the current
+    /// state again before watching anew.
+    async fn watch(&self, p: &Pattern) -> TribResult<mpsc::Receiver<Change>>;
 }
 
 /// This is a toy implementation of a backend storage service.
 /// The trait definition requires this to be safe to utilize across threads
 /// because mutating methods (e.g. [KeyString::set] take `&self` instead of
 /// `&mut self`)
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct MemStorage {
-    kvs: RwLock<HashMap<String, String>>,
-    kv_list: RwLock<HashMap<String, List>>,
-    clock: RwLock<u64>,
+    pub(crate) kvs: RwLock<HashMap<String, String>>,
+    pub(crate) kv_list: RwLock<HashMap<String, List>>,
+    pub(crate) clock: RwLock<u64>,
+    changes: broadcast::Sender<Change>,
+}
+
+impl Default for MemStorage {
+    fn default() -> Self {
+        MemStorage {
+            kvs: RwLock::default(),
+            kv_list: RwLock::default(),
+            clock: RwLock::default(),
+            changes: broadcast::channel(WATCH_BUFFER).0,
+        }
+    }
 }
 
 impl MemStorage {
@@ -107,6 +211,18 @@ impl MemStorage {
     pub fn new() -> MemStorage {
         MemStorage::default()
     }
+
+    /// Reports a change to the current watchers. Called with the lock on
+    /// the changed map held, so watchers see changes in the order they
+    /// were made.
+    fn notify(&self, kind: ChangeKind, key: &str, value: &str) {
+        // An error only means no one is watching
+        let _ = self.changes.send(Change {
+            kind,
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
 }
 
 #[async_trait]
@@ -125,6 +241,7 @@ impl KeyString for MemStorage {
         } else {
             entry.insert(kv.key.clone(), kv.value.clone());
         }
+        self.notify(ChangeKind::Set, &kv.key, &kv.value);
         Ok(true)
     }
 
@@ -139,6 +256,26 @@ impl KeyString for MemStorage {
             .collect::<Vec<String>>();
         Ok(List(result))
     }
+
+    async fn compare_and_set(&self, key: &str, expected: &str, value: &str) -> TribResult<bool> {
+        let mut entry = self.kvs.write().map_err(|e| e.to_string())?;
+        let current = entry.get(key).map(String::as_str).unwrap_or("");
+        if current != expected {
+            return Ok(false);
+        }
+        if value.is_empty() {
+            entry.remove(key);
+        } else {
+            entry.insert(key.to_string(), value.to_string());
+        }
+        self.notify(ChangeKind::Set, key, value);
+        Ok(true)
+    }
+
+    async fn get_many(&self, keys: &[String]) -> TribResult<Vec<Option<String>>> {
+        let kvs = self.kvs.read().map_err(|e| e.to_string())?;
+        Ok(keys.iter().map(|k| kvs.get(k).cloned()).collect())
+    }
 }
 
 #[async_trait]
@@ -155,14 +292,14 @@ impl KeyList for MemStorage {
         match kvl.get_mut(&kv.key) {
             Some(list) => {
                 list.0.push(kv.value.clone());
-                Ok(true)
             }
             None => {
                 let list = vec![kv.value.clone()];
                 kvl.insert(kv.key.clone(), List(list));
-                Ok(true)
             }
         }
+        self.notify(ChangeKind::ListAppend, &kv.key, &kv.value);
+        Ok(true)
     }
 
     async fn list_remove(&self, kv: &KeyValue) -> TribResult<u32> {
@@ -185,6 +322,9 @@ impl KeyList for MemStorage {
                 kvl.remove(&kv.key);
             }
         };
+        if removed > 0 {
+            self.notify(ChangeKind::ListRemove, &kv.key, &kv.value);
+        }
 
         Ok(removed as u32)
     }
@@ -200,6 +340,40 @@ impl KeyList for MemStorage {
         result.sort();
         Ok(List(result))
     }
+
+    async fn list_append_if(&self, kv: &KeyValue, cond: &AppendCondition) -> TribResult<bool> {
+        let mut kvl = self.kv_list.write().map_err(|e| e.to_string())?;
+        let list = kvl.entry(kv.key.clone()).or_insert_with(|| List(vec![]));
+        if !cond.allows(&list.0, &kv.value) {
+            if list.0.is_empty() {
+                kvl.remove(&kv.key);
+            }
+            return Ok(false);
+        }
+        list.0.push(kv.value.clone());
+        self.notify(ChangeKind::ListAppend, &kv.key, &kv.value);
+        Ok(true)
+    }
+
+    async fn list_get_many(&self, keys: &[String]) -> TribResult<Vec<List>> {
+        let kvl = self.kv_list.read().map_err(|e| e.to_string())?;
+        Ok(keys
+            .iter()
+            .map(|k| kvl.get(k).cloned().unwrap_or_else(|| List(vec![])))
+            .collect())
+    }
+
+    async fn list_append_many(&self, kvs: &[KeyValue]) -> TribResult<bool> {
+        let mut kvl = self.kv_list.write().map_err(|e| e.to_string())?;
+        for kv in kvs {
+            kvl.entry(kv.key.clone())
+                .or_insert_with(|| List(vec![]))
+                .0
+                .push(kv.value.clone());
+            self.notify(ChangeKind::ListAppend, &kv.key, &kv.value);
+        }
+        Ok(true)
+    }
 }
 
 #[async_trait]
@@ -217,6 +391,29 @@ impl Storage for MemStorage {
         }
         Ok(ret)
     }
+
+    async fn watch(&self, p: &Pattern) -> TribResult<mpsc::Receiver<Change>> {
+        let mut changes = self.changes.subscribe();
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        let p = p.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tx.closed() => break,
+                    change = changes.recv() => match change {
+                        Ok(change) => {
+                            if p.matches(&change.key) && tx.send(change).await.is_err() {
+                                break;
+                            }
+                        }
+                        // Lagged behind, or the storage is gone
+                        Err(_) => break,
+                    },
+                }
+            }
+        });
+        Ok(rx)
+    }
 }
 
 #[async_trait]
@@ -224,6 +421,15 @@ impl Storage for MemStorage {
 pub trait BinStorage: Send + Sync {
     /// Fetch a [Storage] bin based on the given bin name.
     async fn bin(&self, name: &str) -> TribResult<Box<dyn Storage>>;
+
+    /// Gets the list `key` from each of the bins `names`, in order.
+    async fn list_get_bins(&self, names: &[String], key: &str) -> TribResult<Vec<List>> {
+        let mut lists = Vec::with_capacity(names.len());
+        for name in names {
+            lists.push(self.bin(name).await?.list_get(key).await?);
+        }
+        Ok(lists)
+    }
 }
 
 #[cfg(test)]
@@ -233,7 +439,7 @@ mod test {
         storage::{KeyValue, Pattern, Storage},
     };
 
-    use super::{KeyList, KeyString, MemStorage};
+    use super::{AppendCondition, Change, ChangeKind, KeyList, KeyString, MemStorage};
 
     async fn setup_test_storage() -> MemStorage {
         let storage = MemStorage::new();
@@ -352,6 +558,91 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn storage_get_many() -> TribResult<()> {
+        let storage = setup_test_storage().await;
+        let keys = vec!["test".to_string(), "test2".to_string()];
+        assert_eq!(
+            vec![Some("test-value".to_string()), None],
+            storage.get_many(&keys).await?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn storage_list_many() -> TribResult<()> {
+        let storage = setup_test_storage().await;
+        let kvs = vec![KeyValue::new("test", "val2"), KeyValue::new("test2", "val")];
+        assert!(storage.list_append_many(&kvs).await?);
+        let keys = vec!["test".to_string(), "test2".to_string(), "test3".to_string()];
+        let lists = storage.list_get_many(&keys).await?;
+        assert_eq!(vec!["test-value", "val2"], lists[0].0);
+        assert_eq!(vec!["val"], lists[1].0);
+        assert!(lists[2].0.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn storage_compare_and_set() -> TribResult<()> {
+        let storage = setup_test_storage().await;
+        assert!(!storage.compare_and_set("test", "wrong", "v").await?);
+        assert!(storage.compare_and_set("test", "test-value", "v").await?);
+        assert_eq!(Some("v".to_string()), storage.get("test").await?);
+        assert!(storage.compare_and_set("new", "", "v").await?);
+        assert!(!storage.compare_and_set("new", "", "w").await?);
+        assert!(storage.compare_and_set("new", "v", "").await?);
+        assert_eq!(None, storage.get("new").await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn storage_list_append_if() -> TribResult<()> {
+        let storage = setup_test_storage().await;
+        let absent = AppendCondition {
+            absent: true,
+            max_len: None,
+        };
+        assert!(
+            !storage
+                .list_append_if(&KeyValue::new("test", "test-value"), &absent)
+                .await?
+        );
+        assert!(
+            storage
+                .list_append_if(&KeyValue::new("test", "val2"), &absent)
+                .await?
+        );
+        let bounded = AppendCondition {
+            absent: false,
+            max_len: Some(3),
+        };
+        assert!(
+            storage
+                .list_append_if(&KeyValue::new("test", "val2"), &bounded)
+                .await?
+        );
+        assert!(
+            !storage
+                .list_append_if(&KeyValue::new("test", "val3"), &bounded)
+                .await?
+        );
+        assert_eq!(3, storage.list_get("test").await?.0.len());
+        let none = AppendCondition {
+            absent: false,
+            max_len: Some(0),
+        };
+        assert!(
+            !storage
+                .list_append_if(&KeyValue::new("empty", "v"), &none)
+                .await?
+        );
+        assert_eq!(
+            vec!["test"],
+            storage.list_keys(&Pattern::default()).await?.0
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn storage_get_list_remove() {
         let storage = setup_test_storage().await;
@@ -397,6 +688,37 @@ mod test {
         assert_eq!(1, storage.list_keys(&p5).await.unwrap().0.len());
     }
 
+    #[tokio::test]
+    async fn storage_watch() -> TribResult<()> {
+        let storage = setup_test_storage().await;
+        let p = Pattern {
+            prefix: "w".to_string(),
+            suffix: "".to_string(),
+        };
+        let mut changes = storage.watch(&p).await?;
+        storage.set(&KeyValue::new("ignored", "v")).await?;
+        storage.set(&KeyValue::new("wk", "v")).await?;
+        storage.list_append(&KeyValue::new("wl", "a")).await?;
+        storage.list_remove(&KeyValue::new("wl", "missing")).await?;
+        storage.list_remove(&KeyValue::new("wl", "a")).await?;
+        let expected = vec![
+            (ChangeKind::Set, "wk", "v"),
+            (ChangeKind::ListAppend, "wl", "a"),
+            (ChangeKind::ListRemove, "wl", "a"),
+        ];
+        for (kind, key, value) in expected {
+            let change = Change {
+                kind,
+                key: key.to_string(),
+                value: value.to_string(),
+            };
+            assert_eq!(Some(change), changes.recv().await);
+        }
+        drop(storage);
+        assert_eq!(None, changes.recv().await);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn clock_at_least() {
         let storage = setup_test_storage().await;
@@ -1,18 +1,23 @@
 #![allow(dead_code)]
 //! Package trib defines basic interfaces and constants
 //! for the Tribbler service implementation.
-use std::sync::Arc;
+use std::{
+    cmp::{min, Ordering},
+    sync::Arc,
+};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 
-use crate::err::TribResult;
+use crate::err::{TribResult, TribblerError};
+use crate::validate::UsernamePolicy;
 
 /// Maximum length of a username
 #[allow(dead_code)]
 pub const MAX_USERNAME_LEN: usize = 15;
 
-/// Maximum length of a tribble
+/// Maximum length of a tribble, see [crate::validate::trib_len]
 #[allow(dead_code)]
 pub const MAX_TRIB_LEN: usize = 140;
 
@@ -28,6 +33,16 @@ pub const MIN_LIST_USER: usize = 20;
 #[allow(dead_code)]
 pub const MAX_FOLLOWING: usize = 2000;
 
+/// Maximum length of the display name in a [Profile], counted like
+/// [MAX_TRIB_LEN]
+pub const MAX_DISPLAY_NAME_LEN: usize = 50;
+
+/// Maximum length of the bio in a [Profile], counted like [MAX_TRIB_LEN]
+pub const MAX_BIO_LEN: usize = 160;
+
+/// Maximum length of the avatar URL in a [Profile], in bytes
+pub const MAX_AVATAR_URL_LEN: usize = 500;
+
 /// A [Trib] is a post by a user to the tribbler service.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Trib {
@@ -39,6 +54,65 @@ pub struct Trib {
     pub time: u64,
     /// the logical timestamp when posted
     pub clock: u64,
+    /// an opaque identifier which stays the same for the life of this trib,
+    /// see [trib_id]
+    #[serde(default)]
+    pub id: String,
+    /// the id of the trib this one replies to, empty if it is not a reply
+    #[serde(default)]
+    pub parent: String,
+    /// the id of the trib this one reposts, empty if it is not a retrib
+    #[serde(default)]
+    pub retrib_of: String,
+    /// who reposted this trib. Only set on the retribs timelines return,
+    /// see [as_retrib]
+    #[serde(default)]
+    pub retrib_by: String,
+}
+
+/// Counts shown on a user's page, see [Server::user_stats]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserStats {
+    /// how many users follow this user
+    pub followers: usize,
+    /// how many users this user follows
+    pub following: usize,
+    /// how many tribs this user has posted and not deleted
+    pub tribs: usize,
+    /// how many times tribs of this user have been retribbed
+    pub retribbed: usize,
+}
+
+/// What a user tells others about themselves, see [Server::get_profile]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Profile {
+    /// the name shown next to the username, empty if none was given
+    pub display_name: String,
+    /// a few words on the user
+    pub bio: String,
+    /// the http or https URL of the picture of the user, empty if none
+    pub avatar_url: String,
+    /// the physical timestamp when the user signed up, 0 if unknown
+    pub joined: u64,
+}
+
+/// Everything kept on a user, as returned by [Server::export_user]. It
+/// serializes to the JSON archive handed out to the user.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UserArchive {
+    pub user: String,
+    pub profile: Profile,
+    /// every trib the user posted and has not deleted, oldest first, as
+    /// stored rather than as timelines show them
+    pub tribs: Vec<Arc<Trib>>,
+    /// the users this user follows, sorted
+    pub following: Vec<String>,
+    /// the users following this user, sorted
+    pub followers: Vec<String>,
+    /// the ids of the tribs this user likes, sorted
+    #[serde(default)]
+    pub liked: Vec<String>,
 }
 
 #[async_trait]
@@ -52,6 +126,29 @@ pub trait Server: Send + Sync {
     /// - Concurrent sign ups on the same user might both succeed with no error.
     async fn sign_up(&self, user: &str) -> TribResult<()>;
 
+    /// Creates a user who can [Server::login] with `password`. Only a
+    /// salted hash of the password is kept.
+    ///
+    /// - Returns error when the password is too short;
+    /// - Otherwise the same as [Server::sign_up].
+    async fn sign_up_with_password(&self, user: &str, password: &str) -> TribResult<()>;
+
+    /// Starts a session for a user and returns its token, which
+    /// [Server::session_user] accepts until it expires or is logged out.
+    ///
+    /// - Returns error when the user does not exist, signed up without a
+    /// password or has a different password, without telling which.
+    async fn login(&self, user: &str, password: &str) -> TribResult<String>;
+
+    /// Ends the session of a token. Ending a session that is already
+    /// invalid is not an error.
+    async fn logout(&self, token: &str) -> TribResult<()>;
+
+    /// Returns the user a session token was given to.
+    ///
+    /// - Returns error when the token is unknown, expired or logged out.
+    async fn session_user(&self, token: &str) -> TribResult<String>;
+
     /// List 20 registered users.
     ///
     /// - When there are less than 20 users that signed up the service, all of
@@ -68,10 +165,113 @@ pub trait Server: Send + Sync {
     /// - Returns error when post is too long.
     async fn post(&self, who: &str, post: &str, clock: u64) -> TribResult<()>;
 
+    /// Post a tribble in reply to the trib whose id is `parent`. Otherwise
+    /// the same as [Server::post].
+    ///
+    /// - Returns error when who does not exist;
+    /// - Returns error when the parent trib does not exist;
+    /// - Returns error when post is too long.
+    async fn reply(&self, who: &str, parent: &str, post: &str, clock: u64) -> TribResult<()>;
+
+    /// Repost the trib whose id is `trib_id` on behalf of `who`. The clock is
+    /// as in [Server::post]. Retribbing a retrib reposts the original.
+    ///
+    /// Timelines list a retrib where it was posted, but shown as the
+    /// original trib, see [as_retrib]. A retrib of a deleted trib is left
+    /// out of them.
+    ///
+    /// - Returns error when who does not exist;
+    /// - Returns error when the trib does not exist;
+    /// - Returns error when who already retribbed it.
+    async fn retrib(&self, who: &str, trib_id: &str, clock: u64) -> TribResult<()>;
+
+    /// Likes the trib whose id is `trib_id` on behalf of `who`. A user
+    /// likes a trib at most once, so liking it again does nothing. Liking a
+    /// retrib likes the original.
+    ///
+    /// - Returns error when who does not exist;
+    /// - Returns error when the trib does not exist.
+    async fn like(&self, who: &str, trib_id: &str) -> TribResult<()>;
+
+    /// Takes back a like by [Server::like]. Unliking a trib not liked does
+    /// nothing.
+    ///
+    /// - Returns error when who does not exist;
+    /// - Returns error when the trib does not exist.
+    async fn unlike(&self, who: &str, trib_id: &str) -> TribResult<()>;
+
+    /// Counts the users liking each of the tribs whose ids are `trib_ids`,
+    /// in the same order. Likes of a retrib are kept on the original, so a
+    /// retrib id, like an unknown one, counts none.
+    async fn like_counts(&self, trib_ids: &[String]) -> TribResult<Vec<u64>>;
+
+    /// Tells for each of the tribs whose ids are `trib_ids`, in the same
+    /// order, whether `who` likes it. Retrib ids are as in
+    /// [Server::like_counts].
+    ///
+    /// - Returns error when who does not exist.
+    async fn liked(&self, who: &str, trib_ids: &[String]) -> TribResult<Vec<bool>>;
+
+    /// List the tribs that @mention a particular user, see
+    /// [mentioned_users]. A trib edited to drop the mention is left out.
+    ///
+    /// - Returns error when user has not signed up.
+    async fn mentions(&self, user: &str) -> TribResult<Vec<Arc<Trib>>>;
+
+    /// List the whole conversation the trib whose id is `trib_id` is part of:
+    /// the trib it ultimately replies to, followed by every reply under it,
+    /// oldest first.
+    ///
+    /// - Returns error when the trib does not exist.
+    async fn thread(&self, trib_id: &str) -> TribResult<Vec<Arc<Trib>>>;
+
+    /// List the latest tribs tagged with `#tag`, see [hashtags]. The tag is
+    /// matched case-insensitively, with or without its leading `#`. Tribs
+    /// are ordered by clock, then time, user and message.
+    async fn tag_timeline(&self, tag: &str) -> TribResult<Vec<Arc<Trib>>>;
+
+    /// Searches the messages of all tribs for the words of `query`, see
+    /// [crate::search::tokenize]. Returns up to `limit` (at most
+    /// [MAX_TRIB_FETCH]) tribs, best match first, as ranked by
+    /// [crate::search::rank].
+    async fn search(&self, query: &str, limit: usize) -> TribResult<Vec<Arc<Trib>>>;
+
     /// List the tribs that a particular user posted.
     /// Returns error when user has not signed up.
     async fn tribs(&self, user: &str) -> TribResult<Vec<Arc<Trib>>>;
 
+    /// List up to `limit` (at most [MAX_TRIB_FETCH]) tribs that a particular
+    /// user posted before the one whose id is `cursor`, in the order of
+    /// [page_before]. An empty cursor starts from the latest trib; passing
+    /// the id of the first trib returned gives the page before it.
+    ///
+    /// Unlike [Server::tribs], this reaches back to the very first trib.
+    ///
+    /// - Returns error when user has not signed up;
+    /// - Returns error when the cursor is not a trib id.
+    async fn tribs_before(
+        &self,
+        user: &str,
+        cursor: &str,
+        limit: usize,
+    ) -> TribResult<Vec<Arc<Trib>>>;
+
+    /// Deletes the tribble `who` posted at logical timestamp `clock`, so that
+    /// it no longer shows up in [Server::tribs] or [Server::home].
+    ///
+    /// - Returns error when who does not exist;
+    /// - Returns error when who has no trib with that clock.
+    async fn delete_trib(&self, who: &str, clock: u64) -> TribResult<()>;
+
+    /// Replaces the message of the tribble `who` posted at logical timestamp
+    /// `clock`. The trib keeps its user, clock and time, and so its place in
+    /// every timeline.
+    ///
+    /// - Returns error when who does not exist;
+    /// - Returns error when who has no trib with that clock;
+    /// - Returns error when post is too long.
+    async fn edit_trib(&self, who: &str, clock: u64, post: &str) -> TribResult<()>;
+
     /// Follow someone's timeline.
     ///
     /// - Returns error when who == whom;
@@ -79,6 +279,7 @@ pub trait Server: Send + Sync {
     /// - Returns error when who is trying to following more than
     ///   [MAX_FOLLOWING] users.
     /// - Returns error when who or whom has not signed up.
+    /// - Returns error when either of them has blocked the other.
     ///
     /// Concurrent follows might both succeed without error. The count of
     /// following users might exceed [MAX_FOLLOWING]=2000, if and only if the
@@ -109,42 +310,217 @@ pub trait Server: Send + Sync {
     /// calls.
     async fn following(&self, who: &str) -> TribResult<Vec<String>>;
 
-    /// List the tribs of someone's following users (including himself).
+    /// Gets the list of users following `who`
+    ///
+    /// - Returns the list of followers, in no particular order.
+    /// - Returns error when who has not signed up.
+    async fn followers(&self, who: &str) -> TribResult<Vec<String>>;
+
+    /// Blocks `whom` on behalf of `who`. Neither can follow the other until
+    /// `who` unblocks them, so any follow between them is undone, and `who`
+    /// no longer sees them at home. Blocking someone already blocked does
+    /// nothing.
+    ///
+    /// - Returns error when who == whom.
+    /// - Returns error when who or whom has not signed up.
+    async fn block(&self, who: &str, whom: &str) -> TribResult<()>;
+
+    /// Lifts a block by [Server::block]. Follows it undid are not restored.
+    ///
+    /// - Returns error when who == whom.
+    /// - Returns error when who or whom has not signed up.
+    async fn unblock(&self, who: &str, whom: &str) -> TribResult<()>;
+
+    /// Gets the list of users `who` has blocked, sorted
+    ///
+    /// - Returns error when who has not signed up.
+    async fn blocked(&self, who: &str) -> TribResult<Vec<String>>;
+
+    /// Hides the tribs and retribs of `whom` from the home timeline of
+    /// `who`, without unfollowing them. Muting someone already muted does
+    /// nothing.
+    ///
+    /// - Returns error when who == whom.
+    /// - Returns error when who or whom has not signed up.
+    async fn mute(&self, who: &str, whom: &str) -> TribResult<()>;
+
+    /// Lifts a mute by [Server::mute]
+    ///
+    /// - Returns error when who == whom.
+    /// - Returns error when who or whom has not signed up.
+    async fn unmute(&self, who: &str, whom: &str) -> TribResult<()>;
+
+    /// Gets the list of users `who` has muted, sorted
+    ///
+    /// - Returns error when who has not signed up.
+    async fn muted(&self, who: &str) -> TribResult<Vec<String>>;
+
+    /// Counts the followers, the followed users and the tribs of `user`
+    ///
+    /// - Returns error when user has not signed up.
+    async fn user_stats(&self, user: &str) -> TribResult<UserStats>;
+
+    /// Gets the profile of `user`. A user who never updated it has an
+    /// empty one, apart from the time they joined.
+    ///
+    /// - Returns error when user has not signed up.
+    async fn get_profile(&self, user: &str) -> TribResult<Profile>;
+
+    /// Replaces the display name, bio and avatar URL of `user` with those
+    /// in `profile`. The time they joined is kept as it was.
+    ///
+    /// - Returns error when user has not signed up.
+    /// - Returns error when a field is too long or the avatar URL is not
+    /// an http or https one, see [crate::validate::check_profile].
+    async fn update_profile(&self, user: &str, profile: &Profile) -> TribResult<()>;
+
+    /// Deletes `user` along with all their tribs and likes. They are
+    /// unfollowed by everyone following them, their sessions end and the
+    /// name can be signed up for again.
+    ///
+    /// - Returns error when user has not signed up.
+    async fn delete_user(&self, user: &str) -> TribResult<()>;
+
+    /// Returns everything kept on `user`, see [UserArchive]
+    ///
+    /// - Returns error when user has not signed up.
+    async fn export_user(&self, user: &str) -> TribResult<UserArchive>;
+
+    /// List the tribs of someone's following users (including himself),
+    /// leaving out those posted or retribbed by users they muted or
+    /// blocked.
     ///
     /// - Returns error when user has not signed up.
     async fn home(&self, user: &str) -> TribResult<Vec<Arc<Trib>>>;
+
+    /// Same as [Server::tribs_before], for the home timeline of `user`.
+    async fn home_before(
+        &self,
+        user: &str,
+        cursor: &str,
+        limit: usize,
+    ) -> TribResult<Vec<Arc<Trib>>>;
+
+    /// Subscribes to the tribs joining the home timeline of `user` from now
+    /// on, shown as [Server::home] shows them, in the order they are seen.
+    /// A trib edited meanwhile is sent again with its new message.
+    ///
+    /// - Returns error when user does not exist.
+    /// - The channel may be closed, e.g. when the user starts or stops
+    /// following, muting or blocking someone or falls too far behind, after
+    /// which the caller should read the home timeline again and subscribe
+    /// anew.
+    async fn watch_home(&self, user: &str) -> TribResult<mpsc::Receiver<Arc<Trib>>>;
 }
 
-/// Checks if a username is a valid one. Returns true if it is.
-pub fn is_valid_username(s: &str) -> bool {
-    if s.is_empty() {
-        return false;
-    }
+/// Returns the id of the trib `user` posted at logical timestamp `clock`.
+/// Clients should treat it as opaque.
+pub fn trib_id(user: &str, clock: u64) -> String {
+    format!("{}-{}", user, clock)
+}
 
-    if s.len() > MAX_USERNAME_LEN {
-        return false;
+/// Returns how timelines show the retrib `record` of the trib `original`:
+/// with the user, message, clock, time and parent of the original, but the
+/// id of the retrib and [Trib::retrib_by] set to whoever reposted it.
+pub fn as_retrib(record: &Trib, original: &Trib) -> Trib {
+    Trib {
+        id: record.id.clone(),
+        retrib_of: original.id.clone(),
+        retrib_by: record.user.clone(),
+        ..original.clone()
     }
+}
+
+/// Splits an id built by [trib_id] back into the user and the clock
+pub fn parse_trib_id(id: &str) -> Option<(&str, u64)> {
+    let (user, clock) = id.rsplit_once('-')?;
+    Some((user, clock.parse().ok()?))
+}
+
+/// Orders tribs the way timelines list them: by clock, then by time, user
+/// and message
+fn timeline_order(a: &Trib, b: &Trib) -> Ordering {
+    (a.clock, a.time, &a.user, &a.message).cmp(&(b.clock, b.time, &b.user, &b.message))
+}
 
-    let mut chars = s.chars();
-    // check first character is [a..z]
-    if let Some(r) = chars.next() {
-        if !('a'..='z').contains(&r) {
-            return false;
+/// Returns the latest `limit` (at most [MAX_TRIB_FETCH]) of `tribs` that
+/// come before the trib whose id is `cursor`, or of all `tribs` when the
+/// cursor is empty.
+///
+/// Pages are ordered the same way as [Server::tribs] and [Server::home].
+/// No two tribs share a user and a clock, so walking back page by page
+/// never skips nor repeats a trib. Should the cursor trib have been deleted
+/// meanwhile, the page starts before its clock.
+pub fn page_before(
+    mut tribs: Vec<Arc<Trib>>,
+    cursor: &str,
+    limit: usize,
+) -> TribResult<Vec<Arc<Trib>>> {
+    if !cursor.is_empty() {
+        let (user, clock) = match parse_trib_id(cursor) {
+            Some(x) => x,
+            None => return Err(Box::new(TribblerError::InvalidTribId(cursor.to_string()))),
+        };
+        let at = tribs.iter().find(|t| t.user == user && t.clock == clock);
+        match at.cloned() {
+            Some(at) => tribs.retain(|t| timeline_order(t, &at) == Ordering::Less),
+            None => tribs.retain(|t| t.clock < clock),
         }
     }
+    tribs.sort_by(|a, b| timeline_order(a, b));
+    let start = tribs.len().saturating_sub(min(limit, MAX_TRIB_FETCH));
+    Ok(tribs.split_off(start))
+}
+
+/// Returns the users a trib message @mentions, each once, in the order they
+/// are first mentioned. A mention is an `@` that does not follow a letter
+/// or digit, followed by a valid username.
+pub fn mentioned_users(msg: &str) -> Vec<String> {
+    UsernamePolicy::default().mentioned_users(msg)
+}
 
-    // check the rest of the characters
-    for r in chars {
-        if !('a'..='z').contains(&r) && !('0'..='9').contains(&r) {
-            return false;
+/// Returns the hashtags of a trib message, lowercased and without the `#`,
+/// each once, in the order they first appear. A hashtag is a `#` that does
+/// not follow a letter or digit, followed by letters, digits and `_`, in
+/// any script, as in [crate::search::tokenize].
+pub fn hashtags(msg: &str) -> Vec<String> {
+    let mut tags: Vec<String> = vec![];
+    let mut prev = ' ';
+    for (i, c) in msg.char_indices() {
+        if c == '#' && !prev.is_alphanumeric() {
+            let rest = &msg[i + 1..];
+            let end = rest
+                .find(|r: char| !r.is_alphanumeric() && r != '_')
+                .unwrap_or(rest.len());
+            let tag = rest[..end].to_lowercase();
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
         }
+        prev = c;
     }
-    true
+    tags
+}
+
+/// Turns the tag given to [Server::tag_timeline] into the form [hashtags]
+/// returns
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim_start_matches('#').to_lowercase()
+}
+
+/// Checks if a username is a valid one. Returns true if it is.
+pub fn is_valid_username(s: &str) -> bool {
+    UsernamePolicy::default().is_valid(s)
 }
 
 #[cfg(test)]
 mod test {
-    use crate::trib::is_valid_username;
+    use std::sync::Arc;
+
+    use crate::trib::{
+        hashtags, is_valid_username, mentioned_users, normalize_tag, page_before, parse_trib_id,
+        trib_id, Trib,
+    };
 
     #[test]
     fn valid_usernames() {
@@ -152,4 +528,67 @@ mod test {
         assert_eq!(true, is_valid_username("rkapoor"));
         assert_eq!(true, is_valid_username("fenglu"));
     }
+
+    #[test]
+    fn mentions() {
+        assert_eq!(
+            vec!["h8liu", "fenglu"],
+            mentioned_users("@h8liu, meet @fenglu (cc @h8liu)")
+        );
+        assert!(mentioned_users("mail me at me@h8liu or @ or @1abc").is_empty());
+        assert_eq!(vec!["rkapoor"], mentioned_users("RT @rkapoor: hi"));
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(
+            vec!["rust", "async_io", "2023"],
+            hashtags("#Rust and #async_io, #rust again in #2023!")
+        );
+        assert!(hashtags("issue#12 or # alone").is_empty());
+        assert_eq!(vec!["café", "東京"], hashtags("#Café au #東京. Ça#va"));
+        assert_eq!("rust", normalize_tag("#Rust"));
+        assert_eq!("café", normalize_tag("#CAFÉ"));
+    }
+
+    #[test]
+    fn trib_ids() {
+        assert_eq!(Some(("h8liu", 42)), parse_trib_id(&trib_id("h8liu", 42)));
+        assert_eq!(None, parse_trib_id("h8liu"));
+        assert_eq!(None, parse_trib_id("h8liu-x"));
+    }
+
+    #[test]
+    fn pages_cover_history() {
+        let tribs = (0..250)
+            .map(|i| {
+                let user = ["bob", "alice"][i % 2];
+                let clock = i as u64 / 2;
+                Arc::new(Trib {
+                    user: user.to_string(),
+                    message: format!("{}", i),
+                    time: (i % 2) as u64,
+                    clock,
+                    id: trib_id(user, clock),
+                    parent: "".to_string(),
+                    retrib_of: "".to_string(),
+                    retrib_by: "".to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut seen = vec![];
+        let mut cursor = "".to_string();
+        loop {
+            let page = page_before(tribs.clone(), &cursor, 1000).unwrap();
+            if page.is_empty() {
+                break;
+            }
+            assert!(page.len() <= 100);
+            cursor = page[0].id.clone();
+            seen.splice(0..0, page.into_iter().map(|t| t.message.clone()));
+        }
+        let expected = (0..250).map(|i| format!("{}", i)).collect::<Vec<_>>();
+        assert_eq!(expected, seen);
+        assert!(page_before(tribs, "nope", 10).is_err());
+    }
 }
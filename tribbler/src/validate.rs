@@ -0,0 +1,231 @@
+//! Checks on trib messages and usernames, shared by the
+//! [crate::trib::Server] implementations so that both accept and refuse
+//! the same input.
+use serde::{Deserialize, Serialize};
+use unicode_normalization::is_nfkc;
+use unicode_security::{skeleton, MixedScript};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    err::{TribResult, TribblerError},
+    trib::{
+        Profile, MAX_AVATAR_URL_LEN, MAX_BIO_LEN, MAX_DISPLAY_NAME_LEN, MAX_TRIB_LEN,
+        MAX_USERNAME_LEN,
+    },
+};
+
+/// Returns the length of a trib message as its readers count it: in
+/// grapheme clusters, so that an emoji or a letter with accents counts as
+/// one however many bytes or code points it takes.
+///
+/// ```rust
+/// use tribbler::validate::trib_len;
+/// assert_eq!(5, trib_len("héllo"));
+/// assert_eq!(1, trib_len("👍🏽"));
+/// ```
+pub fn trib_len(msg: &str) -> usize {
+    msg.graphemes(true).count()
+}
+
+/// Returns an error when `msg` is longer than [MAX_TRIB_LEN], as counted
+/// by [trib_len]
+pub fn check_trib(msg: &str) -> TribResult<()> {
+    if trib_len(msg) > MAX_TRIB_LEN {
+        return Err(Box::new(TribblerError::TribTooLong));
+    }
+    Ok(())
+}
+
+/// Returns an error when a field of `profile` is longer than its limit,
+/// counted like trib messages, or when its avatar URL is neither empty nor
+/// an http or https URL
+pub fn check_profile(profile: &Profile) -> TribResult<()> {
+    if trib_len(&profile.display_name) > MAX_DISPLAY_NAME_LEN {
+        return Err(Box::new(TribblerError::ProfileFieldTooLong(
+            "display name".to_string(),
+        )));
+    }
+    if trib_len(&profile.bio) > MAX_BIO_LEN {
+        return Err(Box::new(TribblerError::ProfileFieldTooLong(
+            "bio".to_string(),
+        )));
+    }
+    if profile.avatar_url.len() > MAX_AVATAR_URL_LEN {
+        return Err(Box::new(TribblerError::ProfileFieldTooLong(
+            "avatar url".to_string(),
+        )));
+    }
+    let url = &profile.avatar_url;
+    // anything else, such as a javascript: URL, is unsafe to show as is
+    if !url.is_empty()
+        && (!(url.starts_with("https://") || url.starts_with("http://"))
+            || url.contains(|c: char| c.is_whitespace() || c.is_control()))
+    {
+        return Err(Box::new(TribblerError::InvalidAvatarUrl(url.to_string())));
+    }
+    Ok(())
+}
+
+/// Which usernames a server accepts. The default accepts lowercase ASCII
+/// letters and digits only, starting with a letter, see
+/// [crate::trib::is_valid_username].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsernamePolicy {
+    /// whether names may also use letters and digits outside ASCII. Such
+    /// names have to be lowercase and in NFKC form, so that one name can't
+    /// be spelled two ways.
+    pub unicode: bool,
+    /// whether to refuse names that mix scripts, and names that look like
+    /// one taken already, as told by [confusable_skeleton]
+    pub reject_confusables: bool,
+}
+
+impl UsernamePolicy {
+    /// Checks if a username is valid under this policy. Returns true if it
+    /// is.
+    pub fn is_valid(&self, s: &str) -> bool {
+        if s.is_empty() || s.graphemes(true).count() > MAX_USERNAME_LEN {
+            return false;
+        }
+        if !self.unicode {
+            // the byte length can't be over the limit either
+            return s.len() <= MAX_USERNAME_LEN
+                && s.starts_with(|c: char| c.is_ascii_lowercase())
+                && s.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+        }
+        s.starts_with(char::is_alphabetic)
+            && s.chars().all(|c| c.is_alphanumeric() && !c.is_uppercase())
+            && is_nfkc(s)
+            && (!self.reject_confusables || s.is_single_script())
+    }
+
+    /// Returns an error when a username is invalid under this policy
+    pub fn check(&self, s: &str) -> TribResult<()> {
+        if !self.is_valid(s) {
+            return Err(Box::new(TribblerError::InvalidUsername(s.to_string())));
+        }
+        Ok(())
+    }
+
+    /// Returns the users a trib message @mentions, each once, in the order
+    /// they are first mentioned. A mention is an `@` that does not follow a
+    /// letter or digit, followed by a name valid under this policy.
+    pub fn mentioned_users(&self, msg: &str) -> Vec<String> {
+        let in_word = |c: char| match self.unicode {
+            true => c.is_alphanumeric(),
+            false => c.is_ascii_alphanumeric(),
+        };
+        let in_name = |c: char| match self.unicode {
+            true => c.is_alphanumeric(),
+            false => c.is_ascii_lowercase() || c.is_ascii_digit(),
+        };
+        let mut users: Vec<String> = vec![];
+        let mut prev = ' ';
+        for (i, c) in msg.char_indices() {
+            if c == '@' && !in_word(prev) {
+                let rest = &msg[i + 1..];
+                let end = rest.find(|r: char| !in_name(r)).unwrap_or(rest.len());
+                let user = &rest[..end];
+                if self.is_valid(user) && !users.iter().any(|u| u == user) {
+                    users.push(user.to_string());
+                }
+            }
+            prev = c;
+        }
+        users
+    }
+}
+
+/// Returns the form two usernames share when they look alike, such as
+/// `paypal` spelled with a Cyrillic `а`, or `alicel` and `alice1`. See
+/// [UTS #39](https://www.unicode.org/reports/tr39/#Confusable_Detection).
+pub fn confusable_skeleton(name: &str) -> String {
+    skeleton(name).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_profile, check_trib, confusable_skeleton, trib_len, UsernamePolicy};
+    use crate::trib::Profile;
+
+    #[test]
+    fn lengths() {
+        assert_eq!(0, trib_len(""));
+        assert_eq!(3, trib_len("e\u{301}👨‍👩‍👧🇯🇵"));
+        assert!(check_trib(&"👍".repeat(140)).is_ok());
+        assert!(check_trib(&"👍".repeat(141)).is_err());
+    }
+
+    #[test]
+    fn profiles() {
+        let mut p = Profile {
+            display_name: "Élodie 🌻".to_string(),
+            bio: "👍".repeat(160),
+            avatar_url: "https://example.com/e.png".to_string(),
+            joined: 0,
+        };
+        assert!(check_profile(&p).is_ok());
+        assert!(check_profile(&Profile::default()).is_ok());
+        p.bio.push('!');
+        assert!(check_profile(&p).is_err());
+        p.bio.clear();
+        p.display_name = "x".repeat(51);
+        assert!(check_profile(&p).is_err());
+        p.display_name.clear();
+        for url in ["javascript:alert(1)", "example.com/e.png", "https://a b"] {
+            p.avatar_url = url.to_string();
+            assert!(check_profile(&p).is_err());
+        }
+    }
+
+    #[test]
+    fn policies() {
+        let ascii = UsernamePolicy::default();
+        assert!(ascii.is_valid("alice"));
+        assert!(!ascii.is_valid("élodie"));
+        assert!(!ascii.is_valid("中村"));
+
+        let unicode = UsernamePolicy {
+            unicode: true,
+            reject_confusables: false,
+        };
+        assert!(unicode.is_valid("alice"));
+        assert!(unicode.is_valid("élodie"));
+        assert!(unicode.is_valid("中村"));
+        assert!(unicode.is_valid("pаypal"));
+        // not lowercase, not NFKC, not alphanumeric, not starting with a letter
+        assert!(!unicode.is_valid("Élodie"));
+        assert!(!unicode.is_valid("e\u{301}lodie"));
+        assert!(!unicode.is_valid("ﬁona"));
+        assert!(!unicode.is_valid("a b"));
+        assert!(!unicode.is_valid("1up"));
+        assert!(!unicode.is_valid("ab".repeat(8).as_str()));
+
+        let strict = UsernamePolicy {
+            unicode: true,
+            reject_confusables: true,
+        };
+        assert!(strict.is_valid("élodie"));
+        assert!(!strict.is_valid("pаypal"));
+        assert_eq!(confusable_skeleton("paypal"), confusable_skeleton("pаypal"));
+        assert_eq!(confusable_skeleton("alicel"), confusable_skeleton("alice1"));
+        assert_ne!(confusable_skeleton("alice"), confusable_skeleton("bob"));
+    }
+
+    #[test]
+    fn unicode_mentions() {
+        let unicode = UsernamePolicy {
+            unicode: true,
+            reject_confusables: false,
+        };
+        assert_eq!(
+            vec!["élodie", "bob"],
+            unicode.mentioned_users("hi @élodie, @bob and @Élodie")
+        );
+        assert_eq!(
+            vec!["bob"],
+            UsernamePolicy::default().mentioned_users("hi @élodie, @bob")
+        );
+    }
+}
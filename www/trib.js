@@ -1,6 +1,6 @@
 // Generated by CoffeeScript 2.6.1
 (function() {
-  var _postRetrib, _postTrib, _showHome, _showUser, _updateFollow, _updateFollowing, addUser, appendError, countPostLength, follow, hoveringFollow, lclock, listTribs, listUsers, main, me, postDone, postTrib, seenClock, showHome, showUser, showing, signIn, signOut, unfollow, updateFollow, updateFollowing, updateUsers;
+  var _deleteUser, _postLike, _postRetrib, _postTrib, _showHome, _showUser, _signIn, _signOut, _updateFollow, _updateFollowing, addUser, appendError, countPostLength, deleteUser, exportUser, follow, homeStream, hoveringFollow, lclock, listTribs, listUsers, main, me, postDone, postTrib, requestFailed, seenClock, showHome, showProfile, showUser, showing, signIn, signOut, tribLength, unfollow, updateFollow, updateFollowing, updateUsers, watchHome;
 
   me = "";
 
@@ -8,6 +8,8 @@
 
   lclock = 0;
 
+  homeStream = null;
+
   seenClock = function(c) {
     if (c > lclock) {
       lclock = c;
@@ -15,13 +17,53 @@
     }
   };
 
+  // listens for tribs joining the home timeline, keeping lclock up to date
+  watchHome = function() {
+    if (homeStream !== null) {
+      homeStream.close();
+    }
+    homeStream = new EventSource("api/stream/home/" + me);
+    homeStream.addEventListener("trib", function(ev) {
+      var trib;
+      trib = JSON.parse(ev.data);
+      seenClock(trib.clock);
+      if (showing === "!home") {
+        _showHome();
+      }
+    });
+  };
+
+  // shows the profile of the user whose tribs are listed, if there is one
+  showProfile = function(profile) {
+    var div, joined;
+    div = $("div#profile");
+    div.empty();
+    if (profile == null) {
+      return;
+    }
+    if (profile.avatar_url !== "") {
+      div.append($('<img class="avatar" />').attr("src", profile.avatar_url));
+    }
+    if (profile.display_name !== "") {
+      div.append($('<h3 />').text(profile.display_name));
+    }
+    if (profile.bio !== "") {
+      div.append($('<p />').text(profile.bio));
+    }
+    if (profile.joined > 0) {
+      joined = new Date(profile.joined * 1000);
+      div.append('<span class="time">Joined ' + joined.toLocaleDateString() + '</span>');
+    }
+  };
+
   listTribs = function(data) {
-    var i, len1, li, ref, ret, retrib, trib, tribs, ul;
+    var i, len1, li, like, ref, ret, retrib, trib, tribs, ul;
     ret = JSON.parse(data);
     if (ret.err !== "") {
       appendError(ret.err);
       return;
     }
+    showProfile(ret.profile);
     tribs = $("div#tribs");
     tribs.empty();
     if (ret.tribs === null || ret.tribs.length === 0) {
@@ -38,6 +80,9 @@
       li.append('<span class="author"><a class="author" href="#">@' + trib.user + '</a></span> ');
       li.append('<span class="time">' + trib.time + '</span> ');
       li.append($('<span class="trib" />').text(trib.message));
+      if (trib.retrib_by) {
+        li.append(' <span class="time">retribbed by @' + trib.retrib_by + '</span>');
+      }
       li.find("a.author").click(function(ev) {
         var name;
         ev.preventDefault();
@@ -58,12 +103,28 @@
         $(this).find("a.retrib").hide();
       }));
       retrib.click((function() {
-        var msg, who;
-        msg = trib.message;
-        who = trib.user;
+        var id;
+        id = trib.id;
+        return function(ev) {
+          ev.preventDefault();
+          return _postRetrib(id);
+        };
+      })());
+      like = $('<a href="#" class="like button" />').text("♥ " + trib.likes);
+      if (trib.liked) {
+        like.addClass("liked");
+      }
+      li.append(' ').append(like);
+      // a retrib is liked through the original, which keeps the count
+      like.click((function() {
+        var id, liked;
+        id = trib.retrib_of ? trib.retrib_of : trib.id;
+        liked = trib.liked;
         return function(ev) {
           ev.preventDefault();
-          return _postRetrib('RT @' + who + ': ' + msg, who);
+          if (me !== "") {
+            return _postLike(id, !liked);
+          }
         };
       })());
       ul.append(li);
@@ -142,18 +203,24 @@
   };
 
   addUser = function() {
-    var name;
+    var name, password;
     name = $("form#adduser input#username").val();
+    password = $("form#adduser input#password").val();
     if (name === "") {
       return false;
     }
     $("form#adduser input#username").val("");
+    $("form#adduser input#password").val("");
     console.log("add user", name);
     $.ajax({
-      url: "api/add-user",
+      url: "api/sign-up",
       type: "POST",
-      data: name,
+      data: JSON.stringify({
+        user: name,
+        password: password
+      }),
       success: updateUsers,
+      error: requestFailed,
       cache: false
     });
     return false;
@@ -172,13 +239,39 @@
     return $("div#errors").append('<div class="error">Error: ' + e + '</div>');
   };
 
+  requestFailed = function(xhr) {
+    appendError(xhr.responseText);
+  };
+
   signIn = function(ev) {
+    var name, password;
     ev.preventDefault();
     if (showing === "" || showing === "!home") {
       return;
     }
-    console.log("sign in as: " + showing);
-    me = showing;
+    password = prompt("Password of " + showing);
+    if (password === null) {
+      return;
+    }
+    name = showing;
+    $.ajax({
+      url: "api/login",
+      type: "POST",
+      data: JSON.stringify({
+        user: name,
+        password: password
+      }),
+      success: (function() {
+        return _signIn(name);
+      }),
+      error: requestFailed,
+      cache: false
+    });
+  };
+
+  _signIn = function(name) {
+    console.log("sign in as: " + name);
+    me = name;
     $("div#who").show();
     $("div#who h3").html("Signed in as " + me);
     $("div#compose").show();
@@ -187,11 +280,26 @@
     updateFollow();
     $("div#followings").empty();
     updateFollowing();
+    watchHome();
   };
 
   signOut = function(ev) {
     console.log("sign out");
     ev.preventDefault();
+    $.ajax({
+      url: "api/logout",
+      type: "POST",
+      error: requestFailed,
+      cache: false
+    });
+    _signOut();
+  };
+
+  _signOut = function() {
+    if (homeStream !== null) {
+      homeStream.close();
+      homeStream = null;
+    }
     me = "";
     $("div#who").hide();
     $("div#compose").hide();
@@ -202,6 +310,39 @@
     }
   };
 
+  exportUser = function(ev) {
+    ev.preventDefault();
+    window.location = "api/v2/users/" + encodeURIComponent(me) + "/export";
+  };
+
+  deleteUser = function(ev) {
+    ev.preventDefault();
+    if (!confirm("Delete " + me + " and all their tribbles? This can't be undone.")) {
+      return;
+    }
+    $.ajax({
+      url: "api/delete-user",
+      type: "POST",
+      data: me,
+      success: _deleteUser,
+      error: requestFailed,
+      cache: false
+    });
+  };
+
+  _deleteUser = function(data) {
+    var ret;
+    ret = JSON.parse(data);
+    if (ret.err !== "") {
+      appendError(ret.err);
+      return;
+    }
+    _signOut();
+    showing = "";
+    $("div#timeline").hide();
+    listUsers();
+  };
+
   updateFollowing = function() {
     $.ajax({
       url: "api/following",
@@ -283,6 +424,7 @@
         whom: showing
       }),
       success: _updateFollow,
+      error: requestFailed,
       cache: false
     });
   };
@@ -297,6 +439,7 @@
         whom: showing
       }),
       success: _updateFollow,
+      error: requestFailed,
       cache: false
     });
   };
@@ -331,14 +474,24 @@
         whom: showing
       }),
       success: _updateFollow,
+      error: requestFailed,
       cache: false
     });
   };
 
+  // counts characters the way the server does, as graphemes where the
+  // browser can tell them apart, and as code points otherwise
+  tribLength = function(text) {
+    if (typeof Intl !== "undefined" && (Intl.Segmenter != null)) {
+      return Array.from(new Intl.Segmenter().segment(text)).length;
+    }
+    return Array.from(text).length;
+  };
+
   countPostLength = function() {
     var left, len, text;
     text = $("form#post textarea").val();
-    len = text.length;
+    len = tribLength(text);
     left = 140 - len;
     $("span#nchar").text("" + left);
     if (left < 0) {
@@ -348,10 +501,34 @@
     }
   };
 
-  _postRetrib = function(msg, at) {
-    $("form#post textarea").val(msg);
-    countPostLength();
-    _postTrib();
+  _postRetrib = function(id) {
+    $.ajax({
+      url: "api/retrib",
+      type: "POST",
+      data: JSON.stringify({
+        who: me,
+        id: id,
+        clock: lclock
+      }),
+      success: postDone,
+      error: requestFailed,
+      cache: false
+    });
+  };
+
+  // likes the trib whose id is given, or takes the like back unless like
+  _postLike = function(id, like) {
+    $.ajax({
+      url: like ? "api/like" : "api/unlike",
+      type: "POST",
+      data: JSON.stringify({
+        who: me,
+        id: id
+      }),
+      success: postDone,
+      error: requestFailed,
+      cache: false
+    });
   };
 
   postTrib = function(ev) {
@@ -362,7 +539,7 @@
   _postTrib = function() {
     var len, text;
     text = $("form#post textarea").val();
-    len = text.length;
+    len = tribLength(text);
     if (len === 0) {
       appendError("empty tweet");
       return;
@@ -382,6 +559,7 @@
         clock: lclock
       }),
       success: postDone,
+      error: requestFailed,
       cache: false
     });
   };
@@ -410,6 +588,8 @@
     $("a#signin").click(signIn);
     $("a#home").click(showHome);
     $("a#signout").click(signOut);
+    $("a#export").click(exportUser);
+    $("a#delete").click(deleteUser);
     $("form#post textarea").keydown(function() {
       return setTimeout((function() {
         return countPostLength();